@@ -0,0 +1,68 @@
+use crate::Error;
+use std::thread::JoinHandle;
+
+/// The engine's background threads: MemTable flushes, and the compactions each flush runs
+/// once its table is written.
+///
+/// Handles are kept so [`StorageEngine::close`](crate::StorageEngine::close) can wait for the
+/// work still running and report what failed. Threads that have already finished are joined
+/// whenever a new one is spawned, so a long-lived engine does not pile up handles; the first
+/// error among them is kept until it is reported.
+#[derive(Default)]
+pub(crate) struct BackgroundJobs {
+    running: Vec<JoinHandle<Result<(), Error>>>,
+    first_error: Option<Error>,
+}
+
+impl BackgroundJobs {
+    pub(crate) fn spawn<F>(&mut self, job: F)
+    where
+        F: FnOnce() -> Result<(), Error> + Send + 'static,
+    {
+        self.reap(false);
+        self.running.push(std::thread::spawn(job));
+    }
+
+    /// Waits for every thread to finish, and returns the first error any of them hit since the
+    /// last call.
+    pub(crate) fn join_all(&mut self) -> Result<(), Error> {
+        self.reap(true);
+        self.first_error.take().map_or(Ok(()), Err)
+    }
+
+    // Joins the finished threads, or all of them if `wait` is set.
+    fn reap(&mut self, wait: bool) {
+        let (done, running): (Vec<_>, Vec<_>) = std::mem::take(&mut self.running)
+            .into_iter()
+            .partition(|handle| wait || handle.is_finished());
+        self.running = running;
+        for handle in done {
+            let result = handle.join().unwrap_or(Err(Error::BackgroundPanic));
+            if let Err(e) = result {
+                self.first_error.get_or_insert(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_all_reports_the_first_error_once() {
+        let mut jobs = BackgroundJobs::default();
+        jobs.spawn(|| Ok(()));
+        jobs.spawn(|| Err(std::io::Error::other("disk full").into()));
+        jobs.spawn(|| panic!("boom"));
+
+        // Threads are joined in the order they were spawned.
+        assert_eq!(
+            jobs.join_all().unwrap_err().to_string(),
+            "I/O error: disk full"
+        );
+        assert!(jobs.running.is_empty());
+        // Reported once, then forgotten.
+        assert!(jobs.join_all().is_ok());
+    }
+}
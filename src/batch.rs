@@ -0,0 +1,222 @@
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
+use crate::ttl;
+use std::time::Duration;
+
+/// A group of writes applied atomically by [`StorageEngine::write`](crate::StorageEngine::write).
+///
+/// Operations are recorded in order and applied in that order, so a later operation on the
+/// same key wins. Either every operation in the batch becomes visible and durable, or — if the
+/// process crashes before the batch is fully logged — none of them does. The `_cf` methods
+/// write to another [`ColumnFamily`]; a batch may span any number of families.
+///
+/// ```no_run
+/// use lsmdb::{StorageEngine, WriteBatch};
+///
+/// # let engine = StorageEngine::open("/tmp/db").unwrap();
+/// let mut batch = WriteBatch::new();
+/// batch.put(b"account:alice", b"90");
+/// batch.put(b"account:bob", b"110");
+/// batch.delete(b"pending:tx42");
+/// engine.write(batch)?;
+/// # Ok::<(), lsmdb::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    /// Each operation with the id of the column family it writes to.
+    pub(crate) ops: Vec<(u32, BatchOp)>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum BatchOp {
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    PutWithTtl {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: u64,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+    Merge {
+        key: Vec<u8>,
+        operand: Vec<u8>,
+    },
+    DeleteRange {
+        start: Vec<u8>,
+        end: Vec<u8>,
+    },
+}
+
+impl BatchOp {
+    /// The key written, or the start of the range deleted.
+    pub(crate) fn key(&self) -> &[u8] {
+        match self {
+            BatchOp::Put { key, .. }
+            | BatchOp::PutWithTtl { key, .. }
+            | BatchOp::Delete { key }
+            | BatchOp::Merge { key, .. } => key,
+            BatchOp::DeleteRange { start, .. } => start,
+        }
+    }
+
+    fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(key: K, value: V) -> Self {
+        BatchOp::Put {
+            key: key.as_ref().to_vec(),
+            value: value.as_ref().to_vec(),
+        }
+    }
+
+    fn put_with_ttl<K: AsRef<[u8]>, V: AsRef<[u8]>>(key: K, value: V, ttl: Duration) -> Self {
+        BatchOp::PutWithTtl {
+            key: key.as_ref().to_vec(),
+            value: value.as_ref().to_vec(),
+            expires_at: ttl::expires_at(ttl),
+        }
+    }
+
+    fn delete<K: AsRef<[u8]>>(key: K) -> Self {
+        BatchOp::Delete {
+            key: key.as_ref().to_vec(),
+        }
+    }
+
+    fn delete_range<K: AsRef<[u8]>>(start: K, end: K) -> Self {
+        BatchOp::DeleteRange {
+            start: start.as_ref().to_vec(),
+            end: end.as_ref().to_vec(),
+        }
+    }
+
+    fn merge<K: AsRef<[u8]>, V: AsRef<[u8]>>(key: K, operand: V) -> Self {
+        BatchOp::Merge {
+            key: key.as_ref().to_vec(),
+            operand: operand.as_ref().to_vec(),
+        }
+    }
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a write of `value` to `key`.
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) -> &mut Self {
+        self.push(DEFAULT_COLUMN_FAMILY_ID, BatchOp::put(key, value))
+    }
+
+    /// Queues a write of `value` to `key` in column family `cf`.
+    pub fn put_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        cf: &ColumnFamily,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.push(cf.id, BatchOp::put(key, value))
+    }
+
+    /// Queues a write of `value` to `key` that expires `ttl` from now; see
+    /// [`StorageEngine::put_with_ttl`](crate::StorageEngine::put_with_ttl). The clock starts
+    /// when the write is queued, not when the batch is written.
+    pub fn put_with_ttl<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> &mut Self {
+        self.push(
+            DEFAULT_COLUMN_FAMILY_ID,
+            BatchOp::put_with_ttl(key, value, ttl),
+        )
+    }
+
+    /// Queues a write of `value` to `key` in column family `cf` that expires `ttl` from now.
+    pub fn put_with_ttl_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        cf: &ColumnFamily,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> &mut Self {
+        self.push(cf.id, BatchOp::put_with_ttl(key, value, ttl))
+    }
+
+    /// Queues a deletion of `key`.
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> &mut Self {
+        self.push(DEFAULT_COLUMN_FAMILY_ID, BatchOp::delete(key))
+    }
+
+    /// Queues a deletion of `key` in column family `cf`.
+    pub fn delete_cf<K: AsRef<[u8]>>(&mut self, cf: &ColumnFamily, key: K) -> &mut Self {
+        self.push(cf.id, BatchOp::delete(key))
+    }
+
+    /// Queues a deletion of every key in `start..end`; see
+    /// [`StorageEngine::delete_range`](crate::StorageEngine::delete_range).
+    pub fn delete_range<K: AsRef<[u8]>>(&mut self, start: K, end: K) -> &mut Self {
+        self.push(DEFAULT_COLUMN_FAMILY_ID, BatchOp::delete_range(start, end))
+    }
+
+    /// Queues a deletion of every key in `start..end` in column family `cf`.
+    pub fn delete_range_cf<K: AsRef<[u8]>>(
+        &mut self,
+        cf: &ColumnFamily,
+        start: K,
+        end: K,
+    ) -> &mut Self {
+        self.push(cf.id, BatchOp::delete_range(start, end))
+    }
+
+    /// Queues a merge of `operand` into `key`; see
+    /// [`StorageEngine::merge`](crate::StorageEngine::merge).
+    pub fn merge<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, operand: V) -> &mut Self {
+        self.push(DEFAULT_COLUMN_FAMILY_ID, BatchOp::merge(key, operand))
+    }
+
+    /// Queues a merge of `operand` into `key` in column family `cf`, using that family's
+    /// merge operator.
+    pub fn merge_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        cf: &ColumnFamily,
+        key: K,
+        operand: V,
+    ) -> &mut Self {
+        self.push(cf.id, BatchOp::merge(key, operand))
+    }
+
+    /// A batch of puts (`Some`) and deletions (`None`), keyed by column family id and key: the
+    /// buffered writes of a transaction.
+    pub(crate) fn from_writes(
+        writes: impl IntoIterator<Item = ((u32, Vec<u8>), Option<Vec<u8>>)>,
+    ) -> Self {
+        let mut batch = Self::new();
+        for ((cf, key), value) in writes {
+            match value {
+                Some(value) => batch.push(cf, BatchOp::Put { key, value }),
+                None => batch.push(cf, BatchOp::Delete { key }),
+            };
+        }
+        batch
+    }
+
+    fn push(&mut self, cf: u32, op: BatchOp) -> &mut Self {
+        self.ops.push((cf, op));
+        self
+    }
+
+    /// Number of queued operations. Each one consumes one sequence number when written.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
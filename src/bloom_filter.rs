@@ -119,6 +119,11 @@ impl BloomFilter {
         }
         let k_num_hashes = u32::from_le_bytes(bytes[0..4].try_into().ok()?);
         let num_bits = u64::from_le_bytes(bytes[4..12].try_into().ok()?) as usize;
+        // A payload shorter than the bit count is cut off, and probing the missing bits would
+        // give false negatives.
+        if num_bits > (bytes.len() - 12) * 8 {
+            return None;
+        }
 
         let mut bits = BitVec::from_bytes(&bytes[12..]);
         // `BitVec::from_bytes` rounds up to the nearest byte boundary. We truncate back to the
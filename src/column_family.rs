@@ -0,0 +1,172 @@
+use crate::memtable::MemTable;
+use crate::sstable::SSTableReader;
+use crate::{Error, Options};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+
+/// The id of the family every database starts with, which the methods without a `_cf` suffix
+/// read and write.
+pub(crate) const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+/// The name of the default column family.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// A handle to a column family, returned by
+/// [`StorageEngine::create_column_family`](crate::StorageEngine::create_column_family) and
+/// [`StorageEngine::column_family`](crate::StorageEngine::column_family).
+///
+/// A column family is a keyspace of its own inside one database: it has its own MemTable,
+/// SSTable levels and [`Options`], so a family of large blobs and a family of small index
+/// entries can be flushed and compacted differently. All families share the database's WAL
+/// and sequence numbers, so a [`WriteBatch`](crate::WriteBatch) spanning several of them
+/// commits atomically, and a [`Snapshot`](crate::Snapshot) reads all of them at one point in
+/// time.
+///
+/// ```no_run
+/// use lsmdb::{Options, StorageEngine, WriteBatch};
+///
+/// let engine = StorageEngine::open("/tmp/db")?;
+/// let blobs = engine.create_column_family("blobs", Options {
+///     memtable_capacity_bytes: 64 * 1024 * 1024,
+///     ..Options::default()
+/// })?;
+///
+/// // The blob and the metadata pointing at it become visible together, or not at all.
+/// let mut batch = WriteBatch::new();
+/// batch.put_cf(&blobs, b"sha256:9f86d0", b"<bytes>");
+/// batch.put(b"file:report.pdf", b"sha256:9f86d0");
+/// engine.write(batch)?;
+///
+/// assert_eq!(engine.get_cf(&blobs, b"sha256:9f86d0")?, Some(b"<bytes>".to_vec()));
+/// # Ok::<(), lsmdb::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ColumnFamily {
+    pub(crate) id: u32,
+    name: Arc<str>,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: &str) -> Self {
+        Self {
+            id,
+            name: Arc::from(name),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// One column family's share of the engine: everything but the WAL, the MANIFEST and the
+/// sequence numbers, which all families share.
+///
+/// A family is flushed on its own, when its own MemTable fills up. The WAL is rotated on every
+/// flush, so the older WAL files end up holding writes of families that have not flushed yet;
+/// `active_log` and `immutable_log` record which files each family still needs.
+pub(crate) struct ColumnFamilyData {
+    pub(crate) handle: ColumnFamily,
+    pub(crate) options: Arc<Options>,
+    // Mutex over RwLock: see the concurrency model on `StorageEngine`.
+    pub(crate) active_memtable: Mutex<MemTable>,
+    // Arc<MemTable> inside the Option so the flush thread doesn't hold the lock during I/O.
+    pub(crate) immutable_memtable: Mutex<Option<Arc<MemTable>>>,
+    // Readers are reference-counted so a range scan can keep iterating a table after releasing
+    // the lock, even if a compaction drops that table from the list in the meantime.
+    pub(crate) sstables: RwLock<Vec<Vec<Arc<SSTableReader>>>>,
+    // A Condvar stalls writers when the immutable slot is occupied (flush in progress).
+    // Without this, a second flush trigger while one is running would silently drop data.
+    // Writers block here instead of racing or returning an error.
+    pub(crate) flush_condvar: (Mutex<bool>, Condvar),
+    // Held for the whole of a compaction, so a manual one and the one after a flush never
+    // pick the same input tables.
+    pub(crate) compaction_lock: Mutex<()>,
+    // The first WAL file that may hold writes in the active and the immutable MemTable. Only
+    // changed while the WAL lock is held.
+    pub(crate) active_log: AtomicU64,
+    pub(crate) immutable_log: AtomicU64,
+}
+
+impl ColumnFamilyData {
+    /// A family with the given tables and an empty MemTable. `sstables` holds at least L0.
+    pub(crate) fn new(
+        handle: ColumnFamily,
+        options: Arc<Options>,
+        mut sstables: Vec<Vec<Arc<SSTableReader>>>,
+        log: u64,
+    ) -> Self {
+        // Always guarantee at least one slot for L0 so flush logic never has to bounds-check.
+        if sstables.is_empty() {
+            sstables.push(Vec::new());
+        }
+        Self {
+            active_memtable: Mutex::new(MemTable::new(
+                options.memtable_capacity_bytes,
+                options.bloom_filter_fpr,
+            )),
+            immutable_memtable: Mutex::new(None),
+            sstables: RwLock::new(sstables),
+            flush_condvar: (Mutex::new(false), Condvar::new()),
+            compaction_lock: Mutex::new(()),
+            active_log: AtomicU64::new(log),
+            immutable_log: AtomicU64::new(log),
+            handle,
+            options,
+        }
+    }
+
+    pub(crate) fn id(&self) -> u32 {
+        self.handle.id
+    }
+
+    /// The oldest WAL file holding writes of this family that are not in an SSTable yet, or
+    /// `None` if every write to it has been flushed.
+    ///
+    /// Must be called with the WAL lock held, so the MemTables cannot be rotated under it.
+    pub(crate) fn oldest_unflushed_log(&self) -> Result<Option<u64>, Error> {
+        let imm = self
+            .immutable_memtable
+            .lock()
+            .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
+        if imm.is_some() {
+            return Ok(Some(self.immutable_log.load(Ordering::SeqCst)));
+        }
+        drop(imm);
+
+        let active = self
+            .active_memtable
+            .lock()
+            .map_err(|_| Error::Poisoned("MemTable"))?;
+        Ok((!active.is_empty()).then(|| self.active_log.load(Ordering::SeqCst)))
+    }
+}
+
+/// Hands out SSTable ids. Ids are Unix millisecond timestamps, which keeps them ordered by
+/// age, bumped past the last id handed out so two tables created in the same millisecond —
+/// by flushes of different families, or a flush and a compaction — never share a file name.
+pub(crate) struct FileIds {
+    last: AtomicU64,
+}
+
+impl FileIds {
+    /// Starts above every id in `existing`.
+    pub(crate) fn new(existing: impl IntoIterator<Item = u64>) -> Self {
+        Self {
+            last: AtomicU64::new(existing.into_iter().max().unwrap_or(0)),
+        }
+    }
+
+    pub(crate) fn next(&self) -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let next = |last: u64| (last + 1).max(now);
+        // The closure never returns `None`, so the update always succeeds.
+        let last = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next(last)))
+            .unwrap();
+        next(last)
+    }
+}
@@ -3,6 +3,10 @@
 //! All constants are gathered here so that tuning the engine means changing one file, not
 //! hunting for magic numbers scattered across the codebase. Each constant documents the
 //! tradeoff it controls so you can make an informed decision when adjusting it for your workload.
+//!
+//! The engine-level knobs are only *defaults*: [`Options`](crate::Options) starts from these
+//! values and can override them per database via
+//! [`StorageEngine::open_with_options`](crate::StorageEngine::open_with_options).
 
 /// Maximum size of a MemTable before it is promoted to immutable and scheduled for flushing.
 ///
@@ -39,6 +43,14 @@ pub const WAL_HEADER_SIZE: usize = 7;
 /// constant — it exists to make the splitting arithmetic in `WalWriter` readable.
 pub const WAL_MAX_PAYLOAD_SIZE: usize = WAL_BLOCK_SIZE - WAL_HEADER_SIZE;
 
+/// Longest key a write accepts, in bytes.
+///
+/// Keys carry a 16-bit length in the WAL record format, and short keys are what an LSM-Tree
+/// wants anyway: every key is copied into SkipList nodes, index entries and filters. Longer
+/// keys are rejected with [`Error::InvalidArgument`](crate::Error::InvalidArgument) before
+/// anything is logged.
+pub const MAX_KEY_SIZE: usize = u16::MAX as usize;
+
 /// Maximum size of a Data Block inside an SSTable.
 ///
 /// Smaller blocks improve point-query performance (less data to decompress and scan per lookup)
@@ -46,6 +58,15 @@ pub const WAL_MAX_PAYLOAD_SIZE: usize = WAL_BLOCK_SIZE - WAL_HEADER_SIZE;
 /// inputs). 4 KB matches a typical OS page and is the LevelDB default.
 pub const SSTABLE_BLOCK_SIZE: usize = 4096;
 
+/// Largest Data Block that is stored compressed, and so the most a reader decompresses one
+/// into.
+///
+/// A block only outgrows [`SSTABLE_BLOCK_SIZE`] by its last entry, so only huge values reach
+/// this; such blocks are stored as they are. Readers check the size a compressed block
+/// declares against it before allocating, so a damaged or hostile block cannot make them
+/// allocate gigabytes.
+pub const MAX_COMPRESSED_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
 /// How often a full, uncompressed key is written to a Data Block (the "restart interval").
 ///
 /// Data Blocks use prefix compression: each key stores only the bytes that differ from the
@@ -108,10 +129,56 @@ pub const L1_MAX_BYTES: u64 = 10 * 1024 * 1024;
 /// be lost if the machine loses power.
 pub const WAL_SYNC_ON_WRITE: bool = true;
 
+/// Whether closing the database flushes every column family's MemTable to an SSTable.
+///
+/// Writes still in a MemTable at close are safe either way: the WAL holds them, and the next
+/// open replays it. Flushing trades a slower close (and one more small L0 file per family) for
+/// a faster open with nothing to replay. Off by default, since most databases are closed far
+/// more often than they are waiting to be opened.
+pub const FLUSH_ON_CLOSE: bool = false;
+
 /// Compression type identifier byte prefixed to every Data Block on disk.
 ///
-/// These sentinel values are embedded in the SSTable file so a future reader can decompress
-/// without knowing out-of-band what compression was used. Adding a new algorithm means adding a
-/// new constant and handling it in the read path — the format is forward-compatible.
+/// These sentinel values are embedded in the SSTable file so a reader can decompress without
+/// knowing out-of-band what compression was used. Adding a new algorithm means adding a new
+/// constant, handling it in the read path and bumping [`SSTABLE_FORMAT_VERSION`]: a reader
+/// that predates the algorithm then refuses the whole table when it opens, rather than failing
+/// on the first block it cannot decode.
 pub const COMPRESSION_NONE: u8 = 0x00;
 pub const COMPRESSION_SNAPPY: u8 = 0x01;
+pub const COMPRESSION_LZ4: u8 = 0x02;
+pub const COMPRESSION_ZSTD: u8 = 0x03;
+
+/// A Data Block is stored raw unless compression saves at least 1/8 (12.5%) of its size.
+pub const MIN_COMPRESSION_SAVINGS_DIVISOR: usize = 8;
+
+/// The last 8 bytes of every SSTable, little-endian: they tell an lsmdb table from any other
+/// file.
+pub const SSTABLE_MAGIC: u64 = 0x7473_7362_646d_736c;
+
+/// The SSTable format version this build writes, stored in the footer next to the magic number.
+///
+/// A reader rejects a table with a version it does not know with
+/// [`Error::NotSupported`](crate::Error::NotSupported) rather than misreading it, so the format
+/// can change in a later release. A file without the magic number is rejected the same way;
+/// that includes every table written before the footer held a version.
+///
+/// Version 1 tables compress blocks with Snappy or not at all; version 2 adds LZ4 and Zstd.
+/// This build reads both.
+pub const SSTABLE_FORMAT_VERSION: u32 = 2;
+
+/// How long a pessimistic transaction waits for a key locked by another transaction before
+/// giving up.
+///
+/// Deadlocks are detected as soon as they form, so the timeout only bounds waits on a lock
+/// holder that is slow rather than stuck behind the waiter. Too short and busy keys fail
+/// transactions that would have succeeded a moment later; too long and a stalled holder
+/// stalls every waiter with it.
+pub const LOCK_TIMEOUT_MS: u64 = 1000;
+
+/// Number of independently locked shards in the per-key lock table of a
+/// [`TransactionDB`](crate::TransactionDB).
+///
+/// Each stripe has its own mutex, so transactions locking keys in different stripes do not
+/// contend. More stripes cost a little memory and nothing else.
+pub const LOCK_STRIPES: usize = 16;
@@ -0,0 +1,140 @@
+use crate::transaction::TransactionConflict;
+use crate::transaction_db::LockError;
+use std::path::PathBuf;
+
+/// The error every fallible operation of the crate returns.
+///
+/// Match on the variant to tell failures apart: a
+/// [`TransactionConflict`](Error::TransactionConflict) or a [`Busy`](Error::Busy) is worth
+/// retrying, a [`Corruption`](Error::Corruption) is not.
+///
+/// ```no_run
+/// use lsmdb::{Error, StorageEngine};
+///
+/// # let engine = StorageEngine::open("/tmp/db").unwrap();
+/// match engine.put(b"visits", b"1") {
+///     Ok(()) => {}
+///     Err(Error::ReadOnly) => eprintln!("this instance only serves reads"),
+///     Err(Error::Io(e)) => eprintln!("disk trouble: {}", e),
+///     Err(e) => return Err(e),
+/// }
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// `file` holds bytes at `offset` that this crate did not write: a checksum mismatch, or a
+    /// record that does not decode. `offset` is 0 when the damage is not at one place in the
+    /// file.
+    Corruption {
+        file: PathBuf,
+        offset: u64,
+        message: String,
+    },
+    /// The call cannot be carried out as asked: invalid options, an unknown column family, a
+    /// key too large to store, a merge without a merge operator, and the like. Retrying the
+    /// same call fails the same way.
+    InvalidArgument(String),
+    /// A file is in a format this version of the crate does not read, such as an SSTable
+    /// written by a newer release. Nothing is wrong with the file; a release that knows the
+    /// format reads it.
+    NotSupported(String),
+    /// An optimistic transaction's key was modified by another writer after it started; see
+    /// [`Transaction::commit`](crate::Transaction::commit).
+    TransactionConflict(TransactionConflict),
+    /// A [`PessimisticTransaction`](crate::PessimisticTransaction) could not lock a key.
+    Lock(LockError),
+    /// Other work kept getting in the way — say, a primary changing its MANIFEST during every
+    /// attempt of a secondary to catch up with it. Retrying later may succeed.
+    Busy(String),
+    /// A write to a database opened with
+    /// [`open_read_only`](crate::StorageEngine::open_read_only) or
+    /// [`open_as_secondary`](crate::StorageEngine::open_as_secondary). Nothing was written.
+    ReadOnly,
+    /// Work stopped early because the database is closing. A background compaction stops
+    /// this way when [`close`](crate::StorageEngine::close) begins; `close` does not report it,
+    /// since the compaction is simply resumed after the next flush.
+    ShutdownInProgress,
+    /// A thread panicked while holding the named lock, so the state it guards may be half
+    /// updated.
+    Poisoned(&'static str),
+    /// A background flush or compaction thread panicked.
+    BackgroundPanic,
+}
+
+impl Error {
+    /// A [`Corruption`](Error::Corruption) of `file` at `offset`.
+    pub(crate) fn corruption(
+        file: impl Into<PathBuf>,
+        offset: u64,
+        message: impl Into<String>,
+    ) -> Self {
+        Error::Corruption {
+            file: file.into(),
+            offset,
+            message: message.into(),
+        }
+    }
+
+    /// An [`InvalidArgument`](Error::InvalidArgument) with `message`.
+    pub(crate) fn invalid(message: impl Into<String>) -> Self {
+        Error::InvalidArgument(message.into())
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Corruption {
+                file,
+                offset,
+                message,
+            } => write!(
+                f,
+                "Corruption in {:?} at offset {}: {}",
+                file, offset, message
+            ),
+            Error::InvalidArgument(message)
+            | Error::NotSupported(message)
+            | Error::Busy(message) => write!(f, "{}", message),
+            Error::TransactionConflict(conflict) => write!(f, "{}", conflict),
+            Error::Lock(e) => write!(f, "{}", e),
+            Error::ReadOnly => write!(f, "The database is open read-only"),
+            Error::ShutdownInProgress => write!(f, "The database is shutting down"),
+            Error::Poisoned(lock) => write!(f, "{} lock poisoned", lock),
+            Error::BackgroundPanic => write!(f, "Background thread panicked"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::TransactionConflict(conflict) => Some(conflict),
+            Error::Lock(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<TransactionConflict> for Error {
+    fn from(conflict: TransactionConflict) -> Self {
+        Error::TransactionConflict(conflict)
+    }
+}
+
+impl From<LockError> for Error {
+    fn from(e: LockError) -> Self {
+        Error::Lock(e)
+    }
+}
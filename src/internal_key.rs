@@ -0,0 +1,187 @@
+//! The key format used inside the engine: a user key tagged with the sequence number of the
+//! write that produced it and the kind of write it was.
+//!
+//! Every layer — MemTable, SSTable data and index blocks, iterators — stores and compares
+//! internal keys, so several versions of one user key can coexist and a reader pinned to a
+//! sequence number can pick the newest version it is allowed to see.
+//!
+//! Layout: `[user key] [trailer (8 LE)]`, where the trailer packs `sequence << 8 | value type`.
+//! Internal keys sort by user key ascending, then by trailer *descending*, so the newest
+//! version of a key comes first and seeking to `(key, snapshot_seq)` lands directly on the
+//! newest version visible to that snapshot.
+//!
+//! The value type is what marks a deletion, so a tombstone no longer has to be encoded as an
+//! empty value.
+
+use std::cmp::Ordering;
+
+/// Bytes appended to every user key.
+pub(crate) const TRAILER_SIZE: usize = 8;
+
+/// The largest sequence number the format can carry: the low byte of the trailer holds the
+/// value type.
+pub(crate) const MAX_SEQUENCE: u64 = (1 << 56) - 1;
+
+/// What kind of write an entry records. The numeric values are part of the on-disk format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum ValueType {
+    /// A tombstone: the key was deleted at this sequence number. Its value is empty.
+    Deletion = 0,
+    Value = 1,
+    /// A merge operand, folded into the versions beneath it by the configured
+    /// [`MergeOperator`](crate::MergeOperator).
+    Merge = 2,
+    /// A range tombstone, keyed by the start of its range with the end as its value. Only
+    /// ever stored in an SSTable's range tombstone block, never among the point entries.
+    RangeDeletion = 3,
+    /// A value with a deadline, stored as `[expires_at (8 LE)] [value]` (see [`crate::ttl`]).
+    /// Once the deadline passes it reads as a tombstone.
+    ExpiringValue = 4,
+}
+
+impl ValueType {
+    fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ValueType::Deletion),
+            1 => Some(ValueType::Value),
+            2 => Some(ValueType::Merge),
+            3 => Some(ValueType::RangeDeletion),
+            4 => Some(ValueType::ExpiringValue),
+            _ => None,
+        }
+    }
+}
+
+/// The type to seek with. Types order descending within one sequence number, so the highest
+/// type makes a seek target sort before every entry written at that sequence.
+const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::ExpiringValue;
+
+pub(crate) fn encode(user_key: &[u8], seq: u64, value_type: ValueType) -> Vec<u8> {
+    debug_assert!(seq <= MAX_SEQUENCE);
+    let mut key = Vec::with_capacity(user_key.len() + TRAILER_SIZE);
+    key.extend_from_slice(user_key);
+    key.extend_from_slice(&(seq << 8 | value_type as u64).to_le_bytes());
+    key
+}
+
+/// The smallest internal key for `user_key` at sequence `seq`: the position to seek to in
+/// order to find the newest version visible at `seq`.
+pub(crate) fn seek_key(user_key: &[u8], seq: u64) -> Vec<u8> {
+    encode(user_key, seq, VALUE_TYPE_FOR_SEEK)
+}
+
+/// The largest internal key for `user_key`: every version of the key sorts at or before it.
+pub(crate) fn seek_for_prev_key(user_key: &[u8]) -> Vec<u8> {
+    encode(user_key, 0, ValueType::Deletion)
+}
+
+pub(crate) fn user_key(internal_key: &[u8]) -> &[u8] {
+    &internal_key[..internal_key.len().saturating_sub(TRAILER_SIZE)]
+}
+
+fn trailer(internal_key: &[u8]) -> u64 {
+    let Some(start) = internal_key.len().checked_sub(TRAILER_SIZE) else {
+        return 0;
+    };
+    u64::from_le_bytes(internal_key[start..].try_into().unwrap())
+}
+
+pub(crate) fn sequence(internal_key: &[u8]) -> u64 {
+    trailer(internal_key) >> 8
+}
+
+/// The entry's value type, or `None` for a type this version does not know.
+pub(crate) fn value_type(internal_key: &[u8]) -> Option<ValueType> {
+    ValueType::from_u8(trailer(internal_key) as u8)
+}
+
+/// True if the entry is a tombstone.
+pub(crate) fn is_deletion(internal_key: &[u8]) -> bool {
+    value_type(internal_key) == Some(ValueType::Deletion)
+}
+
+/// Orders internal keys by user key ascending, then sequence (and type) descending.
+///
+/// Plain bytewise order would be wrong here: with the trailer appended, `"a" + trailer` vs
+/// `"ab" + trailer` would compare a trailer byte against `'b'`.
+pub(crate) fn compare(a: &[u8], b: &[u8]) -> Ordering {
+    user_key(a)
+        .cmp(user_key(b))
+        .then_with(|| trailer(b).cmp(&trailer(a)))
+}
+
+/// An owned internal key, ordered by [`compare`] so it can key the MemTable's SkipList.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct InternalKey(pub(crate) Vec<u8>);
+
+impl InternalKey {
+    pub(crate) fn new(user_key: &[u8], seq: u64, value_type: ValueType) -> Self {
+        Self(encode(user_key, seq, value_type))
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.0, &other.0)
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_key_roundtrip() {
+        let key = encode(b"user:42", 7, ValueType::Value);
+        assert_eq!(user_key(&key), b"user:42");
+        assert_eq!(sequence(&key), 7);
+        assert_eq!(value_type(&key), Some(ValueType::Value));
+        assert!(!is_deletion(&key));
+
+        let tombstone = encode(b"user:42", MAX_SEQUENCE, ValueType::Deletion);
+        assert_eq!(sequence(&tombstone), MAX_SEQUENCE);
+        assert!(is_deletion(&tombstone));
+    }
+
+    #[test]
+    fn test_internal_key_ordering() {
+        let value = |k: &[u8], seq| encode(k, seq, ValueType::Value);
+
+        // Newer versions of the same key sort first.
+        assert_eq!(compare(&value(b"a", 9), &value(b"a", 3)), Ordering::Less);
+        // User key order wins over sequence order, even when one key prefixes the other.
+        assert_eq!(compare(&value(b"a", 1), &value(b"ab", 9)), Ordering::Less);
+        assert_eq!(
+            compare(&value(b"b", 9), &value(b"ab", 1)),
+            Ordering::Greater
+        );
+        assert_eq!(compare(&value(b"a", 5), &value(b"a", 5)), Ordering::Equal);
+
+        // The seek targets bracket every version of a key.
+        let tombstone = encode(b"a", 5, ValueType::Deletion);
+        assert_eq!(compare(&seek_key(b"a", 5), &tombstone), Ordering::Less);
+        assert_eq!(
+            compare(
+                &seek_key(b"a", 5),
+                &encode(b"a", 5, ValueType::ExpiringValue)
+            ),
+            Ordering::Equal
+        );
+        assert_eq!(compare(&seek_key(b"a", 5), &value(b"a", 5)), Ordering::Less);
+        assert_eq!(
+            compare(&seek_for_prev_key(b"a"), &value(b"a", 1)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare(&seek_for_prev_key(b"a"), &value(b"a0", 1)),
+            Ordering::Less
+        );
+    }
+}
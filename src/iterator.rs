@@ -0,0 +1,1174 @@
+use crate::Error;
+use crate::internal_key::{self, ValueType};
+use crate::merge::{self, MergeOperator};
+use crate::range_del::RangeTombstones;
+use crate::ttl;
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// A positioned cursor over a sorted run of `(key, value)` pairs, in the LevelDB style.
+///
+/// The cursor sits *on* an entry, or is invalid once it has walked off either end. `key` and
+/// `value` may only be called while it is valid. Every layer of the engine — the MemTables and
+/// each SSTable — exposes its entries through this trait so [`MergingIterator`] can combine
+/// them in either direction. Keys are internal keys (see [`internal_key`]) and are strictly
+/// increasing in internal key order within one source.
+pub(crate) trait InternalIterator: Send {
+    fn valid(&self) -> bool;
+    fn key(&self) -> &[u8];
+    fn value(&self) -> &[u8];
+    fn seek_to_first(&mut self);
+    fn seek_to_last(&mut self);
+    /// Positions at the first entry whose key is >= `key`.
+    fn seek(&mut self, key: &[u8]);
+    /// Positions at the last entry whose key is <= `key`.
+    fn seek_for_prev(&mut self, key: &[u8]);
+    fn next(&mut self);
+    fn prev(&mut self);
+    /// Why the cursor turned invalid early, if it did: a source that cannot be read to the end
+    /// stops there instead of leaving entries out.
+    fn error(&self) -> Option<&Error> {
+        None
+    }
+    /// Hands over the error `error` reports, to be passed on to the caller. The cursor stays
+    /// invalid until it is positioned again.
+    fn take_error(&mut self) -> Option<Error> {
+        None
+    }
+}
+
+/// An [`InternalIterator`] over entries copied out of a live MemTable.
+pub(crate) struct VecIterator {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    // `entries.len()` doubles as the invalid position; stepping back from 0 wraps to it too.
+    pos: usize,
+}
+
+impl VecIterator {
+    /// `entries` must already be sorted in internal key order.
+    pub(crate) fn new(entries: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        let pos = entries.len();
+        Self { entries, pos }
+    }
+}
+
+impl InternalIterator for VecIterator {
+    fn valid(&self) -> bool {
+        self.pos < self.entries.len()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.entries[self.pos].0
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.entries[self.pos].1
+    }
+
+    fn seek_to_first(&mut self) {
+        self.pos = 0;
+    }
+
+    fn seek_to_last(&mut self) {
+        self.pos = self.entries.len().saturating_sub(1);
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.pos = self
+            .entries
+            .partition_point(|(k, _)| internal_key::compare(k, key) == Ordering::Less);
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        let end = self
+            .entries
+            .partition_point(|(k, _)| internal_key::compare(k, key) != Ordering::Greater);
+        self.pos = match end {
+            0 => self.entries.len(),
+            n => n - 1,
+        };
+    }
+
+    fn next(&mut self) {
+        if self.valid() {
+            self.pos += 1;
+        }
+    }
+
+    fn prev(&mut self) {
+        if self.valid() {
+            self.pos = self.pos.checked_sub(1).unwrap_or(self.entries.len());
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Merges several [`InternalIterator`]s into one cursor in internal key order.
+///
+/// Children are ordered newest-first (active MemTable, immutable MemTable, then SSTables in
+/// the order `get` searches them). Every version of a user key is yielded, newest first;
+/// picking the one a reader may see is left to [`DbIterator`]. Should the identical internal
+/// key appear in several children, the lowest index wins and the copies are stepped over
+/// together with it.
+///
+/// Compaction merges with a min-heap, but that only works in one direction. Here every
+/// direction change has to reposition all the children anyway, and with the handful of
+/// children an engine has (two MemTables plus a few SSTables per level) a linear scan for the
+/// smallest or largest key is as fast as maintaining a heap.
+pub(crate) struct MergingIterator {
+    children: Vec<Box<dyn InternalIterator>>,
+    current: Option<usize>,
+    direction: Direction,
+}
+
+impl MergingIterator {
+    pub(crate) fn new(children: Vec<Box<dyn InternalIterator>>) -> Self {
+        Self {
+            children,
+            current: None,
+            direction: Direction::Forward,
+        }
+    }
+
+    pub(crate) fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        self.children[self.current.expect("MergingIterator is not valid")].key()
+    }
+
+    pub(crate) fn value(&self) -> &[u8] {
+        self.children[self.current.expect("MergingIterator is not valid")].value()
+    }
+
+    pub(crate) fn seek(&mut self, key: &[u8]) {
+        for child in &mut self.children {
+            child.seek(key);
+        }
+        self.direction = Direction::Forward;
+        self.find_smallest();
+    }
+
+    pub(crate) fn seek_for_prev(&mut self, key: &[u8]) {
+        for child in &mut self.children {
+            child.seek_for_prev(key);
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
+
+    pub(crate) fn seek_to_first(&mut self) {
+        for child in &mut self.children {
+            child.seek_to_first();
+        }
+        self.direction = Direction::Forward;
+        self.find_smallest();
+    }
+
+    pub(crate) fn seek_to_last(&mut self) {
+        for child in &mut self.children {
+            child.seek_to_last();
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
+
+    pub(crate) fn next(&mut self) {
+        if !self.valid() {
+            return;
+        }
+        let key = self.key().to_vec();
+
+        for child in &mut self.children {
+            // Going forward every child sits at or after `key`. After a reverse walk they sit
+            // at or before it, so they are first brought back in line with a seek.
+            if self.direction == Direction::Reverse {
+                child.seek(&key);
+            }
+            if child.valid() && child.key() == key.as_slice() {
+                child.next();
+            }
+        }
+
+        self.direction = Direction::Forward;
+        self.find_smallest();
+    }
+
+    pub(crate) fn prev(&mut self) {
+        if !self.valid() {
+            return;
+        }
+        let key = self.key().to_vec();
+
+        for child in &mut self.children {
+            if self.direction == Direction::Forward {
+                child.seek_for_prev(&key);
+            }
+            if child.valid() && child.key() == key.as_slice() {
+                child.prev();
+            }
+        }
+
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
+
+    /// The first error among the children. Once a child has failed the merger is invalid:
+    /// going on without it could yield versions that the failed child shadows.
+    fn error(&self) -> Option<&Error> {
+        self.children.iter().find_map(|child| child.error())
+    }
+
+    /// Takes the first error among the children. The merger stays invalid until the next
+    /// seek, which starts every child afresh.
+    pub(crate) fn take_error(&mut self) -> Option<Error> {
+        self.children
+            .iter_mut()
+            .find_map(|child| child.take_error())
+    }
+
+    // Strict comparisons keep the lowest (newest) index on ties.
+    fn find_smallest(&mut self) {
+        if self.error().is_some() {
+            self.current = None;
+            return;
+        }
+        let mut best: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate() {
+            if child.valid()
+                && best.is_none_or(|b| {
+                    internal_key::compare(child.key(), self.children[b].key()) == Ordering::Less
+                })
+            {
+                best = Some(i);
+            }
+        }
+        self.current = best;
+    }
+
+    fn find_largest(&mut self) {
+        if self.error().is_some() {
+            self.current = None;
+            return;
+        }
+        let mut best: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate() {
+            if child.valid()
+                && best.is_none_or(|b| {
+                    internal_key::compare(child.key(), self.children[b].key()) == Ordering::Greater
+                })
+            {
+                best = Some(i);
+            }
+        }
+        self.current = best;
+    }
+}
+
+/// Turns the versions yielded by a [`MergingIterator`] into what a reader pinned at
+/// `sequence` sees: one entry per user key, holding its newest version written at or before
+/// `sequence`, with deleted keys left out and merge operands folded. Keys are user keys.
+///
+/// This follows LevelDB's `DBIter`. Going forward the merger sits on the entry being yielded.
+/// Going backward the newest visible version of a key is only known once every version of it
+/// has been passed, so the merger ends up on the entry *before* the key and the key and value
+/// are kept in `saved_key` and `saved_value`. A key with merge operands is handled the same way
+/// in both directions, since folding them means reading down to the value beneath.
+///
+/// Range tombstones live outside the merger: a version one of them covers is read as a point
+/// tombstone, and so is an expired value (see [`current_type`](Self::current_type)).
+struct DbIterator {
+    merger: MergingIterator,
+    sequence: u64,
+    range_tombstones: RangeTombstones,
+    // The time expiring values are judged against, fixed when the iterator is created.
+    now: u64,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    direction: Direction,
+    valid: bool,
+    // Forward: a user key whose remaining versions must be skipped, or the current key if it
+    // was merged. Reverse: the current key.
+    saved_key: Vec<u8>,
+    saved_value: Vec<u8>,
+    // Forward only: the current key's operands were folded into `saved_value`, leaving the
+    // merger past them — on the value beneath, or already on a later key.
+    current_entry_is_merged: bool,
+}
+
+impl DbIterator {
+    fn new(
+        merger: MergingIterator,
+        sequence: u64,
+        range_tombstones: RangeTombstones,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+    ) -> Self {
+        Self {
+            merger,
+            sequence,
+            range_tombstones,
+            now: ttl::now(),
+            merge_operator,
+            direction: Direction::Forward,
+            valid: false,
+            saved_key: Vec::new(),
+            saved_value: Vec::new(),
+            current_entry_is_merged: false,
+        }
+    }
+
+    fn valid(&self) -> bool {
+        self.valid
+    }
+
+    // The type of the version under the merger, or a deletion if it has expired or a range
+    // tombstone visible to us covers it.
+    fn current_type(&self) -> Option<ValueType> {
+        let key = self.merger.key();
+        let covered = self.range_tombstones.covers(
+            internal_key::user_key(key),
+            internal_key::sequence(key),
+            self.sequence,
+        );
+        match internal_key::value_type(key) {
+            _ if covered => Some(ValueType::Deletion),
+            Some(ValueType::ExpiringValue) if ttl::is_expired(self.merger.value(), self.now) => {
+                Some(ValueType::Deletion)
+            }
+            value_type => value_type,
+        }
+    }
+
+    // The value under the merger, without the deadline an expiring value is stored with.
+    fn current_value(&self) -> &[u8] {
+        match internal_key::value_type(self.merger.key()) {
+            Some(ValueType::ExpiringValue) => ttl::decode(self.merger.value()).1,
+            _ => self.merger.value(),
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        debug_assert!(self.valid);
+        match self.direction {
+            Direction::Forward if !self.current_entry_is_merged => {
+                internal_key::user_key(self.merger.key())
+            }
+            _ => &self.saved_key,
+        }
+    }
+
+    fn value(&self) -> &[u8] {
+        debug_assert!(self.valid);
+        match self.direction {
+            Direction::Forward if !self.current_entry_is_merged => self.current_value(),
+            _ => &self.saved_value,
+        }
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.direction = Direction::Forward;
+        // The newest version visible to us is the first entry at or after (key, sequence).
+        self.merger
+            .seek(&internal_key::seek_key(key, self.sequence));
+        self.find_next_user_entry(false);
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.direction = Direction::Reverse;
+        // Every version of `key` sorts at or before this target, so all of them are behind the
+        // cursor.
+        self.merger
+            .seek_for_prev(&internal_key::seek_for_prev_key(key));
+        self.find_prev_user_entry();
+    }
+
+    fn seek_to_first(&mut self) {
+        self.direction = Direction::Forward;
+        self.merger.seek_to_first();
+        self.find_next_user_entry(false);
+    }
+
+    fn seek_to_last(&mut self) {
+        self.direction = Direction::Reverse;
+        self.merger.seek_to_last();
+        self.find_prev_user_entry();
+    }
+
+    fn next(&mut self) {
+        if !self.valid {
+            return;
+        }
+
+        if self.direction == Direction::Reverse {
+            // The merger sits just before the versions of the current key, which is still in
+            // `saved_key`; step into them and let the skipping below pass over them.
+            self.direction = Direction::Forward;
+            if self.merger.valid() {
+                self.merger.next();
+            } else {
+                self.merger.seek_to_first();
+            }
+        } else if !self.current_entry_is_merged {
+            self.saved_key = internal_key::user_key(self.merger.key()).to_vec();
+            self.merger.next();
+        }
+        // After a merge `saved_key` already holds the current key and the merger is past its
+        // operands; what is left of its versions is skipped below.
+        self.find_next_user_entry(true);
+    }
+
+    fn prev(&mut self) {
+        if !self.valid {
+            return;
+        }
+
+        if self.direction == Direction::Forward {
+            // Walk back past every version of the current key, so the reverse scan below
+            // starts on the previous user key. After a merge the merger is already past those
+            // versions, possibly off the end, and has to come back over them first.
+            if self.current_entry_is_merged {
+                if !self.merger.valid() {
+                    self.merger.seek_to_last();
+                }
+            } else {
+                self.saved_key = internal_key::user_key(self.merger.key()).to_vec();
+            }
+            while self.merger.valid()
+                && internal_key::user_key(self.merger.key()) >= self.saved_key.as_slice()
+            {
+                self.merger.prev();
+            }
+            if !self.merger.valid() {
+                self.valid = false;
+                self.current_entry_is_merged = false;
+                self.saved_key.clear();
+                self.saved_value.clear();
+                return;
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.find_prev_user_entry();
+    }
+
+    // Moves forward to the newest visible version of the next live user key. With `skipping`,
+    // versions of `saved_key` (and of any key a tombstone is found for) are passed over.
+    fn find_next_user_entry(&mut self, mut skipping: bool) {
+        self.current_entry_is_merged = false;
+        while self.merger.valid() {
+            let key = self.merger.key();
+            if internal_key::sequence(key) <= self.sequence {
+                let user_key = internal_key::user_key(key);
+                if !(skipping && user_key <= self.saved_key.as_slice()) {
+                    match self.current_type() {
+                        // A tombstone hides every older version of its key.
+                        Some(ValueType::Deletion) => {
+                            self.saved_key = user_key.to_vec();
+                            skipping = true;
+                        }
+                        Some(ValueType::Merge) => {
+                            self.saved_key = user_key.to_vec();
+                            self.merge_forward();
+                            self.valid = true;
+                            return;
+                        }
+                        _ => {
+                            self.valid = true;
+                            self.saved_key.clear();
+                            return;
+                        }
+                    }
+                }
+            }
+            self.merger.next();
+        }
+        self.valid = false;
+        self.saved_key.clear();
+    }
+
+    // Folds the operands of `saved_key`, starting with the one under the merger, onto the
+    // value or tombstone beneath them, into `saved_value`. Every version after the first one
+    // we see is older, so all of them are visible.
+    fn merge_forward(&mut self) {
+        let mut operands = Vec::new();
+        let mut base = None;
+        while self.merger.valid() {
+            let key = self.merger.key();
+            if internal_key::user_key(key) != self.saved_key.as_slice() {
+                break;
+            }
+            match self.current_type() {
+                Some(ValueType::Merge) => operands.push(self.merger.value().to_vec()),
+                Some(ValueType::Deletion) => break,
+                _ => {
+                    base = Some(self.current_value().to_vec());
+                    break;
+                }
+            }
+            self.merger.next();
+        }
+
+        operands.reverse();
+        self.saved_value = merge::full_merge(
+            self.merge_operator.as_deref(),
+            &self.saved_key,
+            base.as_deref(),
+            &operands,
+        );
+        self.current_entry_is_merged = true;
+    }
+
+    // Moves backward over the versions of the previous user key, remembering the newest visible
+    // one. Stops on the first entry of an even earlier key once a live version has been found.
+    fn find_prev_user_entry(&mut self) {
+        self.current_entry_is_merged = false;
+        let mut found_live = false;
+        // Operands of `saved_key` seen so far, oldest first, and whether `saved_value` holds a
+        // value beneath them.
+        let mut operands: Vec<Vec<u8>> = Vec::new();
+        let mut has_base = false;
+        while self.merger.valid() {
+            let key = self.merger.key();
+            if internal_key::sequence(key) <= self.sequence {
+                let user_key = internal_key::user_key(key);
+                if found_live && user_key < self.saved_key.as_slice() {
+                    break;
+                }
+                // Versions of one key arrive oldest first here, so a value or tombstone
+                // overrides everything before it and an operand applies on top of it.
+                match self.current_type() {
+                    Some(ValueType::Deletion) => {
+                        found_live = false;
+                        has_base = false;
+                        operands.clear();
+                        self.saved_key.clear();
+                        self.saved_value.clear();
+                    }
+                    Some(ValueType::Merge) => {
+                        found_live = true;
+                        self.saved_key = user_key.to_vec();
+                        operands.push(self.merger.value().to_vec());
+                    }
+                    _ => {
+                        found_live = true;
+                        has_base = true;
+                        operands.clear();
+                        self.saved_key = user_key.to_vec();
+                        self.saved_value = self.current_value().to_vec();
+                    }
+                }
+            }
+            self.merger.prev();
+        }
+
+        if found_live {
+            if !operands.is_empty() {
+                self.saved_value = merge::full_merge(
+                    self.merge_operator.as_deref(),
+                    &self.saved_key,
+                    has_base.then_some(self.saved_value.as_slice()),
+                    &operands,
+                );
+            }
+            self.valid = true;
+        } else {
+            self.valid = false;
+            self.saved_key.clear();
+            self.saved_value.clear();
+            self.direction = Direction::Forward;
+        }
+    }
+}
+
+// Where the cursor sits relative to the entry `DbIterator` is positioned on. Positions
+// are *between* entries, so `next` and `prev` both return an entry and can be interleaved
+// freely: `prev` right after `next` returns the same entry again.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Position {
+    BeforeFirst,
+    BeforeCurrent,
+    AfterCurrent,
+    AfterLast,
+}
+
+/// An ordered, de-duplicated, double-ended view over every layer of the engine, returned by
+/// [`StorageEngine::scan`](crate::StorageEngine::scan) and
+/// [`StorageEngine::iter`](crate::StorageEngine::iter).
+///
+/// When a key appears in several layers the newest version wins. Tombstones win that race
+/// like any other version and are then hidden, which is what makes a deleted key disappear
+/// even though older SSTables still contain it.
+///
+/// The cursor sits *between* entries, like a text cursor, and starts before the first entry
+/// in range — so plain `for` loops and iterator adapters walk the range in ascending order.
+/// [`prev`](Self::prev) walks the other way, and the `seek` methods reposition the cursor
+/// anywhere within the range:
+///
+/// ```no_run
+/// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+/// // The ten most recent events, newest first.
+/// let mut iter = engine.scan_prefix(b"event:")?;
+/// iter.seek_to_last();
+/// for _ in 0..10 {
+///     let Some(entry) = iter.prev() else { break };
+///     let (key, value) = entry?;
+///     println!("{:?} = {:?}", key, value);
+/// }
+/// # Ok::<(), lsmdb::Error>(())
+/// ```
+///
+/// The iterator reads a consistent snapshot: it sees exactly the writes that had completed
+/// when it was created (or those visible to the [`Snapshot`](crate::Snapshot) it was created
+/// from), no matter how long it lives or how it is repositioned.
+///
+/// An SSTable that turns out to be damaged ends the walk where the damage is: the iterator
+/// yields the error — an [`Error::Corruption`] naming the table — in place of the next entry,
+/// and nothing after it. Repositioning the cursor reads the tables afresh.
+pub struct EngineIterator {
+    db: DbIterator,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    position: Position,
+}
+
+impl EngineIterator {
+    pub(crate) fn new(
+        children: Vec<Box<dyn InternalIterator>>,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+        sequence: u64,
+        range_tombstones: RangeTombstones,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+    ) -> Self {
+        Self {
+            db: DbIterator::new(
+                MergingIterator::new(children),
+                sequence,
+                range_tombstones,
+                merge_operator,
+            ),
+            start,
+            end,
+            position: Position::BeforeFirst,
+        }
+    }
+
+    /// Moves the cursor before the first entry in range.
+    pub fn seek_to_first(&mut self) {
+        self.position = Position::BeforeFirst;
+    }
+
+    /// Moves the cursor after the last entry in range, so [`prev`](Self::prev) returns it.
+    pub fn seek_to_last(&mut self) {
+        self.position = Position::AfterLast;
+    }
+
+    /// Moves the cursor before the first entry whose key is >= `key`, so `next` returns it.
+    pub fn seek<K: AsRef<[u8]>>(&mut self, key: K) {
+        let key = key.as_ref();
+        match &self.start {
+            Bound::Included(s) | Bound::Excluded(s) if key < s.as_slice() => self.db.seek(s),
+            _ => self.db.seek(key),
+        }
+        self.position = if self.skip_forward() {
+            Position::BeforeCurrent
+        } else {
+            Position::AfterLast
+        };
+    }
+
+    /// Moves the cursor after the last entry whose key is <= `key`, so
+    /// [`prev`](Self::prev) returns it.
+    pub fn seek_for_prev<K: AsRef<[u8]>>(&mut self, key: K) {
+        let key = key.as_ref();
+        match &self.end {
+            Bound::Included(e) | Bound::Excluded(e) if key > e.as_slice() => {
+                self.db.seek_for_prev(e)
+            }
+            _ => self.db.seek_for_prev(key),
+        }
+        self.position = if self.skip_backward() {
+            Position::AfterCurrent
+        } else {
+            Position::BeforeFirst
+        };
+    }
+
+    /// Returns the entry before the cursor and moves the cursor back over it, or `None` once
+    /// the cursor is before the first entry in range. A damaged SSTable on the way is
+    /// reported as for [`next`](Self::next).
+    pub fn prev(&mut self) -> Option<<Self as Iterator>::Item> {
+        match self.position {
+            Position::BeforeFirst => return None,
+            Position::AfterCurrent => {}
+            Position::BeforeCurrent => {
+                self.db.prev();
+                if !self.skip_backward() {
+                    self.position = Position::BeforeFirst;
+                    return self.end_of_walk();
+                }
+            }
+            Position::AfterLast => {
+                match &self.end {
+                    Bound::Included(e) | Bound::Excluded(e) => self.db.seek_for_prev(e),
+                    Bound::Unbounded => self.db.seek_to_last(),
+                }
+                if !self.skip_backward() {
+                    self.position = Position::BeforeFirst;
+                    return self.end_of_walk();
+                }
+            }
+        }
+
+        self.position = Position::BeforeCurrent;
+        Some(Ok(self.current_entry()))
+    }
+
+    fn current_entry(&self) -> (Vec<u8>, Vec<u8>) {
+        (self.db.key().to_vec(), self.db.value().to_vec())
+    }
+
+    // What to yield where the walk runs out: nothing, or the error that cut it short.
+    fn end_of_walk(&mut self) -> Option<<Self as Iterator>::Item> {
+        self.db.merger.take_error().map(Err)
+    }
+
+    fn is_before_start(&self, key: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) => key < start.as_slice(),
+            Bound::Excluded(start) => key <= start.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn is_past_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => key > end.as_slice(),
+            Bound::Excluded(end) => key >= end.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    // Moves forward to the first entry at or after the current position that is within the
+    // start bound. Returns false if there is none before the end bound.
+    fn skip_forward(&mut self) -> bool {
+        while self.db.valid() {
+            let key = self.db.key();
+            if self.is_past_end(key) {
+                return false;
+            }
+            if self.is_before_start(key) {
+                self.db.next();
+                continue;
+            }
+            return true;
+        }
+        false
+    }
+
+    // The mirror image of `skip_forward`.
+    fn skip_backward(&mut self) -> bool {
+        while self.db.valid() {
+            let key = self.db.key();
+            if self.is_before_start(key) {
+                return false;
+            }
+            if self.is_past_end(key) {
+                self.db.prev();
+                continue;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+impl Iterator for EngineIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    /// Returns the entry after the cursor and moves the cursor past it. If a damaged SSTable
+    /// cuts the walk short, returns the error instead, and `None` after it.
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.position {
+            Position::AfterLast => return None,
+            Position::BeforeCurrent => {}
+            Position::AfterCurrent => {
+                self.db.next();
+                if !self.skip_forward() {
+                    self.position = Position::AfterLast;
+                    return self.end_of_walk();
+                }
+            }
+            Position::BeforeFirst => {
+                match &self.start {
+                    Bound::Included(s) | Bound::Excluded(s) => self.db.seek(s),
+                    Bound::Unbounded => self.db.seek_to_first(),
+                }
+                if !self.skip_forward() {
+                    self.position = Position::AfterLast;
+                    return self.end_of_walk();
+                }
+            }
+        }
+
+        self.position = Position::AfterCurrent;
+        Some(Ok(self.current_entry()))
+    }
+}
+
+/// Returns the exclusive upper bound of all keys starting with `prefix`.
+///
+/// That is the prefix with its last byte incremented, after dropping any trailing `0xFF`
+/// bytes (which cannot be incremented without carrying). A prefix made only of `0xFF` bytes
+/// — or an empty prefix — has no finite successor, so the scan is unbounded above.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < 0xFF {
+            upper.push(last + 1);
+            return Bound::Excluded(upper);
+        }
+    }
+    Bound::Unbounded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal_key::{MAX_SEQUENCE, ValueType};
+    use crate::range_del::RangeTombstone;
+
+    // An empty value stands for a deletion, to keep the test tables short.
+    fn entry(key: &str, seq: u64, value: &str) -> (Vec<u8>, Vec<u8>) {
+        let value_type = if value.is_empty() {
+            ValueType::Deletion
+        } else {
+            ValueType::Value
+        };
+        (
+            internal_key::encode(key.as_bytes(), seq, value_type),
+            value.as_bytes().to_vec(),
+        )
+    }
+
+    // Every entry of a source is written at the same sequence number `seq`.
+    fn source(seq: u64, keys: &[(&str, &str)]) -> Box<dyn InternalIterator> {
+        Box::new(VecIterator::new(
+            keys.iter().map(|(k, v)| entry(k, seq, v)).collect(),
+        ))
+    }
+
+    fn keys(entries: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>>) -> Vec<String> {
+        entries
+            .map(|entry| String::from_utf8(entry.unwrap().0).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(
+            prefix_upper_bound(b"user:42:"),
+            Bound::Excluded(b"user:42;".to_vec())
+        );
+        assert_eq!(
+            prefix_upper_bound(&[0x01, 0xFF, 0xFF]),
+            Bound::Excluded(vec![0x02])
+        );
+        assert_eq!(prefix_upper_bound(&[0xFF, 0xFF]), Bound::Unbounded);
+        assert_eq!(prefix_upper_bound(b""), Bound::Unbounded);
+    }
+
+    #[test]
+    fn test_engine_iterator_both_directions() {
+        // Newest first: "b" is deleted and "c" overwritten by the newer source.
+        let newer = source(2, &[("b", ""), ("c", "c2"), ("e", "e2")]);
+        let older = source(1, &[("a", "a1"), ("b", "b1"), ("c", "c1"), ("d", "d1")]);
+        let mut iter = EngineIterator::new(
+            vec![newer, older],
+            Bound::Unbounded,
+            Bound::Unbounded,
+            MAX_SEQUENCE,
+            RangeTombstones::default(),
+            None,
+        );
+
+        iter.seek_to_last();
+        let mut backward = Vec::new();
+        while let Some(e) = iter.prev() {
+            let (k, v) = e.unwrap();
+            backward.push((String::from_utf8(k).unwrap(), String::from_utf8(v).unwrap()));
+        }
+        let expected: Vec<(String, String)> = [("e", "e2"), ("d", "d1"), ("c", "c2"), ("a", "a1")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(backward, expected);
+
+        // The cursor is now before the first entry, so walking forward yields everything.
+        assert_eq!(keys(&mut iter), vec!["a", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_engine_iterator_direction_changes() {
+        let newer = source(2, &[("b", "b2"), ("d", "")]);
+        let older = source(1, &[("a", "a1"), ("c", "c1"), ("d", "d1"), ("e", "e1")]);
+        let mut iter = EngineIterator::new(
+            vec![newer, older],
+            Bound::Unbounded,
+            Bound::Unbounded,
+            MAX_SEQUENCE,
+            RangeTombstones::default(),
+            None,
+        );
+
+        assert_eq!(iter.next().unwrap().unwrap().0, b"a");
+        assert_eq!(iter.next().unwrap().unwrap().0, b"b");
+        // `prev` straight after `next` hands back the same entry.
+        assert_eq!(iter.prev().unwrap().unwrap().0, b"b");
+        assert_eq!(iter.prev().unwrap().unwrap().0, b"a");
+        assert!(iter.prev().is_none());
+        assert_eq!(iter.next().unwrap().unwrap().0, b"a");
+
+        // "d" is deleted, so seeking to it lands on "e" going forward and "c" going back.
+        iter.seek("d");
+        assert_eq!(iter.next().unwrap().unwrap().0, b"e");
+        iter.seek_for_prev("d");
+        assert_eq!(iter.prev().unwrap().unwrap().0, b"c");
+        assert_eq!(iter.next().unwrap().unwrap().0, b"c");
+        assert_eq!(iter.next().unwrap().unwrap().0, b"e");
+        assert!(iter.next().is_none());
+        assert_eq!(iter.prev().unwrap().unwrap().0, b"e");
+    }
+
+    #[test]
+    fn test_engine_iterator_respects_bounds() {
+        let all = source(
+            1,
+            &[("a", "1"), ("b", "2"), ("c", "3"), ("d", "4"), ("e", "5")],
+        );
+        let mut iter = EngineIterator::new(
+            vec![all],
+            Bound::Excluded(b"a".to_vec()),
+            Bound::Excluded(b"e".to_vec()),
+            MAX_SEQUENCE,
+            RangeTombstones::default(),
+            None,
+        );
+
+        iter.seek_to_last();
+        assert_eq!(iter.prev().unwrap().unwrap().0, b"d");
+
+        // Seeks outside the range are clamped to it.
+        iter.seek("0");
+        assert_eq!(iter.next().unwrap().unwrap().0, b"b");
+        iter.seek_for_prev("z");
+        assert_eq!(iter.prev().unwrap().unwrap().0, b"d");
+
+        iter.seek_to_first();
+        assert_eq!(keys(&mut iter), vec!["b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_engine_iterator_reads_at_sequence() {
+        // One source holding several versions per key, newest first within each key:
+        // "a" is overwritten at 5, "b" deleted at 4 and "c" only created at 6.
+        let versions: Box<dyn InternalIterator> = Box::new(VecIterator::new(
+            [
+                ("a", 5, "a5"),
+                ("a", 1, "a1"),
+                ("b", 4, ""),
+                ("b", 2, "b2"),
+                ("c", 6, "c6"),
+            ]
+            .iter()
+            .map(|(k, seq, v)| entry(k, *seq, v))
+            .collect(),
+        ));
+        let mut iter = EngineIterator::new(
+            vec![versions],
+            Bound::Unbounded,
+            Bound::Unbounded,
+            3,
+            RangeTombstones::default(),
+            None,
+        );
+
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (&mut iter).map(Result::unwrap).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (b"a".to_vec(), b"a1".to_vec()),
+                (b"b".to_vec(), b"b2".to_vec())
+            ]
+        );
+
+        // Backwards, and across a direction change, the same versions are picked.
+        assert_eq!(
+            iter.prev().unwrap().unwrap(),
+            (b"b".to_vec(), b"b2".to_vec())
+        );
+        assert_eq!(
+            iter.prev().unwrap().unwrap(),
+            (b"a".to_vec(), b"a1".to_vec())
+        );
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            (b"a".to_vec(), b"a1".to_vec())
+        );
+        iter.seek_for_prev("c");
+        assert_eq!(
+            iter.prev().unwrap().unwrap(),
+            (b"b".to_vec(), b"b2".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_engine_iterator_folds_merge_operands() {
+        let merge = |key: &str, seq, value: &str| {
+            (
+                internal_key::encode(key.as_bytes(), seq, ValueType::Merge),
+                value.as_bytes().to_vec(),
+            )
+        };
+        // "a" has operands on a value, "b" on a tombstone, and "c" on nothing at all. The
+        // newest operand of "a" is split off into its own source, as if in a newer layer.
+        let newer: Box<dyn InternalIterator> =
+            Box::new(VecIterator::new(vec![merge("a", 9, "a9")]));
+        let older: Box<dyn InternalIterator> = Box::new(VecIterator::new(vec![
+            merge("a", 7, "a7"),
+            entry("a", 5, "a5"),
+            entry("a", 1, "a1"),
+            merge("b", 6, "b6"),
+            entry("b", 4, ""),
+            entry("b", 2, "b2"),
+            merge("c", 8, "c8"),
+            merge("c", 3, "c3"),
+        ]));
+        let mut iter = EngineIterator::new(
+            vec![newer, older],
+            Bound::Unbounded,
+            Bound::Unbounded,
+            MAX_SEQUENCE,
+            RangeTombstones::default(),
+            Some(Arc::new(crate::merge::tests::Append)),
+        );
+
+        let entry = |k: &str, v: &str| (k.as_bytes().to_vec(), v.as_bytes().to_vec());
+        let expected = vec![
+            entry("a", "a5,a7,a9"),
+            entry("b", "b6"),
+            entry("c", "c3,c8"),
+        ];
+        let forward: Vec<_> = (&mut iter).map(Result::unwrap).collect();
+        assert_eq!(forward, expected);
+
+        iter.seek_to_last();
+        let mut backward = Vec::new();
+        while let Some(e) = iter.prev() {
+            backward.push(e.unwrap());
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+
+        // Changing direction right after a merged entry picks up the neighbouring keys.
+        assert_eq!(iter.next().unwrap().unwrap(), entry("a", "a5,a7,a9"));
+        assert_eq!(iter.next().unwrap().unwrap(), entry("b", "b6"));
+        assert_eq!(iter.prev().unwrap().unwrap(), entry("b", "b6"));
+        assert_eq!(iter.prev().unwrap().unwrap(), entry("a", "a5,a7,a9"));
+        iter.seek("c");
+        assert_eq!(iter.next().unwrap().unwrap(), entry("c", "c3,c8"));
+        assert_eq!(iter.prev().unwrap().unwrap(), entry("c", "c3,c8"));
+        assert_eq!(iter.prev().unwrap().unwrap(), entry("b", "b6"));
+    }
+
+    #[test]
+    fn test_engine_iterator_applies_range_tombstones() {
+        let merge = |key: &str, seq, value: &str| {
+            (
+                internal_key::encode(key.as_bytes(), seq, ValueType::Merge),
+                value.as_bytes().to_vec(),
+            )
+        };
+        // `[b, d)@5` deletes "b" and "c" as written before it. "c" was written again at 6, and
+        // "d" lies past the exclusive end. The operand on "b" at 7 lands on nothing.
+        let versions: Box<dyn InternalIterator> = Box::new(VecIterator::new(vec![
+            entry("a", 1, "a1"),
+            merge("b", 7, "b7"),
+            entry("b", 2, "b2"),
+            entry("c", 6, "c6"),
+            entry("c", 3, "c3"),
+            entry("d", 4, "d4"),
+        ]));
+        let tombstones = RangeTombstones::new(vec![RangeTombstone {
+            start: b"b".to_vec(),
+            end: b"d".to_vec(),
+            seq: 5,
+        }]);
+        let mut iter = EngineIterator::new(
+            vec![versions],
+            Bound::Unbounded,
+            Bound::Unbounded,
+            MAX_SEQUENCE,
+            tombstones,
+            Some(Arc::new(crate::merge::tests::Append)),
+        );
+
+        let entry = |k: &str, v: &str| (k.as_bytes().to_vec(), v.as_bytes().to_vec());
+        let expected = vec![
+            entry("a", "a1"),
+            entry("b", "b7"),
+            entry("c", "c6"),
+            entry("d", "d4"),
+        ];
+        let forward: Vec<_> = (&mut iter).map(Result::unwrap).collect();
+        assert_eq!(forward, expected);
+
+        iter.seek_to_last();
+        let mut backward = Vec::new();
+        while let Some(e) = iter.prev() {
+            backward.push(e.unwrap());
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+    }
+
+    #[test]
+    fn test_engine_iterator_hides_expired_values() {
+        let expiring = |key: &str, seq, expires_at, value: &str| {
+            (
+                internal_key::encode(key.as_bytes(), seq, ValueType::ExpiringValue),
+                ttl::encode(expires_at, value.as_bytes()),
+            )
+        };
+        // "a" expired and shadows an older value; "b" has yet to expire.
+        let versions: Box<dyn InternalIterator> = Box::new(VecIterator::new(vec![
+            expiring("a", 3, 1, "a3"),
+            entry("a", 1, "a1"),
+            expiring("b", 2, u64::MAX, "b2"),
+            entry("c", 1, "c1"),
+        ]));
+        let mut iter = EngineIterator::new(
+            vec![versions],
+            Bound::Unbounded,
+            Bound::Unbounded,
+            MAX_SEQUENCE,
+            RangeTombstones::default(),
+            None,
+        );
+
+        let entry = |k: &str, v: &str| (k.as_bytes().to_vec(), v.as_bytes().to_vec());
+        let expected = vec![entry("b", "b2"), entry("c", "c1")];
+        let forward: Vec<_> = (&mut iter).map(Result::unwrap).collect();
+        assert_eq!(forward, expected);
+
+        iter.seek_to_last();
+        assert_eq!(iter.prev().unwrap().unwrap(), entry("c", "c1"));
+        assert_eq!(iter.prev().unwrap().unwrap(), entry("b", "b2"));
+        assert!(iter.prev().is_none());
+    }
+}
@@ -29,25 +29,64 @@
 //!
 //! Author: Nrishinghananda Roy
 
+mod background;
+mod batch;
 mod bloom_filter;
+mod column_family;
 pub mod constants;
+mod error;
+mod internal_key;
+mod iterator;
 mod memtable;
+mod merge;
+mod options;
+mod range_del;
+mod secondary;
+mod snapshot;
 mod sstable;
+mod transaction;
+mod transaction_db;
+mod ttl;
 mod wal;
 
-use crate::constants::{
-    BLOCK_CACHE_CAPACITY, BLOOM_FILTER_FPR, L0_COMPACTION_TRIGGER, L1_MAX_BYTES,
-    LEVEL_SIZE_MULTIPLIER, MAX_LEVELS, MEMTABLE_CAPACITY_BYTES,
+pub use crate::batch::WriteBatch;
+pub use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_NAME};
+pub use crate::error::Error;
+pub use crate::iterator::EngineIterator;
+pub use crate::merge::MergeOperator;
+pub use crate::options::{CompressionType, CorruptSSTablePolicy, Options, ReadOptions};
+pub use crate::snapshot::Snapshot;
+pub use crate::transaction::{Transaction, TransactionConflict};
+pub use crate::transaction_db::{
+    LockError, PessimisticTransaction, TransactionDB, TransactionDBOptions,
 };
-use crate::memtable::MemTable;
-use crate::sstable::{Manifest, SSTableBuilder, SSTableReader, VersionEdit, compaction::compact};
-use crate::wal::Wal;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Condvar, Mutex, RwLock};
+
+use crate::background::BackgroundJobs;
+use crate::batch::BatchOp;
+use crate::column_family::{ColumnFamilyData, DEFAULT_COLUMN_FAMILY_ID, FileIds};
+use crate::constants::MAX_KEY_SIZE;
+use crate::internal_key::{MAX_SEQUENCE, ValueType};
+use crate::iterator::{InternalIterator, VecIterator, prefix_upper_bound};
+use crate::memtable::{MemTable, MemTableIterator};
+use crate::merge::MergeContext;
+use crate::range_del::RangeTombstones;
+use crate::secondary::SecondaryState;
+use crate::snapshot::SnapshotList;
+use crate::sstable::compaction::{SSTableIterator, compact};
+use crate::sstable::{Manifest, SSTableBuilder, SSTableReader, VersionEdit};
+use crate::wal::{Opcode, Record, Wal};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 pub type BlockCache = Arc<RwLock<lru::LruCache<(u64, u64), Arc<Vec<u8>>>>>;
 
+// Every column family by id, the default one included.
+type ColumnFamilies = Arc<RwLock<BTreeMap<u32, Arc<ColumnFamilyData>>>>;
+
 /// The central coordinator of the LSM-Tree storage engine.
 ///
 /// ## Concurrency Model
@@ -71,23 +110,46 @@ pub type BlockCache = Arc<RwLock<lru::LruCache<(u64, u64), Arc<Vec<u8>>>>>;
 /// - **`sstables` — `RwLock`**: Many threads can read the SSTable list concurrently during
 ///   parallel point queries. Only a flush or compaction mutates it, which is rare. This is the
 ///   one place where the reader/writer split actually pays off.
+///
+/// Each column family has its own MemTables and SSTable list, locked as above. The WAL is
+/// shared, and its lock is always taken before any family's MemTable locks.
 pub struct StorageEngine {
-    // Mutex over RwLock: see concurrency model above.
-    active_memtable: Arc<Mutex<MemTable>>,
-    // Arc<MemTable> inside the Option so the flush thread doesn't hold the lock during I/O.
-    immutable_memtable: Arc<Mutex<Option<Arc<MemTable>>>>,
+    // Written only when a family is created, so readers almost never contend for it.
+    column_families: ColumnFamilies,
+    // The family the methods without a `_cf` suffix use; also in `column_families`.
+    default_cf: Arc<ColumnFamilyData>,
     // Append-only, never read concurrently — Mutex is correct.
     wal: Arc<Mutex<Wal>>,
-    sstables: Arc<RwLock<Vec<Vec<SSTableReader>>>>,
     manifest: Arc<RwLock<Manifest>>,
-    memtable_capacity: usize,
+    // The sequence number the next write will get. Only advanced once that write is fully
+    // applied, so `next_seq_num - 1` is always the newest write a reader may see.
     next_seq_num: Arc<AtomicU64>,
+    // Shared with the flush thread: compaction must keep every version a snapshot can read.
+    snapshots: Arc<SnapshotList>,
     db_path: Arc<PathBuf>,
     block_cache: BlockCache,
-    // A Condvar stalls writers when the immutable slot is occupied (flush in progress).
-    // Without this, a second flush trigger while one is running would silently drop data.
-    // Writers block here instead of racing or returning an error.
-    flush_condvar: Arc<(Mutex<bool>, Condvar)>,
+    file_ids: Arc<FileIds>,
+    // Flush threads, joined on close.
+    background: Mutex<BackgroundJobs>,
+    // Set once `close` has run, so `Drop` does not shut down a second time.
+    closed: bool,
+    // Set as `close` starts, so background compactions stop early instead of holding it up.
+    shutting_down: Arc<AtomicBool>,
+    // Opened with `open_read_only` or as a secondary: no file is ever created, changed or
+    // deleted.
+    read_only: bool,
+    // Where a secondary instance is in following its primary; `None` for any other.
+    secondary: Option<Mutex<SecondaryState>>,
+    // The SSTables the open skipped as damaged.
+    quarantined_sstables: Vec<u64>,
+}
+
+// How `open_in_mode` opens the database.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OpenMode {
+    ReadWrite,
+    ReadOnly,
+    Secondary,
 }
 
 impl StorageEngine {
@@ -97,118 +159,648 @@ impl StorageEngine {
     /// flushed to an SSTable (e.g., data written between the last flush and a crash) are
     /// replayed into the MemTable. This is safe to do even after a clean shutdown because the
     /// WAL is rotated (the old file is GC'd) only after the corresponding SSTable is confirmed
-    /// written to disk.
+    /// written to disk. A record torn at the end of a WAL file by a crash is dropped; a
+    /// damaged record anywhere else fails the open with [`Error::Corruption`].
     ///
     /// The SSTable list is rebuilt from the MANIFEST, not by scanning the `sst/` directory.
     /// Scanning the directory would pick up partially-written files from interrupted flushes.
     /// The MANIFEST only records files that were fully written and renamed atomically.
-    pub fn open(path: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
-        let db_path = path.into();
-        std::fs::create_dir_all(&db_path)?;
-
-        let wal_dir = db_path.join("wal");
-        let sst_dir = db_path.join("sst");
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::open_with_options(path, Options::default())
+    }
 
-        std::fs::create_dir_all(&wal_dir)?;
-        std::fs::create_dir_all(&sst_dir)?;
+    /// Opens or creates the database at the given path with caller-supplied tuning.
+    ///
+    /// `options` is validated before any file is created, so an invalid configuration never
+    /// leaves a half-initialized directory behind. The options are not persisted: reopening
+    /// the same directory with different values is allowed and takes effect for new writes,
+    /// flushes and compactions.
+    ///
+    /// Column families created earlier are opened with `options` as well; use
+    /// [`open_with_column_families`](Self::open_with_column_families) to tune them separately.
+    pub fn open_with_options(path: impl Into<PathBuf>, options: Options) -> Result<Self, Error> {
+        Self::open_with_column_families(path, options, Vec::<(&str, Options)>::new())
+    }
 
-        let mut wal = Wal::new(wal_dir)?;
+    /// Opens or creates the database at the given path, with `options` for the default column
+    /// family and the database as a whole, and the given options for other column families.
+    ///
+    /// A listed family that does not exist yet is created. A family that exists but is not
+    /// listed is opened with `options`. Like `options`, the families' options are not
+    /// persisted, so a family that uses a [`MergeOperator`] has to be listed on every open.
+    pub fn open_with_column_families<N: AsRef<str>>(
+        path: impl Into<PathBuf>,
+        options: Options,
+        column_families: impl IntoIterator<Item = (N, Options)>,
+    ) -> Result<Self, Error> {
+        Self::open_in_mode(path, options, column_families, OpenMode::ReadWrite)
+    }
 
-        let memtable_capacity = MEMTABLE_CAPACITY_BYTES;
-        let mut memtable = MemTable::new(memtable_capacity, BLOOM_FILTER_FPR);
+    /// Opens the database at the given path for reading only, with default options.
+    ///
+    /// Nothing in the directory is created, changed or deleted, so this is safe to point at a
+    /// database another process has open — an analytics job inspecting a live database, say.
+    /// The SSTables are found through the MANIFEST, and whatever the WAL holds on top of them
+    /// is replayed into a MemTable, so reads see every write that had reached the WAL at the
+    /// time of the call, and nothing later. Nothing is ever flushed or compacted.
+    ///
+    /// Every write, and anything else that would change the files, fails with
+    /// [`Error::ReadOnly`]. Fails if there is no database at `path`.
+    ///
+    /// ```no_run
+    /// use lsmdb::{Error, StorageEngine};
+    ///
+    /// let engine = StorageEngine::open_read_only("/tmp/db")?;
+    /// let count = engine.scan::<&[u8], _>(..)?.try_fold(0, |n, entry| entry.map(|_| n + 1))?;
+    /// assert!(matches!(engine.put(b"key", b"value"), Err(Error::ReadOnly)));
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn open_read_only(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::open_read_only_with_column_families(
+            path,
+            Options::default(),
+            Vec::<(&str, Options)>::new(),
+        )
+    }
 
-        let mut max_seq = 0;
+    /// Like [`open_read_only`](Self::open_read_only), with options for the default column
+    /// family and the database as a whole, and for other column families as in
+    /// [`open_with_column_families`](Self::open_with_column_families). Reads only need the
+    /// options to find a [`MergeOperator`]. Listing a family that does not exist is an error,
+    /// since it cannot be created.
+    pub fn open_read_only_with_column_families<N: AsRef<str>>(
+        path: impl Into<PathBuf>,
+        options: Options,
+        column_families: impl IntoIterator<Item = (N, Options)>,
+    ) -> Result<Self, Error> {
+        Self::open_in_mode(path, options, column_families, OpenMode::ReadOnly)
+    }
 
-        if let Ok(records) = wal.recover() {
-            for record in records {
-                max_seq = max_seq.max(record.seq_num);
-                memtable.set(record.key, record.val);
+    fn open_in_mode<N: AsRef<str>>(
+        path: impl Into<PathBuf>,
+        options: Options,
+        column_families: impl IntoIterator<Item = (N, Options)>,
+        mode: OpenMode,
+    ) -> Result<Self, Error> {
+        options.validate()?;
+        let read_only = mode != OpenMode::ReadWrite;
+        let mut family_options = BTreeMap::new();
+        for (name, cf_options) in column_families {
+            let name = name.as_ref();
+            validate_column_family_name(name)?;
+            if name == DEFAULT_COLUMN_FAMILY_NAME {
+                return Err(Error::invalid(
+                    "The default column family is opened with the database's options",
+                ));
             }
+            cf_options.validate()?;
+            family_options.insert(name.to_string(), Arc::new(cf_options));
         }
 
+        let db_path = path.into();
+        let wal_dir = db_path.join("wal");
+        let sst_dir = db_path.join("sst");
         let manifest_path = db_path.join("MANIFEST");
-        let active_ssts = Manifest::recover(&manifest_path)?;
-        let manifest = Manifest::open(&manifest_path)?;
 
-        let mut sstables: Vec<Vec<SSTableReader>> = Vec::new();
+        // Every open creates the MANIFEST, so a directory without one holds no database.
+        if read_only && !manifest_path.exists() {
+            return Err(Error::invalid(format!("No database at {:?}", db_path)));
+        }
+        if !read_only {
+            std::fs::create_dir_all(&wal_dir)?;
+            std::fs::create_dir_all(&sst_dir)?;
+        }
 
-        for (level, sst_ids) in active_ssts.iter().enumerate() {
-            let mut level_readers = Vec::new();
-            for sst_id in sst_ids {
-                let path = sst_dir.join(format!("{}.sst", sst_id));
-                if path.exists() {
-                    level_readers.push(SSTableReader::new(path));
+        // The MANIFEST comes first: it says which families exist, and which of their writes
+        // the WAL still holds only because another family has not flushed yet.
+        let manifest_state = Manifest::recover(&manifest_path)?;
+        let (mut manifest, mut wal) = if read_only {
+            (Manifest::read_only(), Wal::open_read_only(&wal_dir))
+        } else {
+            (
+                Manifest::open(&manifest_path)?,
+                Wal::new(&wal_dir, options.wal_sync_on_write)?,
+            )
+        };
+        let options = Arc::new(options);
+
+        let mut families = BTreeMap::new();
+        let mut flushed_sequences = BTreeMap::new();
+        let mut sst_ids = Vec::new();
+        let mut quarantined_sstables = Vec::new();
+
+        for (&id, state) in &manifest_state.column_families {
+            let mut sstables: Vec<Vec<Arc<SSTableReader>>> = Vec::new();
+
+            for (level, level_ids) in state.levels.iter().enumerate() {
+                let mut level_readers = Vec::new();
+                for &sst_id in level_ids {
+                    let path = sst_dir.join(format!("{}.sst", sst_id));
+                    if !path.exists() {
+                        continue;
+                    }
+                    match SSTableReader::open(path) {
+                        Ok(reader) => level_readers.push(Arc::new(reader)),
+                        Err(Error::Corruption { .. })
+                            if options.corrupt_sstable_policy
+                                == CorruptSSTablePolicy::Quarantine =>
+                        {
+                            quarantined_sstables.push(sst_id);
+                            if !read_only {
+                                quarantine_sstable(&db_path, &mut manifest, id, level, sst_id)?;
+                            }
+                        }
+                        Err(e) => return Err(e),
+                    }
                 }
+                // L0 files can overlap in key range because each flush writes independent
+                // ranges, and so can the files compactions add to a level. Sorting
+                // newest-first — the order flushes and compactions insert in at runtime —
+                // ensures we always return the most recent value on a read without comparing
+                // timestamps inside individual entries.
+                level_readers.sort_by_key(|r| std::cmp::Reverse(r.id));
+                sstables.push(level_readers);
             }
-            // L0 files can overlap in key range because each flush writes independent ranges.
-            // Sorting newest-first ensures we always return the most recent value on a read
-            // without comparing timestamps inside individual entries.
-            if level == 0 {
-                level_readers.sort_by(|a, b| b.id.cmp(&a.id));
+            sst_ids.extend(state.levels.iter().flatten().copied());
+
+            let cf_options = match family_options.remove(&state.name) {
+                Some(cf_options) => cf_options,
+                None => Arc::clone(&options),
+            };
+            let family = ColumnFamilyData::new(
+                ColumnFamily::new(id, &state.name),
+                cf_options,
+                sstables,
+                wal.current_file_num(),
+            );
+            families.insert(id, family);
+            flushed_sequences.insert(id, state.flushed_sequence);
+        }
+
+        let mut max_seq = 0;
+
+        // A secondary replays the WAL as it catches up, below.
+        let records = match mode {
+            OpenMode::Secondary => Vec::new(),
+            _ => wal.recover()?,
+        };
+        for record in records {
+            max_seq = max_seq.max(record.seq_num);
+            let family = families.get_mut(&record.cf).ok_or_else(|| {
+                Error::corruption(
+                    &wal_dir,
+                    0,
+                    format!("WAL holds writes to unknown column family {}", record.cf),
+                )
+            })?;
+            // The WAL is shared, so it still holds the writes of families that flushed
+            // after it was last trimmed. Those are in SSTables already.
+            if record.seq_num <= flushed_sequences[&record.cf] {
+                continue;
             }
-            sstables.push(level_readers);
+            // Replayed writes may come from any of the old WAL files.
+            *family.active_log.get_mut() = 0;
+            let memtable = family
+                .active_memtable
+                .get_mut()
+                .map_err(|_| Error::Poisoned("MemTable"))?;
+            apply_record(memtable, record);
         }
 
-        // Always guarantee at least one slot for L0 so flush logic never has to bounds-check.
-        if sstables.is_empty() {
-            sstables.push(Vec::new());
+        // Flushed writes are no longer in the WAL, but their sequence numbers are still in the
+        // SSTables. Reusing them would make new writes sort as older than existing data.
+        max_seq = max_seq.max(manifest_state.last_sequence);
+
+        // A secondary keeps the options of families its primary has yet to create.
+        let mut secondary = None;
+        if mode == OpenMode::Secondary {
+            secondary = Some(Mutex::new(SecondaryState::new(
+                Arc::clone(&options),
+                std::mem::take(&mut family_options),
+            )));
+        }
+        for (name, cf_options) in family_options {
+            if read_only {
+                return Err(Error::invalid(format!(
+                    "Column family {:?} does not exist",
+                    name
+                )));
+            }
+            let id = families.keys().max().map_or(0, |id| id + 1);
+            manifest.log_edit(&VersionEdit::AddColumnFamily {
+                id,
+                name: name.clone(),
+            })?;
+            let family = ColumnFamilyData::new(
+                ColumnFamily::new(id, &name),
+                cf_options,
+                Vec::new(),
+                wal.current_file_num(),
+            );
+            families.insert(id, family);
         }
 
-        Ok(Self {
-            active_memtable: Arc::new(Mutex::new(memtable)),
-            immutable_memtable: Arc::new(Mutex::new(None)),
+        let families: BTreeMap<u32, Arc<ColumnFamilyData>> = families
+            .into_iter()
+            .map(|(id, family)| (id, Arc::new(family)))
+            .collect();
+        let default_cf = Arc::clone(&families[&DEFAULT_COLUMN_FAMILY_ID]);
+
+        let engine = Self {
+            column_families: Arc::new(RwLock::new(families)),
+            default_cf,
             wal: Arc::new(Mutex::new(wal)),
-            sstables: Arc::new(RwLock::new(sstables)),
             manifest: Arc::new(RwLock::new(manifest)),
-            memtable_capacity,
             next_seq_num: Arc::new(AtomicU64::new(max_seq + 1)),
+            snapshots: Arc::new(SnapshotList::default()),
             db_path: Arc::new(db_path),
+            // validate() guarantees a non-zero capacity.
             block_cache: Arc::new(RwLock::new(lru::LruCache::new(
-                std::num::NonZeroUsize::new(BLOCK_CACHE_CAPACITY).unwrap(),
+                std::num::NonZeroUsize::new(options.block_cache_capacity).unwrap(),
             ))),
-            flush_condvar: Arc::new((Mutex::new(false), Condvar::new())),
-        })
+            file_ids: Arc::new(FileIds::new(sst_ids)),
+            background: Mutex::new(BackgroundJobs::default()),
+            closed: false,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            read_only,
+            secondary,
+            quarantined_sstables,
+        };
+        if mode == OpenMode::Secondary {
+            engine.try_catch_up_with_primary()?;
+        }
+        Ok(engine)
+    }
+
+    /// The ids of the SSTables that opening the database skipped as damaged, under
+    /// [`CorruptSSTablePolicy::Quarantine`]. Unless the database was opened read-only or as a
+    /// secondary, each one now lies in the `lost` directory as `<id>.sst`.
+    pub fn quarantined_sstables(&self) -> &[u64] {
+        &self.quarantined_sstables
+    }
+
+    // Fails with `Error::ReadOnly` if the database was opened read-only.
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Creates a new column family named `name`, tuned with `options`, and returns its handle.
+    ///
+    /// `wal_sync_on_write` and `block_cache_capacity` belong to the database as a whole, so
+    /// their values in `options` are ignored. Fails if a family with that name already exists.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        options: Options,
+    ) -> Result<ColumnFamily, Error> {
+        self.check_writable()?;
+        validate_column_family_name(name)?;
+        options.validate()?;
+
+        // Taken before the families lock: the flush thread holds the WAL lock while it reads
+        // the families.
+        let log = self
+            .wal
+            .lock()
+            .map_err(|_| Error::Poisoned("WAL"))?
+            .current_file_num();
+
+        let mut families = self
+            .column_families
+            .write()
+            .map_err(|_| Error::Poisoned("Column families"))?;
+        if families.values().any(|f| f.handle.name() == name) {
+            return Err(Error::invalid(format!(
+                "Column family {:?} already exists",
+                name
+            )));
+        }
+        let id = families.keys().max().map_or(0, |id| id + 1);
+
+        self.manifest
+            .write()
+            .map_err(|_| Error::Poisoned("Manifest"))?
+            .log_edit(&VersionEdit::AddColumnFamily {
+                id,
+                name: name.to_string(),
+            })?;
+
+        let handle = ColumnFamily::new(id, name);
+        let family = ColumnFamilyData::new(handle.clone(), Arc::new(options), Vec::new(), log);
+        families.insert(id, Arc::new(family));
+        Ok(handle)
+    }
+
+    /// Returns the handle of the column family named `name`, or `None` if there is none.
+    /// [`DEFAULT_COLUMN_FAMILY_NAME`] names the default family.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamily> {
+        let families = self.column_families.read().ok()?;
+        families
+            .values()
+            .find(|f| f.handle.name() == name)
+            .map(|f| f.handle.clone())
+    }
+
+    // The data behind a handle. Fails for a handle of another engine.
+    fn family(&self, cf: &ColumnFamily) -> Result<Arc<ColumnFamilyData>, Error> {
+        let families = self
+            .column_families
+            .read()
+            .map_err(|_| Error::Poisoned("Column families"))?;
+        families
+            .get(&cf.id)
+            .cloned()
+            .ok_or_else(|| Error::invalid(format!("Unknown column family {:?}", cf.name())))
     }
 
     /// Inserts a key-value pair.
     ///
-    /// The WAL is written **before** the MemTable. If the process is killed between these two
-    /// steps, WAL replay at startup re-inserts the entry. Reversing the order would mean a crash
-    /// after the MemTable insert but before the WAL write loses a durably acknowledged write.
-    ///
-    /// `SeqCst` ordering on the sequence number is intentional: the sequence number establishes
-    /// a total order across all operations (puts, removes). A weaker ordering could allow two
-    /// threads to observe the same sequence number or observe insertions out of order during
-    /// WAL recovery.
-    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+    /// Equivalent to writing a [`WriteBatch`] holding a single put; see [`write`](Self::write)
+    /// for the durability and ordering guarantees.
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(batch)
+    }
+
+    /// Inserts a key-value pair into column family `cf`.
+    pub fn put_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
         &self,
+        cf: &ColumnFamily,
         key: K,
         value: V,
-    ) -> Result<(), anyhow::Error> {
-        let key = key.as_ref().to_vec();
-        let value = value.as_ref().to_vec();
-        let seq = self.next_seq_num.fetch_add(1, Ordering::SeqCst);
+    ) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put_cf(cf, key, value);
+        self.write(batch)
+    }
 
-        // WAL first — crash durability requires the log precede the in-memory change.
-        self.wal
-            .lock()
-            .map_err(|_| anyhow::anyhow!("WAL lock poisoned"))?
-            .add(seq, key.clone(), value.clone())?;
+    /// Inserts a key-value pair that expires `ttl` from now.
+    ///
+    /// The deadline is stored with the value, as a wall-clock timestamp. From then on `get`
+    /// and scans treat the key as deleted — snapshots included, since expiry is a matter of
+    /// time rather than of sequence — and compaction replaces the value with a tombstone,
+    /// reclaiming its space. That tombstone goes like any other, once it reaches the bottom
+    /// level with no snapshot predating it; there the value leaves no tombstone at all.
+    /// Writing the key again replaces the deadline along with the value.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+    /// engine.put_with_ttl(b"session:8f2c", b"user:42", Duration::from_secs(30 * 60))?;
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn put_with_ttl<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl(key, value, ttl);
+        self.write(batch)
+    }
 
-        let needs_flush = {
-            let mut memtable = self
-                .active_memtable
-                .lock()
-                .map_err(|_| anyhow::anyhow!("MemTable lock poisoned"))?;
-            memtable.set(key, value);
-            memtable.needs_flush()
+    /// Inserts a key-value pair that expires `ttl` from now into column family `cf`.
+    pub fn put_with_ttl_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl_cf(cf, key, value, ttl);
+        self.write(batch)
+    }
+
+    /// Applies every operation in `batch` atomically.
+    ///
+    /// The batch is logged as ONE WAL record, so after a crash recovery replays either all of
+    /// it or none of it — even when it spans several column families. It is applied with the
+    /// MemTable of every family it writes to locked, so readers never observe half of a batch
+    /// either. The operations get a contiguous range of
+    /// sequence numbers, in batch order.
+    ///
+    /// The WAL is written **before** the MemTable. If the process is killed between these two
+    /// steps, WAL replay at startup re-inserts the entries. Reversing the order would mean a
+    /// crash after the MemTable insert but before the WAL write loses a durably acknowledged
+    /// write.
+    ///
+    /// The WAL mutex doubles as the engine's write lock: sequence allocation, the WAL append
+    /// and the MemTable insert all happen while it is held. Allocating sequence numbers
+    /// outside it would let two writers log in one order and apply in the other, so the
+    /// MemTable and a later WAL replay could disagree on which write to a key came last.
+    /// The batch's sequence numbers are published only after the MemTable insert, so a reader
+    /// (or snapshot) pinned to them is guaranteed to find the whole batch.
+    pub fn write(&self, batch: WriteBatch) -> Result<(), Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.write_with_check(batch, || Ok(true)).map(|_| ())
+    }
+
+    // `write`, with `check` run under the write lock before anything is logged: nothing can be
+    // written between the check passing and the batch being applied. If `check` returns false
+    // or an error, nothing is written and that is returned. An empty batch only runs the check.
+    fn write_with_check(
+        &self,
+        batch: WriteBatch,
+        check: impl FnOnce() -> Result<bool, Error>,
+    ) -> Result<bool, Error> {
+        self.check_writable()?;
+        // Every family the batch writes to, resolved once so the families lock is not held
+        // while the WAL is written.
+        let mut families = BTreeMap::new();
+        {
+            let column_families = self
+                .column_families
+                .read()
+                .map_err(|_| Error::Poisoned("Column families"))?;
+            for (cf, _) in &batch.ops {
+                if !families.contains_key(cf) {
+                    let family = column_families
+                        .get(cf)
+                        .ok_or_else(|| Error::invalid(format!("Unknown column family {}", cf)))?;
+                    families.insert(*cf, Arc::clone(family));
+                }
+            }
+        }
+
+        // Checked before anything is logged: an operand nothing can fold would be stuck in
+        // the WAL and replayed on every open.
+        if batch.ops.iter().any(|(cf, op)| {
+            matches!(op, BatchOp::Merge { .. }) && families[cf].options.merge_operator.is_none()
+        }) {
+            return Err(Error::invalid(
+                "Cannot merge: no merge operator is configured",
+            ));
+        }
+        if batch
+            .ops
+            .iter()
+            .any(|(_, op)| matches!(op, BatchOp::DeleteRange { start, end } if start > end))
+        {
+            return Err(Error::invalid(
+                "Invalid range deletion: start is greater than end",
+            ));
+        }
+        if let Some((_, op)) = batch
+            .ops
+            .iter()
+            .find(|(_, op)| op.key().len() > MAX_KEY_SIZE)
+        {
+            return Err(Error::invalid(format!(
+                "Key of {} bytes is longer than the maximum of {}",
+                op.key().len(),
+                MAX_KEY_SIZE
+            )));
+        }
+
+        let needs_flush: Vec<Arc<ColumnFamilyData>> = {
+            let mut wal = self.wal.lock().map_err(|_| Error::Poisoned("WAL"))?;
+            if !check()? {
+                return Ok(false);
+            }
+
+            let first_seq = self.next_seq_num.load(Ordering::SeqCst);
+            let batch_len = batch.len() as u64;
+
+            let records: Vec<Record> = batch
+                .ops
+                .into_iter()
+                .zip(first_seq..)
+                .map(|((cf, op), seq_num)| match op {
+                    BatchOp::Put { key, value } => Record {
+                        opcode: Opcode::Put,
+                        cf,
+                        seq_num,
+                        key,
+                        val: value,
+                    },
+                    BatchOp::PutWithTtl {
+                        key,
+                        value,
+                        expires_at,
+                    } => Record {
+                        opcode: Opcode::PutWithTtl,
+                        cf,
+                        seq_num,
+                        key,
+                        val: ttl::encode(expires_at, &value),
+                    },
+                    BatchOp::Delete { key } => Record {
+                        opcode: Opcode::Delete,
+                        cf,
+                        seq_num,
+                        key,
+                        val: vec![],
+                    },
+                    BatchOp::Merge { key, operand } => Record {
+                        opcode: Opcode::Merge,
+                        cf,
+                        seq_num,
+                        key,
+                        val: operand,
+                    },
+                    BatchOp::DeleteRange { start, end } => Record {
+                        opcode: Opcode::DeleteRange,
+                        cf,
+                        seq_num,
+                        key: start,
+                        val: end,
+                    },
+                })
+                .collect();
+
+            // WAL first — crash durability requires the log precede the in-memory change. A
+            // lone operation keeps the plain single-record format.
+            match records.as_slice() {
+                [] => {}
+                [record] => wal.append(record)?,
+                _ => wal.add_batch(&records)?,
+            }
+
+            // Locked in id order, the one order every writer uses.
+            let mut memtables = BTreeMap::new();
+            for (cf, family) in &families {
+                let memtable = family
+                    .active_memtable
+                    .lock()
+                    .map_err(|_| Error::Poisoned("MemTable"))?;
+                memtables.insert(*cf, memtable);
+            }
+            for record in records {
+                let memtable = memtables
+                    .get_mut(&record.cf)
+                    .expect("every family in the batch is locked");
+                apply_record(memtable, record);
+            }
+            self.next_seq_num
+                .store(first_seq + batch_len, Ordering::SeqCst);
+            memtables
+                .iter()
+                .filter(|(_, memtable)| memtable.needs_flush())
+                .map(|(cf, _)| Arc::clone(&families[cf]))
+                .collect()
         };
 
-        if needs_flush {
-            self.trigger_background_flush()?;
+        for family in needs_flush {
+            self.trigger_background_flush(&family)?;
         }
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Atomically replaces the value of `key` with `new` if its current value is `expected`,
+    /// and returns whether it did. `None` means "absent" on both sides: `expected: None` only
+    /// writes if the key does not exist, and `new: None` deletes it.
+    ///
+    /// The comparison and the write both happen under the engine's write lock, so no other
+    /// write can land between them. The comparison sees exactly what [`get`](Self::get) would.
+    ///
+    /// ```no_run
+    /// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+    /// // Take the lease only if nobody holds it.
+    /// if engine.compare_and_swap(b"lease:scheduler", None, Some(b"node-7"))? {
+    ///     // ... we are the leader; release the lease only if it is still ours.
+    ///     engine.compare_and_swap(b"lease:scheduler", Some(b"node-7"), None)?;
+    /// }
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn compare_and_swap<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, Error> {
+        self.compare_and_swap_cf(&self.default_cf.handle, key, expected, new)
+    }
+
+    /// Like [`compare_and_swap`](Self::compare_and_swap), on a key in column family `cf`.
+    pub fn compare_and_swap_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        key: K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, Error> {
+        let family = self.family(cf)?;
+        let key = key.as_ref();
+        let mut batch = WriteBatch::new();
+        match new {
+            Some(value) => batch.put_cf(cf, key, value),
+            None => batch.delete_cf(cf, key),
+        };
+        self.write_with_check(batch, || {
+            // Nothing can be written while the write lock is held, so this is the latest value.
+            let current =
+                self.get_at_sequence(&family, key, self.last_sequence(), &ReadOptions::default())?;
+            Ok(current.as_deref() == expected)
+        })
     }
 
     /// Retrieves the most recent value for a key, or `None` if absent or deleted.
@@ -218,75 +810,669 @@ impl StorageEngine {
     /// append-only, older versions of a key coexist with newer ones on disk; the search order
     /// resolves which version wins without a merge step on every read.
     ///
-    /// An empty value (`val.is_empty()`) means the key was deleted via a tombstone. We return
-    /// `None` rather than the empty bytes so callers see a clean "not found" — they should not
-    /// need to know the deletion mechanism.
-    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, anyhow::Error> {
-        let key = key.as_ref();
+    /// A tombstone (a `ValueType::Deletion` entry) means the key was deleted. We return `None`
+    /// rather than exposing the tombstone so callers see a clean "not found" — they should not
+    /// need to know the deletion mechanism. A key stored with an empty value is present, and
+    /// comes back as `Some(vec![])`. A value written with [`put_with_ttl`](Self::put_with_ttl)
+    /// reads as deleted once it has expired.
+    ///
+    /// Merge operands found on the way down are collected until a value or tombstone is
+    /// reached (or the layers run out), then folded onto it by the configured
+    /// [`MergeOperator`].
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, Error> {
+        self.get_opt(&ReadOptions::default(), key)
+    }
+
+    /// Retrieves the most recent value for a key in column family `cf`.
+    pub fn get_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.get_cf_opt(&ReadOptions::default(), cf, key)
+    }
+
+    /// Like [`get`](Self::get), with [`ReadOptions`] for this read.
+    ///
+    /// ```no_run
+    /// use lsmdb::ReadOptions;
+    ///
+    /// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+    /// let unchecked = ReadOptions {
+    ///     verify_checksums: false,
+    /// };
+    /// let value = engine.get_opt(&unchecked, b"user:1")?;
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn get_opt<K: AsRef<[u8]>>(
+        &self,
+        options: &ReadOptions,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.get_at_sequence(
+            &self.default_cf,
+            key.as_ref(),
+            self.last_sequence(),
+            options,
+        )
+    }
+
+    /// Like [`get_cf`](Self::get_cf), with [`ReadOptions`] for this read.
+    pub fn get_cf_opt<K: AsRef<[u8]>>(
+        &self,
+        options: &ReadOptions,
+        cf: &ColumnFamily,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.get_at_sequence(
+            &*self.family(cf)?,
+            key.as_ref(),
+            self.last_sequence(),
+            options,
+        )
+    }
+
+    /// Retrieves the value `key` had when `snapshot` was taken, or `None` if it was absent or
+    /// deleted at that point.
+    pub fn get_at<K: AsRef<[u8]>>(
+        &self,
+        snapshot: &Snapshot,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.get_at_sequence(
+            &self.default_cf,
+            key.as_ref(),
+            snapshot.sequence(),
+            &ReadOptions::default(),
+        )
+    }
+
+    /// Retrieves the value `key` had in column family `cf` when `snapshot` was taken.
+    pub fn get_at_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        snapshot: &Snapshot,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.get_at_sequence(
+            &*self.family(cf)?,
+            key.as_ref(),
+            snapshot.sequence(),
+            &ReadOptions::default(),
+        )
+    }
+
+    /// Retrieves the most recent value of each of `keys`, in the order given; `None` where a
+    /// key is absent or deleted. Every key is read at the same sequence number, so the values
+    /// are consistent with one another.
+    ///
+    /// Cheaper than calling [`get`](Self::get) once per key: each MemTable lock and the
+    /// SSTable list lock are taken once for the whole batch, and keys are looked up in sorted
+    /// order, so keys that land in the same data block share one read — and one decompression
+    /// — of that block.
+    ///
+    /// ```no_run
+    /// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+    /// let values = engine.multi_get(&[b"user:1", b"user:2", b"user:3"])?;
+    /// assert_eq!(values.len(), 3);
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        self.multi_get_opt(&ReadOptions::default(), keys)
+    }
+
+    /// Like [`multi_get`](Self::multi_get), for keys of column family `cf`.
+    pub fn multi_get_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        keys: &[K],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        self.multi_get_cf_opt(&ReadOptions::default(), cf, keys)
+    }
+
+    /// Like [`multi_get`](Self::multi_get), with [`ReadOptions`] for this read.
+    pub fn multi_get_opt<K: AsRef<[u8]>>(
+        &self,
+        options: &ReadOptions,
+        keys: &[K],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        self.multi_get_at_sequence(&self.default_cf, keys, self.last_sequence(), options)
+    }
+
+    /// Like [`multi_get_cf`](Self::multi_get_cf), with [`ReadOptions`] for this read.
+    pub fn multi_get_cf_opt<K: AsRef<[u8]>>(
+        &self,
+        options: &ReadOptions,
+        cf: &ColumnFamily,
+        keys: &[K],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        self.multi_get_at_sequence(&*self.family(cf)?, keys, self.last_sequence(), options)
+    }
+
+    /// Takes a [`Snapshot`] of the current state of the database.
+    ///
+    /// Taking a snapshot is cheap — it records a sequence number — but see [`Snapshot`] on
+    /// what keeping one alive costs.
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshots.acquire(self.last_sequence())
+    }
+
+    /// Starts an optimistic [`Transaction`].
+    ///
+    /// The transaction reads the database as of this moment, plus its own writes, and takes
+    /// no locks: conflicts with other writers are only detected when it commits.
+    pub fn begin(&self) -> Transaction<'_> {
+        Transaction::new(self, self.snapshot())
+    }
+
+    // Commits a transaction's writes, unless one of `keys` was written by anyone else after
+    // sequence number `seq`.
+    fn commit_transaction(
+        &self,
+        seq: u64,
+        keys: &BTreeSet<(u32, Vec<u8>)>,
+        batch: WriteBatch,
+    ) -> Result<(), Error> {
+        self.write_with_check(batch, || {
+            let families = self
+                .column_families
+                .read()
+                .map_err(|_| Error::Poisoned("Column families"))?;
+            for (cf, key) in keys {
+                let family = families
+                    .get(cf)
+                    .ok_or_else(|| Error::invalid(format!("Unknown column family {}", cf)))?;
+                if self.modified_since(family, key, seq)? {
+                    return Err(TransactionConflict::new(key.clone()).into());
+                }
+            }
+            Ok(true)
+        })
+        .map(|_| ())
+    }
 
+    // True if `key` was written after sequence number `seq`. Layers are ordered newest first,
+    // so the first layer with a write to the key holds its newest one; a MemTable holding
+    // every write since `seq` rules out the layers below it.
+    fn modified_since(
+        &self,
+        family: &ColumnFamilyData,
+        key: &[u8],
+        seq: u64,
+    ) -> Result<bool, Error> {
         {
-            let memtable = self
+            let memtable = family
                 .active_memtable
                 .lock()
-                .map_err(|_| anyhow::anyhow!("MemTable lock poisoned"))?;
-            if let Some(val) = memtable.get(key) {
-                if val.is_empty() {
-                    return Ok(None);
+                .map_err(|_| Error::Poisoned("MemTable"))?;
+            if let Some(latest) = memtable.latest_sequence(key) {
+                return Ok(latest > seq);
+            }
+            if memtable.covers_writes_after(seq) {
+                return Ok(false);
+            }
+        }
+
+        {
+            let imm = family
+                .immutable_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
+            if let Some(imm_memtable) = imm.as_ref() {
+                if let Some(latest) = imm_memtable.latest_sequence(key) {
+                    return Ok(latest > seq);
                 }
-                return Ok(Some(val.clone()));
+                if imm_memtable.covers_writes_after(seq) {
+                    return Ok(false);
+                }
+            }
+        }
+
+        let sstables = family
+            .sstables
+            .read()
+            .map_err(|_| Error::Poisoned("SSTables"))?;
+        for reader in sstables.iter().flatten() {
+            if reader.range_tombstones.max_covering_seq(key, MAX_SEQUENCE) > seq {
+                return Ok(true);
+            }
+            if let Some((_, latest, _)) =
+                reader.get(key, MAX_SEQUENCE, Some(&self.block_cache), true)?
+            {
+                return Ok(latest > seq);
+            }
+        }
+        Ok(false)
+    }
+
+    // The newest write that is fully applied, and therefore safe to read at.
+    fn last_sequence(&self) -> u64 {
+        self.next_seq_num.load(Ordering::SeqCst) - 1
+    }
+
+    // Every layer holds only versions older than those in the layers searched before it, so
+    // the first layer with a value or tombstone for `key` at or before `seq` settles the read.
+    // Merge operands in the layers before it — or beside it, at newer sequence numbers — are
+    // folded onto it. A range tombstone settles the read at the first version older than it,
+    // in its own layer or any below.
+    fn get_at_sequence(
+        &self,
+        family: &ColumnFamilyData,
+        key: &[u8],
+        seq: u64,
+        options: &ReadOptions,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let mut context = MergeContext::new(seq, ttl::now());
+        let operator = family.options.merge_operator.as_deref();
+
+        {
+            let memtable = family
+                .active_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("MemTable"))?;
+            if context.search(memtable.max_covering_tombstone_seq(key, seq), |seq| {
+                memtable
+                    .get(key, seq)
+                    .map(|(value_type, seq, val)| (value_type, seq, val.clone()))
+            }) {
+                return Ok(context.finish(operator, key));
             }
         }
 
         {
-            let imm = self
+            let imm = family
                 .immutable_memtable
                 .lock()
-                .map_err(|_| anyhow::anyhow!("Immutable MemTable lock poisoned"))?;
+                .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
 
             if let Some(imm_memtable) = imm.as_ref()
-                && let Some(val) = imm_memtable.get(key)
+                && context.search(imm_memtable.max_covering_tombstone_seq(key, seq), |seq| {
+                    imm_memtable
+                        .get(key, seq)
+                        .map(|(value_type, seq, val)| (value_type, seq, val.clone()))
+                })
             {
-                if val.is_empty() {
-                    return Ok(None);
-                }
-                return Ok(Some(val.clone()));
+                return Ok(context.finish(operator, key));
             }
         }
 
         {
-            let sstables = self
+            let sstables = family
                 .sstables
                 .read()
-                .map_err(|_| anyhow::anyhow!("SSTables read lock poisoned"))?;
+                .map_err(|_| Error::Poisoned("SSTables"))?;
 
             for level in sstables.iter() {
                 for reader in level.iter() {
-                    if let Some(val) = reader.get(key, Some(&self.block_cache)) {
-                        if val.is_empty() {
-                            return Ok(None);
-                        }
-                        return Ok(Some(val));
+                    let range_deletion_seq = reader.range_tombstones.max_covering_seq(key, seq);
+                    if context.try_search(range_deletion_seq, |seq| {
+                        reader.get(key, seq, Some(&self.block_cache), options.verify_checksums)
+                    })? {
+                        return Ok(context.finish(operator, key));
                     }
                 }
             }
         }
 
-        Ok(None)
+        Ok(context.finish(operator, key))
+    }
+
+    // `get_at_sequence` for a batch of keys, walking the layers once for all of them. A key
+    // settled in a newer layer is left out of the lookups in older ones.
+    fn multi_get_at_sequence<K: AsRef<[u8]>>(
+        &self,
+        family: &ColumnFamilyData,
+        keys: &[K],
+        seq: u64,
+        options: &ReadOptions,
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        // Sorted and deduplicated, so a key asked for twice is read once and SSTable lookups
+        // move forward through each table.
+        let mut sorted: Vec<&[u8]> = keys.iter().map(AsRef::as_ref).collect();
+        sorted.sort_unstable();
+        sorted.dedup();
+
+        let now = ttl::now();
+        let mut contexts: Vec<MergeContext> =
+            sorted.iter().map(|_| MergeContext::new(seq, now)).collect();
+        let mut settled = vec![false; sorted.len()];
+        let operator = family.options.merge_operator.as_deref();
+
+        {
+            let memtable = family
+                .active_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("MemTable"))?;
+            for (i, key) in sorted.iter().enumerate() {
+                settled[i] =
+                    contexts[i].search(memtable.max_covering_tombstone_seq(key, seq), |seq| {
+                        memtable
+                            .get(key, seq)
+                            .map(|(value_type, seq, val)| (value_type, seq, val.clone()))
+                    });
+            }
+        }
+
+        {
+            let imm = family
+                .immutable_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
+
+            if let Some(imm_memtable) = imm.as_ref() {
+                for (i, key) in sorted.iter().enumerate() {
+                    if settled[i] {
+                        continue;
+                    }
+                    settled[i] = contexts[i].search(
+                        imm_memtable.max_covering_tombstone_seq(key, seq),
+                        |seq| {
+                            imm_memtable
+                                .get(key, seq)
+                                .map(|(value_type, seq, val)| (value_type, seq, val.clone()))
+                        },
+                    );
+                }
+            }
+        }
+
+        {
+            let sstables = family
+                .sstables
+                .read()
+                .map_err(|_| Error::Poisoned("SSTables"))?;
+
+            for reader in sstables.iter().flatten() {
+                let pending: Vec<usize> = (0..sorted.len()).filter(|&i| !settled[i]).collect();
+                if pending.is_empty() {
+                    break;
+                }
+                let lookups: Vec<(&[u8], u64)> = pending
+                    .iter()
+                    .map(|&i| (sorted[i], contexts[i].sequence()))
+                    .collect();
+                let found =
+                    reader.get_many(&lookups, Some(&self.block_cache), options.verify_checksums)?;
+
+                for (i, found) in pending.into_iter().zip(found) {
+                    let key = sorted[i];
+                    let range_deletion_seq = reader.range_tombstones.max_covering_seq(key, seq);
+                    // The batched lookup answers the first probe; a merge operand sends the
+                    // search back into this table for the version beneath it.
+                    let mut found = Some(found);
+                    settled[i] =
+                        contexts[i].try_search(range_deletion_seq, |seq| match found.take() {
+                            Some(found) => Ok(found),
+                            None => reader.get(
+                                key,
+                                seq,
+                                Some(&self.block_cache),
+                                options.verify_checksums,
+                            ),
+                        })?;
+                }
+            }
+        }
+
+        let values: BTreeMap<&[u8], Option<Vec<u8>>> = sorted
+            .iter()
+            .zip(contexts)
+            .map(|(&key, context)| (key, context.finish(operator, key)))
+            .collect();
+        Ok(keys
+            .iter()
+            .map(|key| values[key.as_ref()].clone())
+            .collect())
+    }
+
+    /// Returns an ordered iterator over every live key in `range`.
+    ///
+    /// All layers — active MemTable, immutable MemTable and every SSTable on every level — are
+    /// merged so each key appears once with its newest value, and deleted keys are hidden.
+    /// The iterator walks the range in ascending order by default and can also step backwards
+    /// or reposition itself; see [`EngineIterator`].
+    ///
+    /// The active MemTable's entries in range are copied out while its lock is held, because
+    /// the lock guard cannot outlive this call. The immutable MemTable and the SSTables are
+    /// read lazily in place: each SSTable only decompresses the blocks the iterator reaches.
+    ///
+    /// The iterator reads at the sequence number current when it is created, so it is not
+    /// disturbed by writes made while it is in use.
+    ///
+    /// ```no_run
+    /// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+    /// for entry in engine.scan(b"user:100".as_slice()..b"user:200".as_slice())? {
+    ///     let (key, value) = entry?;
+    ///     println!("{:?} = {:?}", key, value);
+    /// }
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn scan<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> Result<EngineIterator, Error> {
+        self.scan_opt(&ReadOptions::default(), range)
+    }
+
+    /// Like [`scan`](Self::scan), over the keys of column family `cf`.
+    pub fn scan_cf<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        cf: &ColumnFamily,
+        range: R,
+    ) -> Result<EngineIterator, Error> {
+        self.scan_cf_opt(&ReadOptions::default(), cf, range)
+    }
+
+    /// Like [`scan`](Self::scan), with [`ReadOptions`] for every block the iterator reads.
+    pub fn scan_opt<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        options: &ReadOptions,
+        range: R,
+    ) -> Result<EngineIterator, Error> {
+        self.scan_at_sequence(&self.default_cf, range, self.last_sequence(), options)
+    }
+
+    /// Like [`scan_cf`](Self::scan_cf), with [`ReadOptions`] for every block the iterator
+    /// reads.
+    pub fn scan_cf_opt<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        options: &ReadOptions,
+        cf: &ColumnFamily,
+        range: R,
+    ) -> Result<EngineIterator, Error> {
+        self.scan_at_sequence(&*self.family(cf)?, range, self.last_sequence(), options)
+    }
+
+    /// Like [`scan`](Self::scan), but reads the database as it was when `snapshot` was taken.
+    pub fn scan_at<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        snapshot: &Snapshot,
+        range: R,
+    ) -> Result<EngineIterator, Error> {
+        self.scan_at_sequence(
+            &self.default_cf,
+            range,
+            snapshot.sequence(),
+            &ReadOptions::default(),
+        )
+    }
+
+    /// Like [`scan_cf`](Self::scan_cf), but reads the family as it was when `snapshot` was
+    /// taken.
+    pub fn scan_at_cf<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        cf: &ColumnFamily,
+        snapshot: &Snapshot,
+        range: R,
+    ) -> Result<EngineIterator, Error> {
+        self.scan_at_sequence(
+            &*self.family(cf)?,
+            range,
+            snapshot.sequence(),
+            &ReadOptions::default(),
+        )
+    }
+
+    // An iterator does not need to register itself like a `Snapshot` does: it holds on to the
+    // MemTables and SSTables it was built from, and compaction never rewrites those in place.
+    fn scan_at_sequence<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        family: &ColumnFamilyData,
+        range: R,
+        seq: u64,
+        options: &ReadOptions,
+    ) -> Result<EngineIterator, Error> {
+        let to_owned = |b: Bound<&K>| match b {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let start = to_owned(range.start_bound());
+        let end = to_owned(range.end_bound());
+
+        let seek_key: Vec<u8> = match &start {
+            Bound::Included(k) | Bound::Excluded(k) => k.clone(),
+            Bound::Unbounded => Vec::new(),
+        };
+
+        let mut children: Vec<Box<dyn InternalIterator>> = Vec::new();
+        // Range tombstones from every layer: one in a newer layer deletes keys in older ones.
+        let mut range_tombstones = Vec::new();
+
+        {
+            let memtable = family
+                .active_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("MemTable"))?;
+
+            // Stopping at the end bound matters: without it a narrow scan would clone the
+            // entire MemTable tail.
+            let entries = memtable
+                .scan_from(&seek_key)
+                .take_while(|(k, _)| {
+                    let k = internal_key::user_key(k);
+                    match &end {
+                        Bound::Included(e) => k <= e.as_slice(),
+                        Bound::Excluded(e) => k < e.as_slice(),
+                        Bound::Unbounded => true,
+                    }
+                })
+                .map(|(k, v)| (k.to_vec(), v.clone()))
+                .collect();
+            children.push(Box::new(VecIterator::new(entries)));
+            range_tombstones.extend_from_slice(memtable.range_tombstones());
+        }
+
+        {
+            let imm = family
+                .immutable_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
+            if let Some(imm_memtable) = imm.as_ref() {
+                children.push(Box::new(MemTableIterator::new(Arc::clone(imm_memtable))));
+                range_tombstones.extend_from_slice(imm_memtable.range_tombstones());
+            }
+        }
+
+        {
+            let sstables = family
+                .sstables
+                .read()
+                .map_err(|_| Error::Poisoned("SSTables"))?;
+
+            // Same newest-first order as `get`, so the merge resolves duplicates identically.
+            for level in sstables.iter() {
+                for reader in level.iter() {
+                    children.push(Box::new(SSTableIterator::new(
+                        Arc::clone(reader),
+                        options.verify_checksums,
+                    )));
+                    range_tombstones.extend_from_slice(reader.range_tombstones.tombstones());
+                }
+            }
+        }
+
+        Ok(EngineIterator::new(
+            children,
+            start,
+            end,
+            seq,
+            RangeTombstones::new(range_tombstones),
+            family.options.merge_operator.clone(),
+        ))
+    }
+
+    /// Returns an iterator over every live key in the database. Shorthand for `scan(..)`.
+    ///
+    /// Combined with [`EngineIterator::seek_to_last`] and [`EngineIterator::prev`] this reads
+    /// the largest keys first — the "latest N entries" query on time-ordered keys.
+    pub fn iter(&self) -> Result<EngineIterator, Error> {
+        self.scan::<&[u8], _>(..)
+    }
+
+    /// Returns an ordered iterator over every live key that starts with `prefix`.
+    ///
+    /// This is a range scan over `[prefix, successor(prefix))`, where the successor is the
+    /// smallest key greater than every key with that prefix (e.g. `user:42:` → `user:42;`).
+    /// Expressing it as a range means each SSTable seeks straight to the first candidate data
+    /// block through its index, and the merge stops at the first key past the prefix instead of
+    /// reading the rest of every table.
+    pub fn scan_prefix<K: AsRef<[u8]>>(&self, prefix: K) -> Result<EngineIterator, Error> {
+        let prefix = prefix.as_ref();
+        self.scan((Bound::Included(prefix.to_vec()), prefix_upper_bound(prefix)))
     }
 
     /// Updates a key. Identical to `put` — the LSM-Tree model has no true in-place update.
     ///
     /// The newest entry always wins on a read, so writing a new version of the key is the
     /// correct way to update it. The old version is removed transparently during compaction.
-    pub fn update<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+    pub fn update<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<(), Error> {
+        self.put(key, value)
+    }
+
+    /// Merges `operand` into the value of `key` using the configured
+    /// [`MergeOperator`](Options::merge_operator), without reading the key first.
+    ///
+    /// The operand is logged and stored as its own version of the key; `get` and scans fold
+    /// the operands onto the value beneath them when they read the key, and compaction folds
+    /// them for good. Fails if no merge operator is configured.
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use lsmdb::{MergeOperator, Options, StorageEngine};
+    /// # struct Counter;
+    /// # impl MergeOperator for Counter {
+    /// #     fn name(&self) -> &str { "counter" }
+    /// #     fn full_merge(&self, _: &[u8], _: Option<&[u8]>, _: &[&[u8]]) -> Vec<u8> { vec![] }
+    /// # }
+    /// let options = Options {
+    ///     merge_operator: Some(Arc::new(Counter)),
+    ///     ..Options::default()
+    /// };
+    /// let engine = StorageEngine::open_with_options("/tmp/db", options)?;
+    /// engine.merge(b"page_views", 1u64.to_le_bytes())?;
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn merge<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, operand: V) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.merge(key, operand);
+        self.write(batch)
+    }
+
+    /// Merges `operand` into the value of `key` in column family `cf`, using the family's
+    /// merge operator.
+    pub fn merge_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
         &self,
+        cf: &ColumnFamily,
         key: K,
-        value: V,
-    ) -> Result<(), anyhow::Error> {
-        self.put(key, value)
+        operand: V,
+    ) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.merge_cf(cf, key, operand);
+        self.write(batch)
     }
 
-    /// Marks a key as deleted by writing a tombstone (empty value).
+    /// Marks a key as deleted by writing a tombstone.
     ///
     /// LSM-Trees cannot physically remove a key from an immutable SSTable. Instead, a tombstone
     /// entry shadows all older versions of the key during reads and compaction. The actual disk
@@ -295,59 +1481,249 @@ impl StorageEngine {
     ///
     /// The tombstone must be WAL-logged for the same reason as `put`: a crash between writing
     /// the tombstone to the MemTable and logging it would resurrect the deleted key on recovery.
-    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<(), anyhow::Error> {
-        let key = key.as_ref().to_vec();
-        let seq = self.next_seq_num.fetch_add(1, Ordering::SeqCst);
-        let tombstone_val: Vec<u8> = vec![];
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(batch)
+    }
 
-        self.wal
-            .lock()
-            .map_err(|_| anyhow::anyhow!("WAL lock poisoned"))?
-            .remove(seq, key.clone())?;
+    /// Marks a key in column family `cf` as deleted by writing a tombstone.
+    pub fn remove_cf<K: AsRef<[u8]>>(&self, cf: &ColumnFamily, key: K) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete_cf(cf, key);
+        self.write(batch)
+    }
 
-        let needs_flush = {
-            let mut memtable = self
-                .active_memtable
-                .lock()
-                .map_err(|_| anyhow::anyhow!("MemTable lock poisoned"))?;
-            memtable.set(key, tombstone_val);
-            memtable.needs_flush()
-        };
+    /// Deletes every key in `start..end` (end exclusive) with a single range tombstone.
+    ///
+    /// The cost does not depend on how many keys the range holds: one WAL record and one
+    /// MemTable entry, instead of a point tombstone per key. The tombstone hides every older
+    /// version in the range from `get` and scans, and compaction drops the data it covers once
+    /// no snapshot can still see it. The tombstone itself goes once compaction carries it below
+    /// every older table and no snapshot predates it. Keys written to the range afterwards are
+    /// unaffected.
+    ///
+    /// ```no_run
+    /// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+    /// // Drops every key of tenant 42, e.g. `tenant:42:users:7`.
+    /// engine.delete_range(b"tenant:42:", b"tenant:42;")?;
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn delete_range<K: AsRef<[u8]>>(&self, start: K, end: K) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(start, end);
+        self.write(batch)
+    }
 
-        if needs_flush {
-            self.trigger_background_flush()?;
+    /// Deletes every key in `start..end` (end exclusive) in column family `cf`.
+    pub fn delete_range_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        start: K,
+        end: K,
+    ) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range_cf(cf, start, end);
+        self.write(batch)
+    }
+
+    /// Flushes the MemTable of every column family to an SSTable, as if it had filled up.
+    ///
+    /// With `wait`, returns once the new tables — and the compactions they set off — are on
+    /// disk, so the WAL no longer holds anything a restart would replay: a good moment to copy
+    /// the database directory for a backup. It also returns the error of any background flush
+    /// that failed, this one or an earlier one. Without `wait` the flushes run in the
+    /// background, like the ones a full MemTable starts.
+    ///
+    /// Either way, a family whose previous flush is still running is waited for first; one
+    /// whose MemTable is empty is left alone.
+    pub fn flush(&self, wait: bool) -> Result<(), Error> {
+        self.check_writable()?;
+        let families: Vec<_> = self
+            .column_families
+            .read()
+            .map_err(|_| Error::Poisoned("Column families"))?
+            .values()
+            .cloned()
+            .collect();
+        for family in &families {
+            self.flush_family(family)?;
+        }
+        if wait {
+            self.join_background_jobs()?;
         }
+        Ok(())
+    }
 
+    /// Like [`flush`](Self::flush), for column family `cf` alone.
+    pub fn flush_cf(&self, cf: &ColumnFamily, wait: bool) -> Result<(), Error> {
+        self.check_writable()?;
+        self.flush_family(&self.family(cf)?)?;
+        if wait {
+            self.join_background_jobs()?;
+        }
         Ok(())
     }
 
-    /// Destroys all data in the database and resets it to a clean empty state.
-    ///
-    /// This deletes the entire SSTable directory, WAL directory, and MANIFEST, then
-    /// re-initializes them. It exists primarily for testing teardown; in production you
-    /// would almost never call this.
-    pub fn clear(&self) -> Result<(), anyhow::Error> {
-        {
-            let mut memtable = self
-                .active_memtable
-                .lock()
-                .map_err(|_| anyhow::anyhow!("MemTable lock poisoned"))?;
-            memtable.clear();
+    fn flush_family(&self, family: &Arc<ColumnFamilyData>) -> Result<(), Error> {
+        let empty = family
+            .active_memtable
+            .lock()
+            .map_err(|_| Error::Poisoned("MemTable"))?
+            .is_empty();
+        if empty {
+            return Ok(());
         }
+        self.trigger_background_flush(family)
+    }
 
-        {
-            let mut imm = self
-                .immutable_memtable
-                .lock()
-                .map_err(|_| anyhow::anyhow!("Immutable MemTable lock poisoned"))?;
-            *imm = None;
+    /// Compacts the SSTables holding keys in `start..end` all the way down to the bottom
+    /// level, whatever the level budgets say; `None` leaves that end of the range open.
+    ///
+    /// Each level with a table overlapping the range is merged with the level below it, so
+    /// versions overwritten or deleted since they were flushed — and values whose TTL has run
+    /// out — stop taking up space. Run it after a bulk delete to reclaim the space, or with
+    /// `None, None` to compact the whole database. Only what is already in SSTables is
+    /// compacted: call [`flush`](Self::flush) first to include the MemTable.
+    ///
+    /// Versions a live [`Snapshot`] can still read are kept. So are the tombstones that hide
+    /// them; once they reach the bottom level and no snapshot predates them, point and range
+    /// tombstones are dropped along with everything they deleted. Over the whole key range,
+    /// with no snapshot open, that leaves no tombstone behind.
+    ///
+    /// This rewrites every table of each level it touches, so it can take a while; it runs
+    /// on the calling thread, and the automatic compactions after flushes wait for it.
+    ///
+    /// ```no_run
+    /// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+    /// engine.delete_range(b"session:", b"session;")?;
+    /// engine.flush(true)?;
+    /// engine.compact_range(Some(b"session:"), Some(b"session;"))?;
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn compact_range<K: AsRef<[u8]>>(
+        &self,
+        start: Option<K>,
+        end: Option<K>,
+    ) -> Result<(), Error> {
+        self.compact_family_range(
+            &self.default_cf,
+            start.as_ref().map(AsRef::as_ref),
+            end.as_ref().map(AsRef::as_ref),
+        )
+    }
+
+    /// Like [`compact_range`](Self::compact_range), over the SSTables of column family `cf`.
+    pub fn compact_range_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        start: Option<K>,
+        end: Option<K>,
+    ) -> Result<(), Error> {
+        self.compact_family_range(
+            &*self.family(cf)?,
+            start.as_ref().map(AsRef::as_ref),
+            end.as_ref().map(AsRef::as_ref),
+        )
+    }
+
+    /// Shuts the database down cleanly, and reports anything that went wrong on the way.
+    ///
+    /// Waits for the flushes still running in the background, flushes the MemTable of every
+    /// column family opened with [`flush_on_close`](Options::flush_on_close), and syncs the WAL
+    /// and the MANIFEST to disk. Background compactions finish the level they are on and stop
+    /// there; the next flush after a reopen carries on.
+    /// Every step is attempted even if an earlier one fails; the first error is returned. An
+    /// error from a background flush that failed earlier — which has nobody else to report
+    /// it to — is returned here too.
+    ///
+    /// Dropping the engine does the same, but has no way to report the errors.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        // A read-only engine has nothing to wait for and nothing to sync.
+        if std::mem::replace(&mut self.closed, true) || self.read_only {
+            return Ok(());
         }
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let mut result = self.join_background_jobs();
+
+        let families: Vec<_> = match self.column_families.read() {
+            Ok(families) => families
+                .values()
+                .filter(|family| family.options.flush_on_close)
+                .cloned()
+                .collect(),
+            Err(_) => {
+                result = result.and(Err(Error::Poisoned("Column families")));
+                Vec::new()
+            }
+        };
+        for family in families {
+            result = result.and(self.flush_family(&family));
+        }
+        result = result.and(self.join_background_jobs());
 
-        let mut sstables = self
-            .sstables
+        let synced = self
+            .wal
+            .lock()
+            .map_err(|_| Error::Poisoned("WAL"))
+            .and_then(|mut wal| wal.sync());
+        result = result.and(synced);
+        let synced = self
+            .manifest
             .write()
-            .map_err(|_| anyhow::anyhow!("SSTables lock poisoned"))?;
-        sstables.clear();
+            .map_err(|_| Error::Poisoned("Manifest"))
+            .and_then(|mut manifest| manifest.sync());
+        result.and(synced)
+    }
+
+    fn join_background_jobs(&self) -> Result<(), Error> {
+        self.background
+            .lock()
+            .map_err(|_| Error::Poisoned("Background jobs"))?
+            .join_all()
+    }
+
+    /// Destroys all data in the database and resets it to a clean empty state.
+    ///
+    /// This deletes the entire SSTable directory, WAL directory, and MANIFEST, then
+    /// re-initializes them. Column families survive, empty. It exists primarily for testing
+    /// teardown; in production you would almost never call this.
+    pub fn clear(&self) -> Result<(), Error> {
+        self.check_writable()?;
+        let families = self
+            .column_families
+            .read()
+            .map_err(|_| Error::Poisoned("Column families"))?;
+
+        let mut all_sstables = Vec::new();
+        for family in families.values() {
+            {
+                let mut memtable = family
+                    .active_memtable
+                    .lock()
+                    .map_err(|_| Error::Poisoned("MemTable"))?;
+                memtable.clear();
+            }
+
+            {
+                let mut imm = family
+                    .immutable_memtable
+                    .lock()
+                    .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
+                *imm = None;
+            }
+
+            let mut sstables = family
+                .sstables
+                .write()
+                .map_err(|_| Error::Poisoned("SSTables"))?;
+            sstables.clear();
+            all_sstables.push(sstables);
+        }
 
         let sst_dir = self.db_path.join("sst");
         let wal_dir = self.db_path.join("wal");
@@ -361,40 +1737,52 @@ impl StorageEngine {
         std::fs::create_dir_all(&sst_dir)?;
 
         {
-            let mut wal_writer = self
-                .wal
-                .lock()
-                .map_err(|_| anyhow::anyhow!("WAL lock poisoned"))?;
-            *wal_writer = Wal::new(wal_dir)?;
+            let mut wal_writer = self.wal.lock().map_err(|_| Error::Poisoned("WAL"))?;
+            *wal_writer = Wal::new(wal_dir, self.default_cf.options.wal_sync_on_write)?;
+            for family in families.values() {
+                family
+                    .active_log
+                    .store(wal_writer.current_file_num(), Ordering::SeqCst);
+            }
         }
 
-        let manifest = crate::sstable::Manifest::open(&manifest_path)?;
+        let mut manifest = crate::sstable::Manifest::open(&manifest_path)?;
+        for family in families.values() {
+            if family.id() != DEFAULT_COLUMN_FAMILY_ID {
+                manifest.log_edit(&VersionEdit::AddColumnFamily {
+                    id: family.id(),
+                    name: family.handle.name().to_string(),
+                })?;
+            }
+        }
         let mut manifest_lock = self
             .manifest
             .write()
-            .map_err(|_| anyhow::anyhow!("Manifest lock poisoned"))?;
+            .map_err(|_| Error::Poisoned("Manifest"))?;
         *manifest_lock = manifest;
-        sstables.push(Vec::new());
+        for sstables in &mut all_sstables {
+            sstables.push(Vec::new());
+        }
 
         let mut cache = self
             .block_cache
             .write()
-            .map_err(|_| anyhow::anyhow!("Block Cache lock poisoned"))?;
+            .map_err(|_| Error::Poisoned("Block Cache"))?;
         cache.clear();
 
         Ok(())
     }
 
-    // Moves the full MemTable into the immutable slot, installs a fresh active MemTable,
-    // and spawns a background thread to persist the immutable one to disk.
+    // Moves the family's full MemTable into its immutable slot, installs a fresh active
+    // MemTable, and spawns a background thread to persist the immutable one to disk.
     //
     // Writers that arrive while a flush is in progress (immutable slot occupied) park on the
     // Condvar rather than returning an error or silently discarding data. This "write stall"
     // is intentional: it applies back-pressure to the caller so the engine never loses writes.
     // The alternative — returning a "try again" error — would require every caller to implement
     // retry logic, which is worse API design.
-    fn trigger_background_flush(&self) -> Result<(), anyhow::Error> {
-        let (flush_mutex, flush_condvar) = &*self.flush_condvar;
+    fn trigger_background_flush(&self, family: &Arc<ColumnFamilyData>) -> Result<(), Error> {
+        let (flush_mutex, flush_condvar) = &family.flush_condvar;
 
         {
             let mut flushing = flush_mutex.lock().unwrap();
@@ -405,90 +1793,119 @@ impl StorageEngine {
         }
 
         {
-            let mut active = self
+            // Same lock order as `write` (WAL, then MemTables), so the two cannot deadlock.
+            // Holding the WAL lock also keeps writers out until the rotation below is done.
+            let mut wal = self.wal.lock().map_err(|_| Error::Poisoned("WAL"))?;
+            let mut active = family
                 .active_memtable
                 .lock()
-                .map_err(|_| anyhow::anyhow!("MemTable lock poisoned"))?;
-            let mut imm = self
+                .map_err(|_| Error::Poisoned("MemTable"))?;
+            let mut imm = family
                 .immutable_memtable
                 .lock()
-                .map_err(|_| anyhow::anyhow!("Immutable MemTable lock poisoned"))?;
+                .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
 
-            let empty_memtable =
-                MemTable::new(self.memtable_capacity, crate::constants::BLOOM_FILTER_FPR);
+            let empty_memtable = MemTable::new(
+                family.options.memtable_capacity_bytes,
+                family.options.bloom_filter_fpr,
+            );
             let memtable_to_flush = std::mem::replace(&mut *active, empty_memtable);
 
             *imm = Some(Arc::new(memtable_to_flush));
 
             // Roll the WAL to a new file before releasing the locks. Any writes that arrive
-            // after this point will go to the new WAL file. This means the old WAL file
-            // contains exactly the records belonging to the MemTable being flushed —
-            // once that SSTable is confirmed on disk, the old WAL file can be safely deleted.
-            let wal_dir = self.db_path.join("wal");
-            if let Ok(mut wal) = Wal::new(wal_dir) {
-                wal.add(0, b"".to_vec(), b"".to_vec()).unwrap_or(());
-                let mut wal_writer = self
-                    .wal
-                    .lock()
-                    .map_err(|_| anyhow::anyhow!("WAL lock poisoned"))?;
-                *wal_writer = wal;
-            }
+            // after this point will go to the new WAL file, so the family's new active MemTable
+            // only needs the files from here on. The old files may still hold writes of other
+            // families, which is why they are only deleted once every family is done with them.
+            wal.rotate()?;
+            family
+                .immutable_log
+                .store(family.active_log.load(Ordering::SeqCst), Ordering::SeqCst);
+            family
+                .active_log
+                .store(wal.current_file_num(), Ordering::SeqCst);
         };
 
-        let imm_memtable_arc = Arc::clone(&self.immutable_memtable);
-        let sstables_arc = Arc::clone(&self.sstables);
+        let family_arc = Arc::clone(family);
+        let column_families_arc = Arc::clone(&self.column_families);
         let manifest_arc = Arc::clone(&self.manifest);
         let db_path_arc = Arc::clone(&self.db_path);
         let wal_arc = Arc::clone(&self.wal);
-        let condvar_arc = Arc::clone(&self.flush_condvar);
-
-        std::thread::spawn(move || {
-            if let Err(e) = Self::flush_immutable_memtable(
-                imm_memtable_arc,
-                sstables_arc,
-                manifest_arc,
-                db_path_arc,
+        let snapshots_arc = Arc::clone(&self.snapshots);
+        let file_ids_arc = Arc::clone(&self.file_ids);
+        let shutting_down = Arc::clone(&self.shutting_down);
+
+        let mut background = self
+            .background
+            .lock()
+            .map_err(|_| Error::Poisoned("Background jobs"))?;
+        // A failed flush is reported by `close`, or printed when the engine is dropped.
+        background.spawn(move || {
+            let result = Self::flush_immutable_memtable(
+                &family_arc,
+                column_families_arc,
+                Arc::clone(&manifest_arc),
+                Arc::clone(&db_path_arc),
                 wal_arc,
-            ) {
-                eprintln!("Background flush failed: {}", e);
-            }
+                &file_ids_arc,
+            )
+            .and_then(|()| {
+                Self::run_compaction(
+                    &family_arc,
+                    manifest_arc,
+                    db_path_arc,
+                    &snapshots_arc,
+                    &file_ids_arc,
+                    &shutting_down,
+                )
+            });
 
             // Unblock all writers waiting in trigger_background_flush. notify_all (not
             // notify_one) is deliberate: there may be multiple stalled writers from
             // different threads that can now all proceed.
-            let (mutex, condvar) = &*condvar_arc;
+            let (mutex, condvar) = &family_arc.flush_condvar;
             let mut flushing = mutex.lock().unwrap();
             *flushing = false;
             condvar.notify_all();
+
+            // A compaction cut short by `close` is not a failure: the flush is done, and the
+            // levels are compacted again after the next flush.
+            match result {
+                Err(Error::ShutdownInProgress) => Ok(()),
+                result => result,
+            }
         });
 
         Ok(())
     }
 
-    // Writes the immutable MemTable to a new SSTable file on disk.
+    // Writes the family's immutable MemTable to a new SSTable file on disk.
     //
     // We clone the Arc<MemTable> and immediately release the Mutex so writers are not blocked
     // for the duration of disk I/O (which could take seconds on a slow or heavily-loaded disk).
     // The Arc ensures the MemTable data stays alive for the duration of the write even after
     // the Mutex guard is dropped.
     //
-    // SSTable filenames are Unix millisecond timestamps. This gives them a natural sort order
-    // (newest = largest number) which the reader uses to check most-recent-first in L0.
+    // SSTable filenames are Unix millisecond timestamps (see `FileIds`). This gives them a
+    // natural sort order (newest = largest number) which the reader uses to check
+    // most-recent-first in L0.
     //
     // WAL GC must happen AFTER the immutable slot is cleared. If we deleted WAL files first
     // and then crashed before clearing the slot, recovery would not find the WAL records and
     // would miss those writes. Clearing the slot first is the safe ordering.
     fn flush_immutable_memtable(
-        immutable_memtable: Arc<Mutex<Option<Arc<MemTable>>>>,
-        sstables: Arc<RwLock<Vec<Vec<SSTableReader>>>>,
+        family: &ColumnFamilyData,
+        column_families: ColumnFamilies,
         manifest: Arc<RwLock<crate::sstable::Manifest>>,
         db_path: Arc<PathBuf>,
         wal: Arc<Mutex<Wal>>,
-    ) -> Result<(), anyhow::Error> {
+        file_ids: &FileIds,
+    ) -> Result<(), Error> {
         let memtable_arc = {
-            let imm = immutable_memtable
+            let imm = family
+                .immutable_memtable
                 .lock()
-                .map_err(|_| anyhow::anyhow!("Immutable MemTable lock poisoned"))?;
+                .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
             match imm.as_ref() {
                 Some(m) => Arc::clone(m),
                 None => return Ok(()),
@@ -499,96 +1916,125 @@ impl StorageEngine {
             return Ok(());
         }
 
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-
-        let sst_path = db_path.join(format!("sst/{}.sst", timestamp));
-        let mut sst_builder = SSTableBuilder::new(sst_path.clone());
+        let sst_id = file_ids.next();
+        let sst_path = db_path.join(format!("sst/{}.sst", sst_id));
+        let mut sst_builder = SSTableBuilder::new(sst_path.clone(), &family.options, 0)?;
 
+        let mut last_seq = 0;
         for (k, v) in memtable_arc.entries() {
-            sst_builder.add(k, v);
+            last_seq = last_seq.max(internal_key::sequence(k));
+            sst_builder.add(k, v)?;
+        }
+        for tombstone in memtable_arc.range_tombstones() {
+            last_seq = last_seq.max(tombstone.seq);
+            sst_builder.add_range_tombstone(tombstone.clone());
         }
 
         sst_builder.finish()?;
+        let reader = Arc::new(SSTableReader::open(sst_path)?);
 
         // Insert at index 0 so newest files are always first in L0 (see open() comment
         // about why L0 must be searched newest-first).
         {
-            let mut sstables_write = sstables
+            let mut sstables_write = family
+                .sstables
                 .write()
-                .map_err(|_| anyhow::anyhow!("SSTables lock poisoned"))?;
-            sstables_write[0].insert(0, SSTableReader::new(sst_path));
+                .map_err(|_| Error::Poisoned("SSTables"))?;
+            sstables_write[0].insert(0, reader);
         }
 
-        let sst_id = timestamp as u64;
-        if let Ok(mut m_lock) = manifest.write() {
-            let _ = m_lock.log_edit(&VersionEdit::AddTable { level: 0, sst_id });
+        // The sequence number goes in before the table: the WAL files holding these writes are
+        // deleted below, after which the MANIFEST is the only record of how far the sequence
+        // has advanced. The flushed sequence goes in after it, so a crash in between replays
+        // the writes again rather than losing them.
+        // A failed edit stops the flush here, before anything is deleted: the writes stay in
+        // the WAL and are replayed on the next open.
+        let cf = family.id();
+        {
+            let mut m_lock = manifest.write().map_err(|_| Error::Poisoned("Manifest"))?;
+            m_lock.log_edit(&VersionEdit::LastSequence { seq: last_seq })?;
+            m_lock.log_edit(&VersionEdit::AddTable {
+                cf,
+                level: 0,
+                sst_id,
+            })?;
+            m_lock.log_edit(&VersionEdit::FlushedSequence { cf, seq: last_seq })?;
         }
 
-        // Read the current WAL file number before clearing the immutable slot.
-        // Any WAL file strictly older than the current one is safe to delete — it belongs
-        // to the MemTable we just persisted. We use saturating_sub(1) because file 0 is
-        // the sentinel empty file written during WAL rotation.
-        let safe_to_delete_wal_num = {
-            let wal_lock = wal.lock().unwrap();
-            wal_lock.current_file_num().saturating_sub(1)
-        };
-
         {
-            let mut imm = immutable_memtable.lock().unwrap();
+            let mut imm = family
+                .immutable_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("Immutable MemTable"))?;
             *imm = None;
         }
 
-        if safe_to_delete_wal_num > 0 {
-            let wal_lock = wal.lock().unwrap();
-            let _ = wal_lock.delete_old_files(safe_to_delete_wal_num);
+        // A WAL file can go once no family has unflushed writes in it. Under the WAL lock, so
+        // no family can rotate its MemTables while we look. The current file is never deleted.
+        {
+            let wal_lock = wal.lock().map_err(|_| Error::Poisoned("WAL"))?;
+            let families = column_families
+                .read()
+                .map_err(|_| Error::Poisoned("Column families"))?;
+            let mut oldest_needed = wal_lock.current_file_num();
+            for other in families.values() {
+                if let Some(log) = other.oldest_unflushed_log()? {
+                    oldest_needed = oldest_needed.min(log);
+                }
+            }
+            // File numbers start at 1, so 0 means "nothing to delete".
+            let safe_to_delete_wal_num = oldest_needed.saturating_sub(1);
+            if safe_to_delete_wal_num > 0 {
+                let _ = wal_lock.delete_old_files(safe_to_delete_wal_num);
+            }
         }
 
-        let _ = Self::run_compaction(sstables, manifest, db_path);
-
         Ok(())
     }
 
-    // Compacts levels starting from L0, cascading upward until no level exceeds its budget.
+    // Compacts the family's levels starting from L0, cascading upward until no level exceeds
+    // its budget. Stops with `Error::ShutdownInProgress` between two levels once `close` has
+    // begun, so it does not keep `close` waiting on a long cascade.
     //
     // L0 triggers by file count (not byte size) because L0 files can overlap in key range.
     // More L0 files means more files to scan on a read miss. Keeping L0 small bounds read
     // amplification. Higher levels use byte budgets because they are sorted and non-overlapping
     // — a single well-sized file is as fast to search as multiple small ones via the index.
-    //
-    // We log VersionEdits to the MANIFEST BEFORE updating the in-memory sstables list. If we
-    // did it afterward and crashed between the two steps, the in-memory list would be stale on
-    // restart but the MANIFEST would reflect the correct state (the MANIFEST is authoritative).
-    // Doing it before the in-memory update means a crash leaves the MANIFEST correct and the
-    // in-memory state is rebuilt from it at startup.
-    //
-    // Input SSTable files are deleted only after both the MANIFEST is updated and the in-memory
-    // list no longer references them — a read thread holding a reference to a now-deleted file
-    // would segfault on mmap access otherwise.
     fn run_compaction(
-        sstables: Arc<RwLock<Vec<Vec<SSTableReader>>>>,
+        family: &ColumnFamilyData,
         manifest: Arc<RwLock<Manifest>>,
         db_path: Arc<PathBuf>,
-    ) -> Result<(), anyhow::Error> {
-        let max_levels = MAX_LEVELS;
+        snapshots: &SnapshotList,
+        file_ids: &FileIds,
+        shutting_down: &AtomicBool,
+    ) -> Result<(), Error> {
+        let _compacting = family
+            .compaction_lock
+            .lock()
+            .map_err(|_| Error::Poisoned("Compaction"))?;
+        let options = &family.options;
+        let sstables = &family.sstables;
+        let max_levels = options.max_levels;
 
         for level in 0..max_levels.saturating_sub(1) {
+            if shutting_down.load(Ordering::SeqCst) {
+                return Err(Error::ShutdownInProgress);
+            }
             let next_level = level + 1;
 
-            let (needs_compact, input_ids, input_paths) = {
-                let sst_read = sstables.read().unwrap();
+            let (needs_compact, input_ids, input_paths, bottommost) = {
+                let sst_read = sstables.read().map_err(|_| Error::Poisoned("SSTables"))?;
 
                 if sst_read.len() <= level || sst_read[level].is_empty() {
                     break;
                 }
 
                 let should_compact = if level == 0 {
-                    sst_read[level].len() >= L0_COMPACTION_TRIGGER
+                    sst_read[level].len() >= options.l0_compaction_trigger
                 } else {
-                    let level_budget = L1_MAX_BYTES
-                        * (LEVEL_SIZE_MULTIPLIER as u64).pow(level.saturating_sub(1) as u32);
+                    let level_budget = options.l1_max_bytes
+                        * (options.level_size_multiplier as u64)
+                            .pow(level.saturating_sub(1) as u32);
 
                     let total_bytes: u64 = sst_read[level]
                         .iter()
@@ -611,53 +2057,248 @@ impl StorageEngine {
                     .map(|id| db_path.join(format!("sst/{}.sst", id)))
                     .collect();
 
-                (true, ids, paths)
+                // The next level's own tables are not inputs, so only with no table there or
+                // below is there nothing older for a tombstone to shadow.
+                let bottommost = sst_read.iter().skip(next_level).all(Vec::is_empty);
+
+                (true, ids, paths, bottommost)
             };
 
             if !needs_compact {
                 break;
             }
 
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64;
+            let output_id = file_ids.next();
+            let output_path = db_path.join(format!("sst/{}.sst", output_id));
+
+            // Read per compaction, so snapshots released in the meantime free their versions.
+            let written = compact(
+                input_paths,
+                output_path,
+                next_level,
+                bottommost,
+                options,
+                &snapshots.sequences(),
+            )?;
+
+            let inputs: Vec<(usize, u64)> = input_ids.iter().map(|&id| (level, id)).collect();
+            Self::install_compaction(
+                family,
+                &manifest,
+                &db_path,
+                &inputs,
+                next_level,
+                written.then_some(output_id),
+            )?;
+        }
 
-            let output_path = db_path.join(format!("sst/{}.sst", timestamp));
+        Ok(())
+    }
 
-            compact(input_paths, output_path.clone())?;
+    // Compacts every level of the family with a table overlapping `start..end` into the level
+    // below it, together with that level's tables, down to the bottom level. The bottom level
+    // is rewritten into a single table too if it overlaps, even one table alone: tombstones a
+    // since-released snapshot kept there can go now.
+    fn compact_family_range(
+        &self,
+        family: &ColumnFamilyData,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        self.check_writable()?;
+        let _compacting = family
+            .compaction_lock
+            .lock()
+            .map_err(|_| Error::Poisoned("Compaction"))?;
+        let bottom = family.options.max_levels - 1;
+
+        for level in 0..=bottom {
+            let output_level = (level + 1).min(bottom);
+            // Newest first: this level's tables, then the older ones of the level below.
+            let (inputs, bottommost) = {
+                let sstables = family
+                    .sstables
+                    .read()
+                    .map_err(|_| Error::Poisoned("SSTables"))?;
+                let Some(tables) = sstables.get(level) else {
+                    break;
+                };
+                let mut overlapping = false;
+                for reader in tables {
+                    if reader.overlaps(start, end)? {
+                        overlapping = true;
+                        break;
+                    }
+                }
+                if !overlapping {
+                    continue;
+                }
+                let inputs: Vec<(usize, u64)> = (level..=output_level)
+                    .flat_map(|l| {
+                        sstables
+                            .get(l)
+                            .into_iter()
+                            .flatten()
+                            .map(move |r| (l, r.id))
+                    })
+                    .collect();
+                let bottommost = sstables.iter().skip(output_level + 1).all(Vec::is_empty);
+                (inputs, bottommost)
+            };
 
-            // MANIFEST first — see function-level comment on ordering.
-            {
-                let mut m_lock = manifest.write().unwrap();
+            let output_id = self.file_ids.next();
+            let input_paths = inputs
+                .iter()
+                .map(|(_, id)| self.db_path.join(format!("sst/{}.sst", id)))
+                .collect();
+            let written = compact(
+                input_paths,
+                self.db_path.join(format!("sst/{}.sst", output_id)),
+                output_level,
+                bottommost,
+                &family.options,
+                &self.snapshots.sequences(),
+            )?;
+            Self::install_compaction(
+                family,
+                &self.manifest,
+                &self.db_path,
+                &inputs,
+                output_level,
+                written.then_some(output_id),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Swaps a finished compaction's output in for its inputs: table `output_id` joins
+    // `output_level`, and the `inputs`, as (level, id) pairs, leave their levels and are
+    // deleted. With no output — everything in the inputs was dropped — the inputs just go.
+    //
+    // We log VersionEdits to the MANIFEST BEFORE updating the in-memory sstables list. If we
+    // did it afterward and crashed between the two steps, the in-memory list would be stale on
+    // restart but the MANIFEST would reflect the correct state (the MANIFEST is authoritative).
+    // Doing it before the in-memory update means a crash leaves the MANIFEST correct and the
+    // in-memory state is rebuilt from it at startup.
+    //
+    // Input SSTable files are deleted only after both the MANIFEST is updated and the in-memory
+    // list no longer references them — a read thread holding a reference to a now-deleted file
+    // would segfault on mmap access otherwise.
+    fn install_compaction(
+        family: &ColumnFamilyData,
+        manifest: &RwLock<Manifest>,
+        db_path: &std::path::Path,
+        inputs: &[(usize, u64)],
+        output_level: usize,
+        output_id: Option<u64>,
+    ) -> Result<(), Error> {
+        // Opened up front, so a table that will not open leaves the MANIFEST untouched.
+        let output = output_id
+            .map(|id| SSTableReader::open(db_path.join(format!("sst/{}.sst", id))).map(Arc::new))
+            .transpose()?;
+
+        // MANIFEST first — see the comment above on ordering.
+        {
+            let mut m_lock = manifest.write().map_err(|_| Error::Poisoned("Manifest"))?;
+            if let Some(sst_id) = output_id {
                 m_lock.log_edit(&VersionEdit::AddTable {
-                    level: next_level as u32,
-                    sst_id: timestamp,
+                    cf: family.id(),
+                    level: output_level as u32,
+                    sst_id,
                 })?;
-                for id in &input_ids {
-                    m_lock.log_edit(&VersionEdit::RemoveTable {
-                        level: level as u32,
-                        sst_id: *id,
-                    })?;
-                }
             }
+            for &(level, id) in inputs {
+                m_lock.log_edit(&VersionEdit::RemoveTable {
+                    cf: family.id(),
+                    level: level as u32,
+                    sst_id: id,
+                })?;
+            }
+        }
 
-            // In-memory list updated after MANIFEST — correctness argument above.
-            {
-                let mut sst_write = sstables.write().unwrap();
-                while sst_write.len() <= next_level {
+        // In-memory list updated after MANIFEST — correctness argument above.
+        {
+            let mut sst_write = family
+                .sstables
+                .write()
+                .map_err(|_| Error::Poisoned("SSTables"))?;
+            for &(level, id) in inputs {
+                sst_write[level].retain(|r| r.id != id);
+            }
+            if let Some(output) = output {
+                while sst_write.len() <= output_level {
                     sst_write.push(Vec::new());
                 }
-                sst_write[next_level].insert(0, SSTableReader::new(output_path));
-                sst_write[level].retain(|r| !input_ids.contains(&r.id));
+                sst_write[output_level].insert(0, output);
             }
+        }
 
-            // Files deleted last — only safe once no in-memory reference points to them.
-            for id in &input_ids {
-                let _ = std::fs::remove_file(db_path.join(format!("sst/{}.sst", id)));
-            }
+        // Files deleted last — only safe once no in-memory reference points to them.
+        for &(_, id) in inputs {
+            let _ = std::fs::remove_file(db_path.join(format!("sst/{}.sst", id)));
         }
 
         Ok(())
     }
 }
+
+// Dropping without `close` still waits for background work, so a flush thread never outlives
+// the engine and finds its files gone. Errors are lost; `close` is there to see them.
+impl Drop for StorageEngine {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+// Moves a damaged SSTable from `sst/` into `lost/`, where it can still be inspected, and drops
+// it from the MANIFEST so the next open does not meet it again.
+fn quarantine_sstable(
+    db_path: &Path,
+    manifest: &mut Manifest,
+    cf: u32,
+    level: usize,
+    sst_id: u64,
+) -> Result<(), Error> {
+    let lost_dir = db_path.join("lost");
+    std::fs::create_dir_all(&lost_dir)?;
+    let file_name = format!("{}.sst", sst_id);
+    std::fs::rename(
+        db_path.join("sst").join(&file_name),
+        lost_dir.join(&file_name),
+    )?;
+    manifest.log_edit(&VersionEdit::RemoveTable {
+        cf,
+        level: level as u32,
+        sst_id,
+    })
+}
+
+// Names are stored with a 2-byte length in the MANIFEST.
+fn validate_column_family_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > u16::MAX as usize {
+        return Err(Error::invalid(format!(
+            "Column family names must be 1 to {} bytes long",
+            u16::MAX
+        )));
+    }
+    Ok(())
+}
+
+// Deletes, merges and expiring puts are logged with their own opcodes and stored as their own
+// value types, and a range deletion — logged with its start as the key and its end as the
+// value — goes to the MemTable's tombstone list. Everything else is a value.
+fn apply_record(memtable: &mut MemTable, record: Record) {
+    let value_type = match record.opcode {
+        Opcode::DeleteRange => {
+            memtable.delete_range(record.key, record.val, record.seq_num);
+            return;
+        }
+        Opcode::Delete => ValueType::Deletion,
+        Opcode::Merge => ValueType::Merge,
+        // The record's value already carries the deadline in front, as the entry stores it.
+        Opcode::PutWithTtl => ValueType::ExpiringValue,
+        Opcode::Put | Opcode::Batch => ValueType::Value,
+    };
+    memtable.set(record.key, record.seq_num, value_type, record.val);
+}
@@ -127,7 +127,9 @@ fn main() -> Result<()> {
         }
     }
 
-    Ok(())
+    engine
+        .close()
+        .map_err(|e| anyhow::anyhow!("Could not close database at {:?}: {}", db_path, e))
 }
 
 fn print_banner(db_path: &Path) {
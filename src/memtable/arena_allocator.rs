@@ -235,7 +235,7 @@ mod tests {
         assert_eq!((p2 as usize) - (p1 as usize), chunk_size);
 
         // p4 and p5 should be in DIFFERENT blocks, meaning their addresses are far apart
-        let diff = (p5 as isize - p4 as isize).abs() as usize;
+        let diff = (p5 as isize - p4 as isize).unsigned_abs();
         assert!(diff >= chunk_size);
     }
 
@@ -1,5 +1,9 @@
 use crate::bloom_filter::BloomFilter;
-use skiplist::SkipList;
+use crate::internal_key::{self, InternalKey, MAX_SEQUENCE, ValueType};
+use crate::iterator::InternalIterator;
+use crate::range_del::RangeTombstone;
+use skiplist::{SkipList, SkipListCursor};
+use std::sync::Arc;
 
 mod arena_allocator;
 mod skiplist;
@@ -10,18 +14,27 @@ mod tests;
 /// Write buffer for the LSM-Tree. All puts and removes land here before being flushed to disk.
 ///
 /// Keys are sorted inside the SkipList so the flush path can emit entries in sorted order,
-/// which is required for the SSTable format (binary-searchable blocks).
+/// which is required for the SSTable format (binary-searchable blocks). Every write is stored
+/// under its own internal key (user key + sequence number), so overwriting a key adds a newer
+/// version instead of replacing the old one — a snapshot may still need to read it.
 ///
 /// The Bloom Filter is kept in sync with every insert so that `get()` can answer "definitely
 /// not present" in O(k) hash operations without touching the SkipList at all. Most reads in a
 /// write-heavy workload miss the MemTable — the filter makes those misses cheap.
+///
+/// Range tombstones are kept in a plain list beside the SkipList: a handful of them can cover
+/// any number of keys, so they are few, and each read checks all of them.
 pub struct MemTable {
-    entries: SkipList<Vec<u8>, Vec<u8>>,
+    entries: SkipList<InternalKey, Vec<u8>>,
+    range_tombstones: Vec<RangeTombstone>,
     capacity_bytes: usize,
     // Separate from the Arena's tracked memory because the Arena over-allocates in slab-sized
     // chunks. size_bytes tracks the actual key+value payload so the flush threshold is
     // meaningful and predictable regardless of internal Arena fragmentation.
     size_bytes: usize,
+    // The oldest sequence number written here, or `MAX_SEQUENCE` while empty. Writes arrive in
+    // sequence order, so every write after it is in this MemTable too.
+    earliest_seq: u64,
     bloom_filter: BloomFilter,
     // Stored so that clear() can rebuild the filter with the same FPR, not a hardcoded default.
     false_positive_rate: f64,
@@ -37,36 +50,95 @@ impl MemTable {
     pub fn new(capacity_bytes: usize, false_positive_rate: f64) -> Self {
         assert!(capacity_bytes > 0, "Capacity must be greater than zero");
 
+        // Clamped to 1: a zero-element filter has no bits, and `contains` on an empty bit
+        // array answers "definitely absent" for every key — which would hide real entries.
         let avg_entry_size = 100;
-        let num_elements = capacity_bytes / avg_entry_size;
+        let num_elements = (capacity_bytes / avg_entry_size).max(1);
 
         Self {
             entries: SkipList::new(),
+            range_tombstones: Vec::new(),
             capacity_bytes,
             size_bytes: 0,
+            earliest_seq: MAX_SEQUENCE,
             bloom_filter: BloomFilter::new(num_elements, false_positive_rate),
             false_positive_rate,
         }
     }
 
-    /// Inserts or overwrites a key. An empty value represents a tombstone (deletion marker).
-    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+    /// Records the version of `key` written at sequence number `seq`. A deletion is recorded
+    /// as a `ValueType::Deletion` entry (a tombstone) with an empty value.
+    pub fn set(&mut self, key: Vec<u8>, seq: u64, value_type: ValueType, value: Vec<u8>) {
         self.bloom_filter.set(&key);
-        self.size_bytes += key.len() + value.len();
-        self.entries.insert(key, value);
+        self.size_bytes += key.len() + internal_key::TRAILER_SIZE + value.len();
+        self.earliest_seq = self.earliest_seq.min(seq);
+        self.entries
+            .insert(InternalKey::new(&key, seq, value_type), value);
+    }
+
+    /// Records a deletion of every key in `start..end` at sequence number `seq`.
+    pub fn delete_range(&mut self, start: Vec<u8>, end: Vec<u8>, seq: u64) {
+        self.size_bytes += start.len() + end.len() + internal_key::TRAILER_SIZE;
+        self.earliest_seq = self.earliest_seq.min(seq);
+        self.range_tombstones
+            .push(RangeTombstone { start, end, seq });
+    }
+
+    /// The sequence number of the newest range tombstone covering `key` that a reader at `seq`
+    /// can see, or 0 if there is none.
+    pub fn max_covering_tombstone_seq(&self, key: &[u8], seq: u64) -> u64 {
+        self.range_tombstones
+            .iter()
+            .filter(|t| t.seq <= seq && t.covers(key))
+            .map(|t| t.seq)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The range tombstones, in the order they were written.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
     }
 
-    /// Returns the value for `key`, or `None` if definitely absent.
-    pub fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
+    /// Returns the newest version of `key` written at or before sequence number `seq` as its
+    /// type, sequence number and value, or `None` if there is none. A `ValueType::Deletion`
+    /// result means the key was deleted.
+    pub fn get(&self, key: &[u8], seq: u64) -> Option<(ValueType, u64, &Vec<u8>)> {
         if !self.bloom_filter.contains(key) {
             return None;
         }
 
-        // The SkipList is keyed on Vec<u8>, so we must convert the slice to a Vec for the
-        // lookup. This allocation is the cost of not having a Borrow-generic SkipList key type.
-        // It is acceptable here because this path is already behind a Bloom Filter check.
-        let search_key = key.to_vec();
-        self.entries.get(&search_key)
+        // Versions sort newest first, so the first entry at or after (key, seq) is the newest
+        // one visible at `seq` — provided it still belongs to `key`.
+        let search_key = InternalKey(internal_key::seek_key(key, seq));
+        let (found, value) = self.entries.iter_from(&search_key).next()?;
+        if internal_key::user_key(&found.0) != key {
+            return None;
+        }
+        Some((
+            internal_key::value_type(&found.0)?,
+            internal_key::sequence(&found.0),
+            value,
+        ))
+    }
+
+    /// The sequence number of the newest write to `key` here — a version of it, or a range
+    /// tombstone covering it — or `None` if there is none.
+    pub fn latest_sequence(&self, key: &[u8]) -> Option<u64> {
+        let point_seq = self.get(key, MAX_SEQUENCE).map_or(0, |(_, seq, _)| seq);
+        let range_seq = self.max_covering_tombstone_seq(key, MAX_SEQUENCE);
+        Some(point_seq.max(range_seq)).filter(|&seq| seq > 0)
+    }
+
+    /// True if the MemTable holds every write made after sequence number `seq`, so older
+    /// layers cannot hold anything newer than `seq`.
+    pub fn covers_writes_after(&self, seq: u64) -> bool {
+        self.earliest_seq <= seq.saturating_add(1)
+    }
+
+    /// Returns true if nothing has been written to the MemTable.
+    pub fn is_empty(&self) -> bool {
+        self.size_bytes == 0
     }
 
     /// Returns true when the MemTable has filled to its capacity and must be flushed.
@@ -90,13 +162,101 @@ impl MemTable {
     /// the same configured FPR rather than a hardcoded default.
     pub fn clear(&mut self) {
         self.entries = SkipList::new();
-        let num_elements = self.capacity_bytes / 100;
+        self.range_tombstones.clear();
+        let num_elements = (self.capacity_bytes / 100).max(1);
         self.bloom_filter = BloomFilter::new(num_elements, self.false_positive_rate);
         self.size_bytes = 0;
+        self.earliest_seq = MAX_SEQUENCE;
+    }
+
+    /// Returns entries in internal key order, starting at the newest version of the first user
+    /// key >= `start`. Keys are yielded as internal keys.
+    ///
+    /// Unlike `get`, this bypasses the Bloom Filter: a range scan needs every key in the
+    /// range, and the filter can only answer membership for exact keys.
+    pub fn scan_from(&self, start: &[u8]) -> impl Iterator<Item = (&[u8], &Vec<u8>)> {
+        self.entries
+            .iter_from(&InternalKey(internal_key::seek_key(start, MAX_SEQUENCE)))
+            .map(|(k, v)| (k.0.as_slice(), v))
+    }
+
+    /// Returns all entries, keyed by internal key and in internal key order, for writing to an
+    /// SSTable.
+    pub fn entries(&self) -> Vec<(&[u8], &Vec<u8>)> {
+        self.entries
+            .iter()
+            .map(|(k, v)| (k.0.as_slice(), v))
+            .collect()
+    }
+}
+
+/// A bidirectional cursor over a frozen (immutable) MemTable.
+///
+/// The active MemTable can only be read under its lock, so scans copy the entries they need
+/// out of it. A frozen MemTable receives no more writes, so it can be walked in place instead:
+/// the cursor holds an `Arc` to keep the SkipList alive for as long as the scan needs it, even
+/// if a flush finishes and the engine drops its own reference in the meantime.
+pub(crate) struct MemTableIterator {
+    // Declared before `_memtable` so it is dropped first: it points into that MemTable.
+    cursor: SkipListCursor<'static, InternalKey, Vec<u8>>,
+    _memtable: Arc<MemTable>,
+}
+
+// SAFETY: the cursor only reads nodes of a SkipList that no longer receives writes, and the
+// SkipList itself is `Send + Sync`; the raw node pointer is what keeps the auto-impl away.
+unsafe impl Send for MemTableIterator {}
+
+impl MemTableIterator {
+    pub(crate) fn new(memtable: Arc<MemTable>) -> Self {
+        // SAFETY: the SkipList lives inside the `Arc` allocation, so its address is stable and
+        // it outlives the cursor, which is dropped first (see field order above).
+        let cursor = unsafe {
+            std::mem::transmute::<
+                SkipListCursor<'_, InternalKey, Vec<u8>>,
+                SkipListCursor<'static, InternalKey, Vec<u8>>,
+            >(memtable.entries.cursor())
+        };
+        Self {
+            cursor,
+            _memtable: memtable,
+        }
+    }
+}
+
+impl InternalIterator for MemTableIterator {
+    fn valid(&self) -> bool {
+        self.cursor.valid()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.cursor.key().0
+    }
+
+    fn value(&self) -> &[u8] {
+        self.cursor.value()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.cursor.seek_to_first();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.cursor.seek_to_last();
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.cursor.seek(&InternalKey(key.to_vec()));
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.cursor.seek_for_prev(&InternalKey(key.to_vec()));
+    }
+
+    fn next(&mut self) {
+        self.cursor.next();
     }
 
-    /// Returns all entries in sorted key order for writing to an SSTable.
-    pub fn entries(&self) -> Vec<(&Vec<u8>, &Vec<u8>)> {
-        self.entries.iter().collect()
+    fn prev(&mut self) {
+        self.cursor.prev();
     }
 }
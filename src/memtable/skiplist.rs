@@ -145,6 +145,55 @@ impl<K: Ord + Default, V: Default> SkipList<K, V> {
         }
     }
 
+    /// Returns the last node whose key is < `key`, or null if there is none.
+    ///
+    /// Nodes only link forward, so stepping backwards is a fresh O(log n) descent that stops
+    /// one node short of where `find_greater_or_equal` would land. That is the classic
+    /// LevelDB trade-off: no back-pointers to maintain on insert, at the cost of a slower
+    /// `prev`.
+    fn find_less_than(&self, key: &K) -> *mut Node<K, V> {
+        let mut current = self.head;
+        let mut level = self.max_height.load(Ordering::Relaxed) - 1;
+
+        loop {
+            let next = unsafe { (*current).next_ptr(level).load(Ordering::Acquire) };
+
+            if !next.is_null() && unsafe { (*next).key < *key } {
+                current = next;
+            } else if level == 0 {
+                return if current == self.head {
+                    ptr::null_mut()
+                } else {
+                    current
+                };
+            } else {
+                level -= 1;
+            }
+        }
+    }
+
+    /// Returns the last node in the list, or null if the list is empty.
+    fn find_last(&self) -> *mut Node<K, V> {
+        let mut current = self.head;
+        let mut level = self.max_height.load(Ordering::Relaxed) - 1;
+
+        loop {
+            let next = unsafe { (*current).next_ptr(level).load(Ordering::Acquire) };
+
+            if !next.is_null() {
+                current = next;
+            } else if level == 0 {
+                return if current == self.head {
+                    ptr::null_mut()
+                } else {
+                    current
+                };
+            } else {
+                level -= 1;
+            }
+        }
+    }
+
     /// Inserts a new key-value pair, or overwrites the value in-place if the key already exists.
     pub fn insert(&self, key: K, value: V) {
         let mut prev = [ptr::null_mut(); MAX_HEIGHT];
@@ -206,6 +255,7 @@ impl<K: Ord + Default, V: Default> SkipList<K, V> {
     }
 
     /// Retrieves a reference to the value if the key exists.
+    #[allow(dead_code)]
     pub fn get(&self, key: &K) -> Option<&V> {
         let node = self.find_greater_or_equal(key, None);
         if !node.is_null() && unsafe { (*node).key == *key } {
@@ -228,6 +278,26 @@ impl<K: Ord + Default, V: Default> SkipList<K, V> {
             _phantom: PhantomData,
         }
     }
+
+    /// Iterator starting at the first element whose key is >= `key`.
+    ///
+    /// Reuses the O(log n) descent from `find_greater_or_equal`, so a range scan never walks
+    /// the level-0 chain from the head just to reach its start key.
+    pub fn iter_from(&self, key: &K) -> SkipListIterator<'_, K, V> {
+        SkipListIterator {
+            current: self.find_greater_or_equal(key, None),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// A positioned cursor that can move in both directions. It starts out invalid; call one
+    /// of the `seek` methods first.
+    pub fn cursor(&self) -> SkipListCursor<'_, K, V> {
+        SkipListCursor {
+            list: self,
+            node: ptr::null_mut(),
+        }
+    }
 }
 
 impl<K, V> Drop for SkipList<K, V> {
@@ -270,6 +340,69 @@ impl<'a, K: 'a, V: 'a> Iterator for SkipListIterator<'a, K, V> {
     }
 }
 
+/// A bidirectional cursor over a [`SkipList`], in the LevelDB style: it sits *on* an entry
+/// (or is invalid) and `next`/`prev` move it.
+///
+/// `next` follows the level-0 link in O(1); `prev` re-descends from the head in O(log n)
+/// because nodes carry no back-pointers.
+pub struct SkipListCursor<'a, K, V> {
+    list: &'a SkipList<K, V>,
+    node: *mut Node<K, V>,
+}
+
+impl<'a, K: Ord + Default, V: Default> SkipListCursor<'a, K, V> {
+    pub fn valid(&self) -> bool {
+        !self.node.is_null()
+    }
+
+    /// The current key. Panics if the cursor is not valid.
+    pub fn key(&self) -> &'a K {
+        assert!(self.valid(), "SkipListCursor::key on an invalid cursor");
+        unsafe { &(*self.node).key }
+    }
+
+    /// The current value. Panics if the cursor is not valid.
+    pub fn value(&self) -> &'a V {
+        assert!(self.valid(), "SkipListCursor::value on an invalid cursor");
+        unsafe { &(*self.node).value }
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.node = unsafe { (*self.list.head).next_ptr(0).load(Ordering::Acquire) };
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.node = self.list.find_last();
+    }
+
+    /// Positions at the first entry whose key is >= `key`.
+    pub fn seek(&mut self, key: &K) {
+        self.node = self.list.find_greater_or_equal(key, None);
+    }
+
+    /// Positions at the last entry whose key is <= `key`.
+    pub fn seek_for_prev(&mut self, key: &K) {
+        let node = self.list.find_greater_or_equal(key, None);
+        self.node = if !node.is_null() && unsafe { (*node).key == *key } {
+            node
+        } else {
+            self.list.find_less_than(key)
+        };
+    }
+
+    pub fn next(&mut self) {
+        if self.valid() {
+            self.node = unsafe { (*self.node).next_ptr(0).load(Ordering::Acquire) };
+        }
+    }
+
+    pub fn prev(&mut self) {
+        if self.valid() {
+            self.node = self.list.find_less_than(unsafe { &(*self.node).key });
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -315,4 +448,62 @@ mod tests {
         assert_eq!(iter.next(), Some((&4, &"four")));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_skip_list_iter_from() {
+        let list = SkipList::new();
+
+        for i in [10, 30, 20, 50, 40] {
+            list.insert(i, i * 10);
+        }
+
+        let keys: Vec<i32> = list.iter_from(&25).map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![30, 40, 50]);
+
+        // An exact match is included.
+        assert_eq!(list.iter_from(&40).next(), Some((&40, &400)));
+
+        // Past the end yields nothing.
+        assert_eq!(list.iter_from(&99).next(), None);
+    }
+
+    #[test]
+    fn test_skip_list_cursor_both_directions() {
+        let list = SkipList::new();
+        for i in [10, 30, 20, 50, 40] {
+            list.insert(i, i * 10);
+        }
+
+        let mut cursor = list.cursor();
+        assert!(!cursor.valid());
+
+        cursor.seek_to_last();
+        let mut keys = Vec::new();
+        while cursor.valid() {
+            keys.push(*cursor.key());
+            cursor.prev();
+        }
+        assert_eq!(keys, vec![50, 40, 30, 20, 10]);
+
+        cursor.seek_for_prev(&35);
+        assert_eq!(*cursor.key(), 30);
+        cursor.seek_for_prev(&40);
+        assert_eq!(*cursor.key(), 40);
+        cursor.next();
+        assert_eq!(*cursor.value(), 500);
+        cursor.seek_for_prev(&5);
+        assert!(!cursor.valid());
+
+        cursor.seek(&35);
+        assert_eq!(*cursor.key(), 40);
+        cursor.prev();
+        assert_eq!(*cursor.key(), 30);
+
+        let empty: SkipList<i32, i32> = SkipList::new();
+        let mut cursor = empty.cursor();
+        cursor.seek_to_last();
+        assert!(!cursor.valid());
+        cursor.seek_to_first();
+        assert!(!cursor.valid());
+    }
 }
@@ -26,27 +26,96 @@ fn test_set_and_get() {
     let mut m = MemTable::new(1024 * 1024, 0.01);
     let kv = generate_dummy_kv_pairs();
 
-    for (k, v) in &kv {
-        m.set(k.clone(), v.clone());
+    for (seq, (k, v)) in kv.iter().enumerate() {
+        m.set(k.clone(), seq as u64 + 1, ValueType::Value, v.clone());
     }
 
     for (k, v) in &kv {
-        assert_eq!(m.get(k).unwrap(), v);
+        assert_eq!(m.get(k, MAX_SEQUENCE).unwrap().2, v);
     }
 
     // Non-existent key
-    assert_eq!(m.get(b"missing"), None);
+    assert_eq!(m.get(b"missing", MAX_SEQUENCE), None);
 }
 
 #[test]
 fn test_overwrite_key() {
     let mut m = MemTable::new(1024 * 1024, 0.01);
 
-    m.set(b"key".to_vec(), b"v1".to_vec());
-    assert_eq!(m.get(b"key").unwrap(), b"v1");
+    m.set(b"key".to_vec(), 1, ValueType::Value, b"v1".to_vec());
+    assert_eq!(m.get(b"key", MAX_SEQUENCE).unwrap().2, b"v1");
+
+    m.set(b"key".to_vec(), 2, ValueType::Value, b"v2".to_vec());
+    assert_eq!(m.get(b"key", MAX_SEQUENCE).unwrap().2, b"v2");
+}
+
+#[test]
+fn test_get_at_sequence() {
+    let mut m = MemTable::new(1024 * 1024, 0.01);
 
-    m.set(b"key".to_vec(), b"v2".to_vec());
-    assert_eq!(m.get(b"key").unwrap(), b"v2");
+    m.set(b"key".to_vec(), 10, ValueType::Value, b"v10".to_vec());
+    m.set(b"key".to_vec(), 20, ValueType::Value, b"v20".to_vec());
+    m.set(b"key0".to_vec(), 5, ValueType::Value, b"other".to_vec());
+
+    // Both versions are retained; a read sees the newest one at or before its sequence.
+    assert_eq!(m.entries().len(), 3);
+    assert_eq!(m.get(b"key", 25).unwrap().2, b"v20");
+    assert_eq!(m.get(b"key", 20).unwrap().2, b"v20");
+    assert_eq!(m.get(b"key", 19).unwrap().2, b"v10");
+    // Older than both: the next entry in order belongs to "key0" and must not leak through.
+    assert_eq!(m.get(b"key", 9), None);
+
+    // A deletion is a version like any other, distinguished by its type rather than its value.
+    m.set(b"key".to_vec(), 30, ValueType::Deletion, Vec::new());
+    assert_eq!(m.get(b"key", 30).unwrap().0, ValueType::Deletion);
+    assert_eq!(
+        m.get(b"key", 29).unwrap(),
+        (ValueType::Value, 20, &b"v20".to_vec())
+    );
+}
+
+#[test]
+fn test_range_tombstones() {
+    let mut m = MemTable::new(1024 * 1024, 0.01);
+    m.set(b"user:1".to_vec(), 1, ValueType::Value, b"a".to_vec());
+    m.delete_range(b"user:".to_vec(), b"user;".to_vec(), 2);
+    m.delete_range(b"user:0".to_vec(), b"user:5".to_vec(), 4);
+
+    // The newest tombstone covering the key that the reader can see.
+    assert_eq!(m.max_covering_tombstone_seq(b"user:1", MAX_SEQUENCE), 4);
+    assert_eq!(m.max_covering_tombstone_seq(b"user:1", 3), 2);
+    assert_eq!(m.max_covering_tombstone_seq(b"user:1", 1), 0);
+    assert_eq!(m.max_covering_tombstone_seq(b"user:7", MAX_SEQUENCE), 2);
+    // `end` is exclusive.
+    assert_eq!(m.max_covering_tombstone_seq(b"user;", MAX_SEQUENCE), 0);
+
+    // The point entry itself is untouched; readers combine the two.
+    assert_eq!(m.get(b"user:1", MAX_SEQUENCE).unwrap().2, b"a");
+    assert_eq!(m.range_tombstones().len(), 2);
+}
+
+#[test]
+fn test_latest_sequence() {
+    let mut m = MemTable::new(1024 * 1024, 0.01);
+    assert!(!m.covers_writes_after(0));
+
+    m.set(b"a".to_vec(), 5, ValueType::Value, b"1".to_vec());
+    m.set(b"a".to_vec(), 7, ValueType::Deletion, vec![]);
+    m.delete_range(b"b".to_vec(), b"c".to_vec(), 9);
+    m.set(b"b".to_vec(), 8, ValueType::Value, b"2".to_vec());
+
+    assert_eq!(m.latest_sequence(b"a"), Some(7));
+    // A range tombstone counts as a write to every key it covers.
+    assert_eq!(m.latest_sequence(b"b"), Some(9));
+    assert_eq!(m.latest_sequence(b"bb"), Some(9));
+    assert_eq!(m.latest_sequence(b"c"), None);
+
+    // Writes 5 onwards are all here, earlier ones may be in older layers.
+    assert!(m.covers_writes_after(4));
+    assert!(m.covers_writes_after(8));
+    assert!(!m.covers_writes_after(3));
+    m.clear();
+    assert!(!m.covers_writes_after(4));
 }
 
 #[test]
@@ -58,8 +127,18 @@ fn test_needs_flush() {
 
     // Insert a huge payload bypassing natural memory limit (which allocates an entire block via Arena)
     // We insert slightly more than 4MB.
-    m.set(b"massive_key".to_vec(), vec![0u8; 4_000_000]);
-    m.set(b"another_key".to_vec(), vec![0u8; 300_000]);
+    m.set(
+        b"massive_key".to_vec(),
+        1,
+        ValueType::Value,
+        vec![0u8; 4_000_000],
+    );
+    m.set(
+        b"another_key".to_vec(),
+        2,
+        ValueType::Value,
+        vec![0u8; 300_000],
+    );
 
     // The arena easily exceeded 4MB capacity.
     assert!(m.needs_flush());
@@ -68,14 +147,31 @@ fn test_needs_flush() {
 #[test]
 fn test_concurrent_reads() {
     let mut m = MemTable::new(1024 * 1024, 0.01);
-    m.set(b"key".to_vec(), b"value".to_vec());
+    m.set(b"key".to_vec(), 1, ValueType::Value, b"value".to_vec());
 
     let m_ref = &m;
     std::thread::scope(|s| {
         for _ in 0..10 {
             s.spawn(move || {
-                assert_eq!(m_ref.get(b"key").unwrap(), b"value");
+                assert_eq!(m_ref.get(b"key", MAX_SEQUENCE).unwrap().2, b"value");
             });
         }
     });
 }
+
+#[test]
+fn test_scan_from() {
+    let mut m = MemTable::new(1024 * 1024, 0.01);
+    for (seq, (k, v)) in generate_dummy_kv_pairs().into_iter().enumerate() {
+        m.set(k, seq as u64 + 1, ValueType::Value, v);
+    }
+
+    let keys: Vec<&[u8]> = m
+        .scan_from(b"key2")
+        .map(|(k, _)| internal_key::user_key(k))
+        .collect();
+    assert_eq!(keys, vec![b"key2".as_slice(), b"key3".as_slice()]);
+
+    assert_eq!(m.scan_from(b"key4").count(), 0);
+    assert_eq!(m.scan_from(b"").count(), 3);
+}
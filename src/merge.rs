@@ -0,0 +1,267 @@
+use crate::internal_key::ValueType;
+use crate::ttl;
+use std::fmt;
+
+/// Folds the operands written by [`StorageEngine::merge`](crate::StorageEngine::merge) into a
+/// value, configured through [`Options::merge_operator`](crate::Options::merge_operator).
+///
+/// A merge stores the operand as-is and returns, so a read-modify-write such as incrementing
+/// a counter costs one write instead of a `get` plus a `put`. Reads fold the operands lazily,
+/// and compaction collapses them so they do not pile up on disk.
+///
+/// ```
+/// use lsmdb::MergeOperator;
+///
+/// /// Adds little-endian `u64` deltas to a counter.
+/// struct Counter;
+///
+/// fn decode(bytes: &[u8]) -> u64 {
+///     bytes.try_into().map(u64::from_le_bytes).unwrap_or(0)
+/// }
+///
+/// impl MergeOperator for Counter {
+///     fn name(&self) -> &str {
+///         "counter"
+///     }
+///
+///     fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8> {
+///         let total = operands.iter().map(|op| decode(op)).sum::<u64>();
+///         (existing.map_or(0, decode) + total).to_le_bytes().to_vec()
+///     }
+///
+///     // Addition is associative, so deltas can be summed before the base value is known.
+///     fn partial_merge(&self, _key: &[u8], older: &[u8], newer: &[u8]) -> Option<Vec<u8>> {
+///         Some((decode(older) + decode(newer)).to_le_bytes().to_vec())
+///     }
+/// }
+/// ```
+pub trait MergeOperator: Send + Sync {
+    /// Identifies the operator in `Debug` output.
+    fn name(&self) -> &str;
+
+    /// Applies `operands`, oldest first, on top of `existing`: the value the key held before
+    /// the first of them, or `None` if it had none (never written, or deleted).
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8>;
+
+    /// Combines two adjacent operands into one without knowing the value beneath them, or
+    /// returns `None` if that is not possible.
+    ///
+    /// Compaction uses this to collapse operands whose base value lives in a table it is not
+    /// rewriting. The default keeps every operand until one compaction sees the base value.
+    fn partial_merge(&self, _key: &[u8], _older: &[u8], _newer: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MergeOperator").field(&self.name()).finish()
+    }
+}
+
+/// Applies `operands`, oldest first, on top of `existing`.
+///
+/// Without an operator — a database reopened without the one its merges were written with —
+/// the operands are treated as plain values, so the newest one wins.
+pub(crate) fn full_merge(
+    operator: Option<&dyn MergeOperator>,
+    key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &[Vec<u8>],
+) -> Vec<u8> {
+    match operator {
+        Some(operator) => {
+            let operands: Vec<&[u8]> = operands.iter().map(Vec::as_slice).collect();
+            operator.full_merge(key, existing, &operands)
+        }
+        None => operands.last().cloned().unwrap_or_default(),
+    }
+}
+
+/// Collapses `operands`, oldest first, into a single operand, or returns `None` if the
+/// operator cannot.
+pub(crate) fn partial_merge_all(
+    operator: Option<&dyn MergeOperator>,
+    key: &[u8],
+    operands: &[Vec<u8>],
+) -> Option<Vec<u8>> {
+    let operator = operator?;
+    let (oldest, newer) = operands.split_first()?;
+    newer.iter().try_fold(oldest.clone(), |acc, operand| {
+        operator.partial_merge(key, &acc, operand)
+    })
+}
+
+/// The versions of one key a point lookup collects on its way down the layers: merge
+/// operands, newest first, until a value or tombstone settles the read.
+pub(crate) struct MergeContext {
+    // Only versions at or before this sequence are still unread. After an operand it drops
+    // just below it, so the next lookup in the same layer finds the version beneath.
+    seq: u64,
+    // Versions older than this are deleted by a range tombstone found on the way down.
+    deleted_below: u64,
+    // The time expiring values are judged against, fixed for the whole read.
+    now: u64,
+    operands: Vec<Vec<u8>>,
+    base: Option<Vec<u8>>,
+}
+
+impl MergeContext {
+    pub(crate) fn new(seq: u64, now: u64) -> Self {
+        Self {
+            seq,
+            deleted_below: 0,
+            now,
+            operands: Vec::new(),
+            base: None,
+        }
+    }
+
+    /// The sequence number the next lookup must read at.
+    pub(crate) fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// Reads one layer. `range_deletion_seq` is the newest range tombstone in that layer
+    /// covering the key and visible to the read (0 if none), and `lookup(seq)` must return the
+    /// newest version of the key in that layer written at or before `seq`, as
+    /// `(type, sequence, value)`.
+    ///
+    /// Returns true once a value or tombstone has settled the read, so older layers need not
+    /// be searched.
+    pub(crate) fn search(
+        &mut self,
+        range_deletion_seq: u64,
+        mut lookup: impl FnMut(u64) -> Option<(ValueType, u64, Vec<u8>)>,
+    ) -> bool {
+        match self.try_search(range_deletion_seq, |seq| {
+            Ok::<_, std::convert::Infallible>(lookup(seq))
+        }) {
+            Ok(settled) => settled,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`search`](Self::search), for a layer whose lookups can fail — an SSTable may turn
+    /// out to be damaged. The first failure ends the search.
+    pub(crate) fn try_search<E>(
+        &mut self,
+        range_deletion_seq: u64,
+        mut lookup: impl FnMut(u64) -> Result<Option<(ValueType, u64, Vec<u8>)>, E>,
+    ) -> Result<bool, E> {
+        // A range tombstone also hides older versions in the layers below its own.
+        self.deleted_below = self.deleted_below.max(range_deletion_seq);
+        while let Some((value_type, seq, value)) = lookup(self.seq)? {
+            if seq < self.deleted_below {
+                return Ok(true);
+            }
+            match value_type {
+                ValueType::Value => {
+                    self.base = Some(value);
+                    return Ok(true);
+                }
+                ValueType::ExpiringValue => {
+                    if !ttl::is_expired(&value, self.now) {
+                        self.base = Some(ttl::decode(&value).1.to_vec());
+                    }
+                    return Ok(true);
+                }
+                ValueType::Deletion | ValueType::RangeDeletion => return Ok(true),
+                ValueType::Merge => {
+                    self.operands.push(value);
+                    // Sequence numbers start at 1; nothing can sit beneath sequence 0.
+                    let Some(below) = seq.checked_sub(1) else {
+                        return Ok(true);
+                    };
+                    self.seq = below;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// The value the key reads as, or `None` if it is absent or deleted.
+    pub(crate) fn finish(
+        mut self,
+        operator: Option<&dyn MergeOperator>,
+        key: &[u8],
+    ) -> Option<Vec<u8>> {
+        if self.operands.is_empty() {
+            return self.base;
+        }
+        self.operands.reverse();
+        Some(full_merge(
+            operator,
+            key,
+            self.base.as_deref(),
+            &self.operands,
+        ))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Joins operands with commas, so a result shows exactly what was folded and in which
+    /// order.
+    pub(crate) struct Append;
+
+    impl MergeOperator for Append {
+        fn name(&self) -> &str {
+            "append"
+        }
+
+        fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8> {
+            let mut parts: Vec<&[u8]> = existing.into_iter().collect();
+            parts.extend_from_slice(operands);
+            parts.join(&b","[..])
+        }
+
+        fn partial_merge(&self, _key: &[u8], older: &[u8], newer: &[u8]) -> Option<Vec<u8>> {
+            Some([older, newer].join(&b","[..]))
+        }
+    }
+
+    #[test]
+    fn test_merge_context_reads_down_to_the_base() {
+        // One layer's versions of a key, newest first: two operands on top of a value.
+        let layer = [
+            (ValueType::Merge, 30, b"c".to_vec()),
+            (ValueType::Merge, 20, b"b".to_vec()),
+            (ValueType::Value, 10, b"a".to_vec()),
+        ];
+        let lookup = |seq: u64| layer.iter().find(|(_, s, _)| *s <= seq).cloned();
+
+        let mut context = MergeContext::new(25, 0);
+        assert!(context.search(0, lookup));
+        assert_eq!(context.finish(Some(&Append), b"k"), Some(b"a,b".to_vec()));
+
+        // Operands with nothing beneath them in this layer leave the read open for the next.
+        let mut context = MergeContext::new(30, 0);
+        assert!(!context.search(0, |seq| lookup(seq).filter(|(_, s, _)| *s > 10)));
+        assert!(context.search(0, |_| Some((ValueType::Deletion, 5, Vec::new()))));
+        assert_eq!(context.finish(Some(&Append), b"k"), Some(b"b,c".to_vec()));
+
+        // Without an operator the newest operand wins.
+        let mut context = MergeContext::new(30, 0);
+        assert!(context.search(0, lookup));
+        assert_eq!(context.finish(None, b"k"), Some(b"c".to_vec()));
+
+        // A range tombstone at 25 deletes the value and the older operand beneath it.
+        let mut context = MergeContext::new(30, 0);
+        assert!(context.search(25, lookup));
+        assert_eq!(context.finish(Some(&Append), b"k"), Some(b"c".to_vec()));
+
+        // An expiring base is folded onto until its deadline, and reads as deleted after.
+        let expiring = || Some((ValueType::ExpiringValue, 10, ttl::encode(100, b"a")));
+        let mut context = MergeContext::new(30, 99);
+        assert!(!context.search(0, |seq| lookup(seq).filter(|(_, s, _)| *s > 10)));
+        assert!(context.search(0, |_| expiring()));
+        assert_eq!(context.finish(Some(&Append), b"k"), Some(b"a,b,c".to_vec()));
+        let mut context = MergeContext::new(30, 100);
+        assert!(!context.search(0, |seq| lookup(seq).filter(|(_, s, _)| *s > 10)));
+        assert!(context.search(0, |_| expiring()));
+        assert_eq!(context.finish(Some(&Append), b"k"), Some(b"b,c".to_vec()));
+    }
+}
@@ -0,0 +1,381 @@
+use crate::Error;
+use crate::constants::{
+    BLOCK_CACHE_CAPACITY, BLOOM_FILTER_FPR, FLUSH_ON_CLOSE, L0_COMPACTION_TRIGGER, L1_MAX_BYTES,
+    LEVEL_SIZE_MULTIPLIER, MAX_COMPRESSED_BLOCK_SIZE, MAX_LEVELS, MEMTABLE_CAPACITY_BYTES,
+    SSTABLE_BLOCK_SIZE, SSTABLE_RESTART_INTERVAL, WAL_SYNC_ON_WRITE,
+};
+use crate::merge::MergeOperator;
+use std::sync::Arc;
+
+/// Runtime tuning for a single [`StorageEngine`](crate::StorageEngine) instance.
+///
+/// Every tuning field defaults to the matching constant in [`constants`](crate::constants), so
+/// `Options::default()` behaves exactly like [`StorageEngine::open`](crate::StorageEngine::open).
+/// Keeping the knobs on a value rather than in `const`s lets two databases in the same process
+/// be tuned for different workloads (e.g. a write-heavy log next to a read-heavy index).
+///
+/// Override only what you need with struct update syntax:
+///
+/// ```
+/// use lsmdb::Options;
+///
+/// let opts = Options {
+///     memtable_capacity_bytes: 8 * 1024 * 1024,
+///     wal_sync_on_write: false,
+///     ..Options::default()
+/// };
+/// assert!(opts.validate().is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// See [`MEMTABLE_CAPACITY_BYTES`](crate::constants::MEMTABLE_CAPACITY_BYTES).
+    pub memtable_capacity_bytes: usize,
+    /// See [`BLOOM_FILTER_FPR`](crate::constants::BLOOM_FILTER_FPR).
+    pub bloom_filter_fpr: f64,
+    /// See [`WAL_SYNC_ON_WRITE`](crate::constants::WAL_SYNC_ON_WRITE).
+    pub wal_sync_on_write: bool,
+    /// See [`FLUSH_ON_CLOSE`](crate::constants::FLUSH_ON_CLOSE).
+    pub flush_on_close: bool,
+    /// See [`L0_COMPACTION_TRIGGER`](crate::constants::L0_COMPACTION_TRIGGER).
+    pub l0_compaction_trigger: usize,
+    /// See [`BLOCK_CACHE_CAPACITY`](crate::constants::BLOCK_CACHE_CAPACITY).
+    pub block_cache_capacity: usize,
+    /// See [`MAX_LEVELS`](crate::constants::MAX_LEVELS).
+    pub max_levels: usize,
+    /// See [`LEVEL_SIZE_MULTIPLIER`](crate::constants::LEVEL_SIZE_MULTIPLIER).
+    pub level_size_multiplier: usize,
+    /// See [`L1_MAX_BYTES`](crate::constants::L1_MAX_BYTES).
+    pub l1_max_bytes: u64,
+    /// See [`SSTABLE_BLOCK_SIZE`](crate::constants::SSTABLE_BLOCK_SIZE).
+    pub sstable_block_size: usize,
+    /// See [`SSTABLE_RESTART_INTERVAL`](crate::constants::SSTABLE_RESTART_INTERVAL).
+    pub sstable_restart_interval: usize,
+    /// Folds the operands written by [`StorageEngine::merge`](crate::StorageEngine::merge).
+    /// `None` (the default) rejects merges.
+    ///
+    /// A database holding merge operands must be reopened with the same operator. Without
+    /// one, the operands read back as plain values and the newest one wins.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// What opening the database does with an SSTable that is damaged. Only the database's
+    /// options count; a column family's are ignored.
+    pub corrupt_sstable_policy: CorruptSSTablePolicy,
+    /// How data blocks are compressed on every level that
+    /// [`compression_per_level`](Self::compression_per_level) does not name.
+    pub compression: CompressionType,
+    /// How data blocks are compressed on each level: entry `i` for level `i`. Levels past the
+    /// end of the list use [`compression`](Self::compression); the default, an empty list,
+    /// leaves every level to it.
+    ///
+    /// Upper levels are rewritten often and read hot, so they favour cheap decoding; the bottom
+    /// levels hold most of the data and are rarely rewritten, so they repay a slower codec
+    /// with a better ratio:
+    ///
+    /// ```
+    /// use lsmdb::{CompressionType, Options};
+    ///
+    /// let opts = Options {
+    ///     compression: CompressionType::Zstd(6),
+    ///     compression_per_level: vec![CompressionType::None, CompressionType::Lz4],
+    ///     ..Options::default()
+    /// };
+    /// assert!(opts.validate().is_ok());
+    /// ```
+    pub compression_per_level: Vec<CompressionType>,
+}
+
+/// How the data blocks of an SSTable are compressed; see [`Options::compression`].
+///
+/// A block that compression would not shrink by at least an eighth is stored uncompressed,
+/// since every read of it would pay for the decompression. Each block records its own type,
+/// so changing the options applies to tables written from then on, and older tables stay
+/// readable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    /// Store blocks as they are.
+    None,
+    /// Snappy: fast in both directions, at a modest ratio.
+    #[default]
+    Snappy,
+    /// LZ4: a ratio close to Snappy's, and faster to decompress.
+    Lz4,
+    /// Zstandard at the given level, from 1 (fastest) to 22 (smallest); negative levels trade
+    /// ratio for even more speed. Decompression stays fast at every level.
+    Zstd(i32),
+}
+
+/// How [`StorageEngine::open`](crate::StorageEngine::open) treats an SSTable that does not
+/// open: one too short for its footer, or whose index, Bloom Filter or range tombstones do not
+/// decode. Damage inside a data block only shows once the block is read, and fails that read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CorruptSSTablePolicy {
+    /// Fail the open with [`Error::Corruption`], leaving the files as they are.
+    #[default]
+    Fail,
+    /// Move the file into the database's `lost` directory, drop it from the MANIFEST and open
+    /// without it. Its writes are gone, and older versions of their keys in deeper levels may
+    /// show through again. A read-only or secondary instance leaves the file in place and only
+    /// skips it. [`quarantined_sstables`](crate::StorageEngine::quarantined_sstables) lists
+    /// the tables skipped.
+    Quarantine,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            memtable_capacity_bytes: MEMTABLE_CAPACITY_BYTES,
+            bloom_filter_fpr: BLOOM_FILTER_FPR,
+            wal_sync_on_write: WAL_SYNC_ON_WRITE,
+            flush_on_close: FLUSH_ON_CLOSE,
+            l0_compaction_trigger: L0_COMPACTION_TRIGGER,
+            block_cache_capacity: BLOCK_CACHE_CAPACITY,
+            max_levels: MAX_LEVELS,
+            level_size_multiplier: LEVEL_SIZE_MULTIPLIER,
+            l1_max_bytes: L1_MAX_BYTES,
+            sstable_block_size: SSTABLE_BLOCK_SIZE,
+            sstable_restart_interval: SSTABLE_RESTART_INTERVAL,
+            merge_operator: None,
+            corrupt_sstable_policy: CorruptSSTablePolicy::default(),
+            compression: CompressionType::default(),
+            compression_per_level: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Rejects values that would make the engine misbehave rather than merely perform badly.
+    ///
+    /// Called by [`StorageEngine::open_with_options`](crate::StorageEngine::open_with_options)
+    /// before anything touches the disk, so a bad configuration fails loudly at startup instead
+    /// of surfacing later as a panic on a background flush thread.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.memtable_capacity_bytes == 0 {
+            return Err(Error::invalid(
+                "Invalid options: memtable_capacity_bytes must be greater than zero",
+            ));
+        }
+
+        // An FPR of 0 would require an infinitely large filter; 1 or above makes the filter
+        // answer "maybe" for every key, so it would cost memory and save nothing.
+        if !(self.bloom_filter_fpr > 0.0 && self.bloom_filter_fpr < 1.0) {
+            return Err(Error::invalid(format!(
+                "Invalid options: bloom_filter_fpr must be in the open range (0, 1), got {}",
+                self.bloom_filter_fpr
+            )));
+        }
+
+        if self.l0_compaction_trigger == 0 {
+            return Err(Error::invalid(
+                "Invalid options: l0_compaction_trigger must be at least 1",
+            ));
+        }
+
+        if self.block_cache_capacity == 0 {
+            return Err(Error::invalid(
+                "Invalid options: block_cache_capacity must be at least 1",
+            ));
+        }
+
+        // Compaction always moves data from level N to N+1, so a single level leaves L0 with
+        // nowhere to go and it would grow without bound.
+        if self.max_levels < 2 {
+            return Err(Error::invalid(format!(
+                "Invalid options: max_levels must be at least 2, got {}",
+                self.max_levels
+            )));
+        }
+
+        if self.level_size_multiplier < 2 {
+            return Err(Error::invalid(format!(
+                "Invalid options: level_size_multiplier must be at least 2, got {}",
+                self.level_size_multiplier
+            )));
+        }
+
+        if self.l1_max_bytes == 0 {
+            return Err(Error::invalid(
+                "Invalid options: l1_max_bytes must be greater than zero",
+            ));
+        }
+
+        // The deepest level's budget is L1 × multiplier^(max_levels - 2). If that overflows a
+        // u64 the budget arithmetic in compaction would wrap or panic.
+        let deepest_budget = (self.level_size_multiplier as u64)
+            .checked_pow(self.max_levels.saturating_sub(2) as u32)
+            .and_then(|m| m.checked_mul(self.l1_max_bytes));
+        if deepest_budget.is_none() {
+            return Err(Error::invalid(
+                "Invalid options: l1_max_bytes × level_size_multiplier^(max_levels - 2) overflows",
+            ));
+        }
+
+        // A single flushed MemTable already exceeds L1's budget, so every L0→L1 compaction
+        // would immediately cascade into L2 and L1 would never hold data.
+        if self.l1_max_bytes < self.memtable_capacity_bytes as u64 {
+            return Err(Error::invalid(format!(
+                "Invalid options: l1_max_bytes ({}) must be at least memtable_capacity_bytes ({})",
+                self.l1_max_bytes, self.memtable_capacity_bytes
+            )));
+        }
+
+        if self.sstable_block_size == 0 {
+            return Err(Error::invalid(
+                "Invalid options: sstable_block_size must be greater than zero",
+            ));
+        }
+
+        // Larger blocks would all be stored uncompressed.
+        if self.sstable_block_size > MAX_COMPRESSED_BLOCK_SIZE {
+            return Err(Error::invalid(format!(
+                "Invalid options: sstable_block_size ({}) must be at most {}",
+                self.sstable_block_size, MAX_COMPRESSED_BLOCK_SIZE
+            )));
+        }
+
+        if self.sstable_restart_interval == 0 {
+            return Err(Error::invalid(
+                "Invalid options: sstable_restart_interval must be at least 1",
+            ));
+        }
+
+        let zstd_levels = zstd::compression_level_range();
+        for compression in std::iter::once(&self.compression).chain(&self.compression_per_level) {
+            if let CompressionType::Zstd(level) = *compression
+                && !zstd_levels.contains(&level)
+            {
+                return Err(Error::invalid(format!(
+                    "Invalid options: Zstd compression level must be in {:?}, got {}",
+                    zstd_levels, level
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How data blocks written to `level` are compressed.
+    pub(crate) fn compression_for_level(&self, level: usize) -> CompressionType {
+        self.compression_per_level
+            .get(level)
+            .copied()
+            .unwrap_or(self.compression)
+    }
+}
+
+/// Options for a single read, passed to the `_opt` variants of the read methods, such as
+/// [`get_opt`](crate::StorageEngine::get_opt) and [`scan_opt`](crate::StorageEngine::scan_opt).
+/// The other read methods use the defaults.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadOptions {
+    /// Check each SSTable data block read from disk against its CRC32C checksum, failing the
+    /// read with [`Error::Corruption`] on a mismatch. On by default; turning it off saves the
+    /// checksum computation on reads that can live with damaged data. Blocks served from the
+    /// block cache were checked when they were first read, if that read checked them.
+    /// Compaction always checks.
+    pub verify_checksums: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            verify_checksums: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_options_are_valid() {
+        assert!(Options::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_rejects_zero_values() {
+        let opts = Options {
+            memtable_capacity_bytes: 0,
+            ..Options::default()
+        };
+        assert!(opts.validate().is_err());
+
+        let opts = Options {
+            sstable_restart_interval: 0,
+            ..Options::default()
+        };
+        assert!(opts.validate().is_err());
+
+        let opts = Options {
+            block_cache_capacity: 0,
+            ..Options::default()
+        };
+        assert!(opts.validate().is_err());
+    }
+
+    #[test]
+    fn test_rejects_blocks_too_large_to_compress() {
+        let opts = Options {
+            sstable_block_size: MAX_COMPRESSED_BLOCK_SIZE,
+            ..Options::default()
+        };
+        assert!(opts.validate().is_ok());
+
+        let opts = Options {
+            sstable_block_size: MAX_COMPRESSED_BLOCK_SIZE + 1,
+            ..Options::default()
+        };
+        assert!(opts.validate().is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_fpr() {
+        for fpr in [0.0, 1.0, -0.5, f64::NAN] {
+            let opts = Options {
+                bloom_filter_fpr: fpr,
+                ..Options::default()
+            };
+            assert!(opts.validate().is_err(), "fpr {} should be rejected", fpr);
+        }
+    }
+
+    #[test]
+    fn test_rejects_nonsensical_combinations() {
+        // L1 smaller than one MemTable.
+        let opts = Options {
+            memtable_capacity_bytes: 64 * 1024 * 1024,
+            l1_max_bytes: 1024 * 1024,
+            ..Options::default()
+        };
+        assert!(opts.validate().is_err());
+
+        // Deepest level budget overflows u64.
+        let opts = Options {
+            max_levels: 40,
+            ..Options::default()
+        };
+        assert!(opts.validate().is_err());
+    }
+
+    #[test]
+    fn test_compression_per_level() {
+        let opts = Options {
+            compression: CompressionType::Zstd(19),
+            compression_per_level: vec![CompressionType::None, CompressionType::Lz4],
+            ..Options::default()
+        };
+        assert!(opts.validate().is_ok());
+        assert_eq!(opts.compression_for_level(0), CompressionType::None);
+        assert_eq!(opts.compression_for_level(1), CompressionType::Lz4);
+        assert_eq!(opts.compression_for_level(6), CompressionType::Zstd(19));
+        assert_eq!(
+            Options::default().compression_for_level(3),
+            CompressionType::Snappy
+        );
+
+        let opts = Options {
+            compression_per_level: vec![CompressionType::Zstd(100)],
+            ..Options::default()
+        };
+        assert!(opts.validate().is_err());
+    }
+}
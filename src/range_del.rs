@@ -0,0 +1,167 @@
+//! Range tombstones: one entry deleting every key in `[start, end)` written before it.
+//!
+//! A range tombstone is a version like a point tombstone, with a sequence number of its own: it
+//! hides a version of a covered key only if that version is older than the tombstone, and only
+//! from readers whose sequence number is at or after it. Tombstones are kept apart from the
+//! point entries — in their own list in the MemTable and their own block in each SSTable —
+//! because they cover keys that sort nowhere near their own start key.
+
+/// Deletes every key in `start..end` written before sequence number `seq`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RangeTombstone {
+    pub(crate) start: Vec<u8>,
+    pub(crate) end: Vec<u8>,
+    pub(crate) seq: u64,
+}
+
+impl RangeTombstone {
+    pub(crate) fn covers(&self, key: &[u8]) -> bool {
+        self.start.as_slice() <= key && key < self.end.as_slice()
+    }
+}
+
+/// A set of range tombstones, cut into non-overlapping fragments so that the ones covering a
+/// key are found with one binary search instead of a scan over every tombstone.
+///
+/// Tombstones `[a, e)@5` and `[c, g)@9` become the fragments `[a, c)@{5}`, `[c, e)@{5, 9}` and
+/// `[e, g)@{9}`. The original tombstones are kept as well, for writing them back out.
+#[derive(Debug, Default)]
+pub(crate) struct RangeTombstones {
+    tombstones: Vec<RangeTombstone>,
+    fragments: Vec<Fragment>,
+}
+
+#[derive(Debug)]
+struct Fragment {
+    start: Vec<u8>,
+    end: Vec<u8>,
+    // The sequence numbers of every tombstone covering the fragment, ascending.
+    seqs: Vec<u64>,
+}
+
+impl RangeTombstones {
+    pub(crate) fn new(mut tombstones: Vec<RangeTombstone>) -> Self {
+        tombstones.retain(|t| t.start < t.end);
+        tombstones.sort_by(|a, b| a.start.cmp(&b.start).then(b.seq.cmp(&a.seq)));
+        tombstones.dedup();
+
+        let mut bounds: Vec<&[u8]> = tombstones
+            .iter()
+            .flat_map(|t| [t.start.as_slice(), t.end.as_slice()])
+            .collect();
+        bounds.sort();
+        bounds.dedup();
+
+        let mut fragments: Vec<Fragment> = bounds
+            .windows(2)
+            .map(|w| Fragment {
+                start: w[0].to_vec(),
+                end: w[1].to_vec(),
+                seqs: Vec::new(),
+            })
+            .collect();
+        for tombstone in &tombstones {
+            let first = bounds.partition_point(|b| *b < tombstone.start.as_slice());
+            let last = bounds.partition_point(|b| *b < tombstone.end.as_slice());
+            for fragment in &mut fragments[first..last] {
+                fragment.seqs.push(tombstone.seq);
+            }
+        }
+        // Gaps between tombstones become fragments too; they cover nothing.
+        fragments.retain(|f| !f.seqs.is_empty());
+        for fragment in &mut fragments {
+            fragment.seqs.sort_unstable();
+        }
+
+        Self {
+            tombstones,
+            fragments,
+        }
+    }
+
+    /// The tombstones, ordered by start key and then newest first.
+    pub(crate) fn tombstones(&self) -> &[RangeTombstone] {
+        &self.tombstones
+    }
+
+    // The sequence numbers of the tombstones covering `key`, ascending.
+    fn covering_seqs(&self, key: &[u8]) -> &[u64] {
+        let i = self
+            .fragments
+            .partition_point(|f| f.start.as_slice() <= key);
+        match i.checked_sub(1).map(|i| &self.fragments[i]) {
+            Some(fragment) if key < fragment.end.as_slice() => &fragment.seqs,
+            _ => &[],
+        }
+    }
+
+    /// The sequence number of the newest tombstone covering `key` that a reader at `read_seq`
+    /// can see, or 0 if there is none. Every version of `key` older than it is deleted.
+    pub(crate) fn max_covering_seq(&self, key: &[u8], read_seq: u64) -> u64 {
+        let seqs = self.covering_seqs(key);
+        let visible = seqs.partition_point(|&s| s <= read_seq);
+        visible.checked_sub(1).map_or(0, |i| seqs[i])
+    }
+
+    /// True if a reader at `read_seq` sees the version of `key` written at `seq` as deleted.
+    pub(crate) fn covers(&self, key: &[u8], seq: u64, read_seq: u64) -> bool {
+        self.max_covering_seq(key, read_seq) > seq
+    }
+
+    /// The oldest tombstone covering `key` that is newer than `seq`: the first one to hide the
+    /// version of `key` written at `seq`.
+    pub(crate) fn oldest_covering_after(&self, key: &[u8], seq: u64) -> Option<u64> {
+        let seqs = self.covering_seqs(key);
+        seqs.get(seqs.partition_point(|&s| s <= seq)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tombstone(start: &str, end: &str, seq: u64) -> RangeTombstone {
+        RangeTombstone {
+            start: start.as_bytes().to_vec(),
+            end: end.as_bytes().to_vec(),
+            seq,
+        }
+    }
+
+    #[test]
+    fn test_overlapping_tombstones_are_fragmented() {
+        let set = RangeTombstones::new(vec![tombstone("c", "g", 9), tombstone("a", "e", 5)]);
+
+        assert_eq!(set.covering_seqs(b"a"), &[5]);
+        assert_eq!(set.covering_seqs(b"d"), &[5, 9]);
+        assert_eq!(set.covering_seqs(b"e"), &[9]);
+        // `end` is exclusive, and keys outside every tombstone are not covered.
+        assert!(set.covering_seqs(b"g").is_empty());
+        assert!(set.covering_seqs(b"0").is_empty());
+
+        // A reader at 7 sees only the older tombstone.
+        assert_eq!(set.max_covering_seq(b"d", 7), 5);
+        assert_eq!(set.max_covering_seq(b"f", 7), 0);
+        assert!(set.covers(b"d", 4, 7));
+        assert!(!set.covers(b"d", 6, 7));
+        assert!(set.covers(b"d", 6, 9));
+
+        assert_eq!(set.oldest_covering_after(b"d", 3), Some(5));
+        assert_eq!(set.oldest_covering_after(b"d", 6), Some(9));
+        assert_eq!(set.oldest_covering_after(b"d", 9), None);
+    }
+
+    #[test]
+    fn test_gaps_and_empty_ranges_cover_nothing() {
+        let set = RangeTombstones::new(vec![
+            tombstone("a", "b", 1),
+            tombstone("x", "y", 2),
+            tombstone("m", "m", 3),
+        ]);
+
+        assert!(set.covering_seqs(b"m").is_empty());
+        assert!(set.covering_seqs(b"c").is_empty());
+        assert_eq!(set.covering_seqs(b"x"), &[2]);
+        assert_eq!(set.tombstones().len(), 2);
+    }
+}
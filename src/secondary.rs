@@ -0,0 +1,307 @@
+//! Secondary instances: read-only engines that follow a primary writing the same directory.
+//!
+//! A secondary reads the primary's files and never writes any. Catching up re-reads the
+//! MANIFEST, which says which SSTables each column family has and how far each family has
+//! been flushed, then replays from the WAL whatever is not in an SSTable yet. Between
+//! flushes only the records appended since the last catch-up are read; once a family has
+//! been flushed, its MemTable is rebuilt from the whole WAL, so the writes that moved into
+//! SSTables do not pile up in memory.
+
+use crate::column_family::{ColumnFamily, ColumnFamilyData};
+use crate::memtable::MemTable;
+use crate::sstable::manifest::ManifestState;
+use crate::sstable::{Manifest, SSTableReader};
+use crate::wal::{Record, WalPosition};
+use crate::{CorruptSSTablePolicy, Error, OpenMode, Options, StorageEngine, apply_record};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, MutexGuard};
+
+// The SSTables of each column family, by family id, level by level.
+type PrimaryTables = HashMap<u32, Vec<Vec<Arc<SSTableReader>>>>;
+
+// How often a catch-up starts over because the primary changed the MANIFEST while the WAL
+// was being read, before giving up.
+const CATCH_UP_ATTEMPTS: usize = 8;
+
+/// What a secondary remembers between catch-ups.
+pub(crate) struct SecondaryState {
+    // Options for the families the primary creates later: the ones listed at open by name,
+    // any other with the database's options.
+    options: Arc<Options>,
+    family_options: BTreeMap<String, Arc<Options>>,
+    // Each family's flushed sequence as of the last catch-up. The MemTables hold the writes
+    // after it.
+    flushed: BTreeMap<u32, u64>,
+    // How far the WAL has been replayed.
+    tail: WalPosition,
+}
+
+impl SecondaryState {
+    pub(crate) fn new(
+        options: Arc<Options>,
+        family_options: BTreeMap<String, Arc<Options>>,
+    ) -> Self {
+        Self {
+            options,
+            family_options,
+            flushed: BTreeMap::new(),
+            tail: WalPosition::default(),
+        }
+    }
+}
+
+impl StorageEngine {
+    /// Opens the database at the given path as a secondary instance of the process writing
+    /// it (the primary), with default options.
+    ///
+    /// A secondary is a read-only view — see [`open_read_only`](Self::open_read_only) — that
+    /// can be brought up to date: each call to
+    /// [`try_catch_up_with_primary`](Self::try_catch_up_with_primary) picks up the tables the
+    /// primary has flushed or compacted since, and the writes it has logged to the WAL. A
+    /// reporting process can call it on a timer to stay nearly up to date.
+    ///
+    /// ```no_run
+    /// use lsmdb::StorageEngine;
+    ///
+    /// let follower = StorageEngine::open_as_secondary("/tmp/db")?;
+    /// loop {
+    ///     follower.try_catch_up_with_primary()?;
+    ///     let mut orders = follower.scan(b"order:".as_slice()..b"order;".as_slice())?;
+    ///     println!("{} orders", orders.try_fold(0, |n, entry| entry.map(|_| n + 1))?);
+    ///     std::thread::sleep(std::time::Duration::from_secs(10));
+    /// }
+    /// # Ok::<(), lsmdb::Error>(())
+    /// ```
+    pub fn open_as_secondary(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::open_as_secondary_with_column_families(
+            path,
+            Options::default(),
+            Vec::<(&str, Options)>::new(),
+        )
+    }
+
+    /// Like [`open_as_secondary`](Self::open_as_secondary), with options for the default
+    /// column family and the database as a whole, and for other column families as in
+    /// [`open_with_column_families`](Self::open_with_column_families). A listed family the
+    /// primary has not created yet gets its options once it does.
+    pub fn open_as_secondary_with_column_families<N: AsRef<str>>(
+        path: impl Into<PathBuf>,
+        options: Options,
+        column_families: impl IntoIterator<Item = (N, Options)>,
+    ) -> Result<Self, Error> {
+        Self::open_in_mode(path, options, column_families, OpenMode::Secondary)
+    }
+
+    /// Brings a secondary instance up to date with its primary: afterwards it reads every
+    /// write that had reached the primary's WAL, plus the SSTables and column families the
+    /// primary has added since the last call. Fails on an instance not opened with
+    /// [`open_as_secondary`](Self::open_as_secondary).
+    ///
+    /// Reads running during the call may see some of the tables it brings in before others,
+    /// but never lose a write they could see before. A busy primary can keep changing the
+    /// MANIFEST while the WAL is read; the call then starts over, and gives up after a few
+    /// attempts — try again later.
+    pub fn try_catch_up_with_primary(&self) -> Result<(), Error> {
+        let mut secondary = self
+            .secondary
+            .as_ref()
+            .ok_or_else(|| {
+                Error::invalid("Only a secondary instance can catch up with its primary")
+            })?
+            .lock()
+            .map_err(|_| Error::Poisoned("Secondary state"))?;
+        let manifest_path = self.db_path.join("MANIFEST");
+
+        for _ in 0..CATCH_UP_ATTEMPTS {
+            // The MANIFEST is append-only, so its length tells whether it changed.
+            let manifest_len = std::fs::metadata(&manifest_path)?.len();
+            let state = Manifest::recover(&manifest_path)?;
+            let rebuild = state
+                .column_families
+                .iter()
+                .any(|(id, family)| secondary.flushed.get(id) != Some(&family.flushed_sequence));
+            let from = if rebuild {
+                WalPosition::default()
+            } else {
+                secondary.tail
+            };
+            let (records, tail) = self
+                .wal
+                .lock()
+                .map_err(|_| Error::Poisoned("WAL"))?
+                .read_from(from)?;
+            // The primary also logs a compaction before deleting its inputs, so a table gone
+            // from disk means the MANIFEST changed too.
+            let Some(tables) = self.open_primary_tables(&secondary, &state)? else {
+                continue;
+            };
+
+            // The primary only deletes a WAL file after logging the flush that emptied it. If
+            // the MANIFEST is unchanged, no file was deleted while we read, and the records
+            // pick up exactly where the state we read leaves off.
+            if std::fs::metadata(&manifest_path)?.len() != manifest_len {
+                continue;
+            }
+            self.install_primary_state(&mut secondary, state, tables, records, rebuild)?;
+            secondary.tail = tail;
+            return Ok(());
+        }
+
+        Err(Error::Busy(format!(
+            "The primary changed the MANIFEST during each of {} attempts to catch up with it",
+            CATCH_UP_ATTEMPTS
+        )))
+    }
+
+    // Opens the SSTables of each family in `state`, level by level and newest first, reusing
+    // the readers already open. Returns `None` if one of them is missing from disk: the
+    // primary has compacted it away since `state` was read.
+    fn open_primary_tables(
+        &self,
+        secondary: &SecondaryState,
+        state: &ManifestState,
+    ) -> Result<Option<PrimaryTables>, Error> {
+        let families = self
+            .column_families
+            .read()
+            .map_err(|_| Error::Poisoned("Column families"))?;
+        let sst_dir = self.db_path.join("sst");
+        let mut tables = HashMap::new();
+        for (id, family_state) in &state.column_families {
+            let mut open: HashMap<u64, Arc<SSTableReader>> = HashMap::new();
+            if let Some(family) = families.get(id) {
+                let sstables = family
+                    .sstables
+                    .read()
+                    .map_err(|_| Error::Poisoned("SSTables"))?;
+                open.extend(
+                    sstables
+                        .iter()
+                        .flatten()
+                        .map(|reader| (reader.id, Arc::clone(reader))),
+                );
+            }
+            let mut levels: Vec<Vec<Arc<SSTableReader>>> = Vec::new();
+            for ids in &family_state.levels {
+                let mut level = Vec::new();
+                for &sst_id in ids {
+                    if let Some(reader) = open.get(&sst_id) {
+                        level.push(Arc::clone(reader));
+                        continue;
+                    }
+                    match SSTableReader::open(sst_dir.join(format!("{}.sst", sst_id))) {
+                        Ok(reader) => level.push(Arc::new(reader)),
+                        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                            return Ok(None);
+                        }
+                        // Only the primary moves a damaged table aside.
+                        Err(Error::Corruption { .. })
+                            if secondary.options.corrupt_sstable_policy
+                                == CorruptSSTablePolicy::Quarantine => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                // Newest first, as at open.
+                level.sort_by_key(|reader| std::cmp::Reverse(reader.id));
+                levels.push(level);
+            }
+            if levels.is_empty() {
+                levels.push(Vec::new());
+            }
+            tables.insert(*id, levels);
+        }
+        Ok(Some(tables))
+    }
+
+    // Installs the primary's layout as of `state` — its SSTables opened into `tables` — and
+    // the WAL `records` written after it: on top of the MemTables, or into fresh ones if
+    // `rebuild` is set.
+    fn install_primary_state(
+        &self,
+        secondary: &mut MutexGuard<'_, SecondaryState>,
+        state: ManifestState,
+        tables: PrimaryTables,
+        records: Vec<Record>,
+        rebuild: bool,
+    ) -> Result<(), Error> {
+        let families = {
+            let mut families = self
+                .column_families
+                .write()
+                .map_err(|_| Error::Poisoned("Column families"))?;
+            for (&id, family_state) in &state.column_families {
+                if families.contains_key(&id) {
+                    continue;
+                }
+                let options = match secondary.family_options.get(&family_state.name) {
+                    Some(options) => Arc::clone(options),
+                    None => Arc::clone(&secondary.options),
+                };
+                let family = ColumnFamilyData::new(
+                    ColumnFamily::new(id, &family_state.name),
+                    options,
+                    Vec::new(),
+                    0,
+                );
+                families.insert(id, Arc::new(family));
+            }
+            families.clone()
+        };
+
+        // Tables before MemTables: a write the primary has since flushed is briefly in both,
+        // never in neither.
+        for (id, levels) in tables {
+            *families[&id]
+                .sstables
+                .write()
+                .map_err(|_| Error::Poisoned("SSTables"))? = levels;
+        }
+
+        let mut by_family: BTreeMap<u32, Vec<Record>> = BTreeMap::new();
+        let mut max_seq = state.last_sequence;
+        for record in records {
+            let flushed = state
+                .column_families
+                .get(&record.cf)
+                .ok_or_else(|| {
+                    Error::corruption(
+                        self.db_path.join("wal"),
+                        0,
+                        format!("WAL holds writes to unknown column family {}", record.cf),
+                    )
+                })?
+                .flushed_sequence;
+            max_seq = max_seq.max(record.seq_num);
+            if record.seq_num > flushed {
+                by_family.entry(record.cf).or_default().push(record);
+            }
+        }
+        for (id, family) in &families {
+            let records = by_family.remove(id).unwrap_or_default();
+            let mut memtable = family
+                .active_memtable
+                .lock()
+                .map_err(|_| Error::Poisoned("MemTable"))?;
+            if rebuild {
+                *memtable = MemTable::new(
+                    family.options.memtable_capacity_bytes,
+                    family.options.bloom_filter_fpr,
+                );
+            }
+            for record in records {
+                apply_record(&mut memtable, record);
+            }
+        }
+
+        // Only now do reads see the new writes: they read at the last sequence number.
+        self.next_seq_num.fetch_max(max_seq + 1, Ordering::SeqCst);
+        secondary.flushed = state
+            .column_families
+            .iter()
+            .map(|(&id, family)| (id, family.flushed_sequence))
+            .collect();
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// A consistent, read-only view of the database as of the moment it was taken, returned by
+/// [`StorageEngine::snapshot`](crate::StorageEngine::snapshot).
+///
+/// Reads through a snapshot — [`get_at`](crate::StorageEngine::get_at) and
+/// [`scan_at`](crate::StorageEngine::scan_at) — see every write that had completed when the
+/// snapshot was taken and nothing written since, even across flushes and compactions.
+///
+/// While a snapshot is alive, compaction keeps the versions it can see, so long-lived
+/// snapshots hold on to disk space. Dropping the snapshot releases them.
+///
+/// ```no_run
+/// # let engine = lsmdb::StorageEngine::open("/tmp/db").unwrap();
+/// engine.put(b"balance", b"100")?;
+/// let snapshot = engine.snapshot();
+/// engine.put(b"balance", b"40")?;
+///
+/// assert_eq!(engine.get_at(&snapshot, b"balance")?, Some(b"100".to_vec()));
+/// assert_eq!(engine.get(b"balance")?, Some(b"40".to_vec()));
+/// # Ok::<(), lsmdb::Error>(())
+/// ```
+pub struct Snapshot {
+    seq: u64,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// The sequence number of the newest write visible through this snapshot.
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
+
+impl std::fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot").field("seq", &self.seq).finish()
+    }
+}
+
+/// The sequence numbers of every live [`Snapshot`], shared with the flush thread so compaction
+/// knows which old versions it must keep.
+///
+/// Several snapshots often share a sequence number (nothing was written in between), so each
+/// one is reference-counted rather than stored once per handle.
+#[derive(Default)]
+pub(crate) struct SnapshotList {
+    seqs: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    pub(crate) fn acquire(self: &Arc<Self>, seq: u64) -> Snapshot {
+        // A poisoned lock only means another thread panicked mid-update of a counter; the map
+        // itself is still usable, and `Drop` must not panic.
+        let mut seqs = self.seqs.lock().unwrap_or_else(|e| e.into_inner());
+        *seqs.entry(seq).or_insert(0) += 1;
+        Snapshot {
+            seq,
+            list: Arc::clone(self),
+        }
+    }
+
+    fn release(&self, seq: u64) {
+        let mut seqs = self.seqs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = seqs.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                seqs.remove(&seq);
+            }
+        }
+    }
+
+    /// The sequence numbers of the live snapshots, ascending.
+    pub(crate) fn sequences(&self) -> Vec<u64> {
+        let seqs = self.seqs.lock().unwrap_or_else(|e| e.into_inner());
+        seqs.keys().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_list_tracks_live_snapshots() {
+        let list = Arc::new(SnapshotList::default());
+
+        let a = list.acquire(5);
+        let b = list.acquire(5);
+        let c = list.acquire(9);
+        assert_eq!(list.sequences(), vec![5, 9]);
+
+        drop(a);
+        assert_eq!(list.sequences(), vec![5, 9]);
+        drop(b);
+        assert_eq!(list.sequences(), vec![9]);
+        assert_eq!(c.sequence(), 9);
+        drop(c);
+        assert!(list.sequences().is_empty());
+    }
+}
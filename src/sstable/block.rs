@@ -1,6 +1,10 @@
+use std::cmp::Ordering;
+
 use super::varint;
 
-const RESTART_INTERVAL: usize = crate::constants::SSTABLE_RESTART_INTERVAL;
+/// Orders the keys of a block. Data and index blocks hold internal keys and must be searched
+/// with `internal_key::compare`; plain bytewise order is the default.
+pub type Comparator = fn(&[u8], &[u8]) -> Ordering;
 
 pub struct BlockBuilder {
     /// The raw byte buffer where we write our entries.
@@ -11,16 +15,22 @@ pub struct BlockBuilder {
     counter: usize,
     /// The key of the last entry added (used to calculate prefix overlap).
     last_key: Vec<u8>,
+    /// Soft size limit; `is_block_maxed` reports true once the payload reaches it.
+    block_size: usize,
+    /// Number of entries between full (non-prefix-compressed) keys.
+    restart_interval: usize,
 }
 
 impl BlockBuilder {
-    pub fn new() -> Self {
+    pub fn new(block_size: usize, restart_interval: usize) -> Self {
         Self {
             buffer: Vec::new(),
             // The first restart point is always offset 0
             restarts: vec![0],
             counter: 0,
             last_key: Vec::new(),
+            block_size,
+            restart_interval,
         }
     }
 
@@ -34,7 +44,7 @@ impl BlockBuilder {
             .take_while(|(a, b)| a == b)
             .count();
 
-        if self.counter == RESTART_INTERVAL {
+        if self.counter == self.restart_interval {
             shared_length = 0;
             self.restarts.push(self.buffer.len() as u32);
             self.counter = 0;
@@ -67,7 +77,7 @@ impl BlockBuilder {
     }
 
     pub fn is_block_maxed(&self) -> bool {
-        self.buffer.len() >= crate::constants::SSTABLE_BLOCK_SIZE
+        self.buffer.len() >= self.block_size
     }
 
     pub fn last_key(&self) -> Vec<u8> {
@@ -79,25 +89,47 @@ impl BlockBuilder {
     }
 }
 
+/// A single decoded entry, as located by `BlockReader::seek` and its backward counterparts.
+pub struct BlockEntry<'a> {
+    /// Byte offset of the entry's header (the `shared_len` varint) within the block.
+    pub offset: usize,
+    /// Byte offset of the entry that follows, or `restarts_offset` for the last entry.
+    pub next_offset: usize,
+    /// The full key, with any shared prefix already re-attached.
+    pub key: Vec<u8>,
+    pub value: &'a [u8],
+}
+
 pub struct BlockReader<'a> {
     pub(crate) data: &'a [u8],
     pub(crate) restarts_offset: usize,
     pub num_restarts: usize,
+    cmp: Comparator,
 }
 
 impl<'a> BlockReader<'a> {
-    pub fn new(data: &'a [u8]) -> Self {
-        let len = data.len();
-        let num_restarts = u32::from_le_bytes(data[len - 4..].try_into().unwrap()) as usize;
-        let restarts_offset = len - (num_restarts * 4) - 4;
+    /// A reader that orders keys bytewise.
+    #[allow(dead_code)]
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        Self::with_comparator(data, |a, b| a.cmp(b))
+    }
 
-        Self {
+    /// Returns `None` if `data` is too short to hold the restart array its trailer announces.
+    /// Entries are only decoded on demand, and a damaged one reads as the end of the block.
+    pub fn with_comparator(data: &'a [u8], cmp: Comparator) -> Option<Self> {
+        let trailer_offset = data.len().checked_sub(4)?;
+        let num_restarts = u32::from_le_bytes(data[trailer_offset..].try_into().ok()?) as usize;
+        let restarts_offset = trailer_offset.checked_sub(num_restarts.checked_mul(4)?)?;
+
+        Some(Self {
             data,
             restarts_offset,
             num_restarts,
-        }
+            cmp,
+        })
     }
 
+    #[allow(dead_code)]
     pub fn get(&self, search_key: &[u8]) -> Option<&'a [u8]> {
         if self.num_restarts == 0 {
             return None;
@@ -111,31 +143,23 @@ impl<'a> BlockReader<'a> {
             let mid = left + (right - left) / 2;
             let offset = self.read_restart_offset(mid) as usize;
 
-            // At a restart point, shared_len is ALWAYS 0.
-            let mut ptr = offset;
-
-            let (_, len1) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len1;
-
-            let (unshared_len, len2) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len2;
-
-            let (_, len3) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len3;
+            let key_at_mid = self.restart_key(offset)?;
 
-            let key_at_mid = &self.data[ptr..ptr + unshared_len as usize];
-
-            if key_at_mid < search_key {
-                best_restart_index = mid;
-                left = mid + 1;
-            } else if key_at_mid > search_key {
-                if mid == 0 {
+            match (self.cmp)(key_at_mid, search_key) {
+                Ordering::Less => {
+                    best_restart_index = mid;
+                    left = mid + 1;
+                }
+                Ordering::Greater => {
+                    if mid == 0 {
+                        break;
+                    }
+                    right = mid - 1;
+                }
+                Ordering::Equal => {
+                    best_restart_index = mid;
                     break;
                 }
-                right = mid - 1;
-            } else {
-                best_restart_index = mid;
-                break;
             }
         }
 
@@ -143,30 +167,16 @@ impl<'a> BlockReader<'a> {
         let mut current_key = Vec::new();
 
         while ptr < self.restarts_offset {
-            let (shared_len, len1) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len1;
-
-            let (unshared_len, len2) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len2;
-
-            let (value_len, len3) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len3;
+            let entry = self.entry_at(ptr, &mut current_key)?;
+            ptr = entry.next_offset;
 
-            current_key.truncate(shared_len as usize);
-            let unshared_bytes = &self.data[ptr..ptr + unshared_len as usize];
-            current_key.extend_from_slice(unshared_bytes);
-            ptr += unshared_len as usize;
-
-            let value_bytes = &self.data[ptr..ptr + value_len as usize];
-            ptr += value_len as usize;
-
-            if current_key.as_slice() == search_key {
-                return Some(value_bytes);
-            } else if current_key.as_slice() > search_key {
-                // Since keys are strictly sorted in an SSTable, if we see a current_key
+            match (self.cmp)(&entry.key, search_key) {
+                Ordering::Equal => return Some(entry.value),
+                // Since keys are strictly sorted in an SSTable, if we see a key
                 // that is larger than what we are looking for, we know our search_key
                 // does not exist in this block.
-                return None;
+                Ordering::Greater => return None,
+                Ordering::Less => {}
             }
         }
 
@@ -175,6 +185,15 @@ impl<'a> BlockReader<'a> {
 
     // Like `get`, but returns the FIRST key >= search_key. This is crucial for Index blocks!
     pub fn lookup(&self, search_key: &[u8]) -> Option<&'a [u8]> {
+        self.seek(search_key).map(|entry| entry.value)
+    }
+
+    /// Finds the FIRST entry whose key is >= `search_key` and returns where it lives.
+    ///
+    /// `lookup` only needs the value, but an iterator that wants to continue from the found
+    /// entry also needs its byte offset and fully reconstructed key: the key lets the caller
+    /// decode the following prefix-compressed entry, and the offset lets it resume there.
+    pub fn seek(&self, search_key: &[u8]) -> Option<BlockEntry<'a>> {
         if self.num_restarts == 0 {
             return None;
         }
@@ -187,17 +206,9 @@ impl<'a> BlockReader<'a> {
             let mid = left + (right - left) / 2;
             let offset = self.read_restart_offset(mid) as usize;
 
-            let mut ptr = offset;
-            let (_, len1) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len1;
-            let (unshared_len, len2) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len2;
-            let (_, len3) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len3;
-
-            let key_at_mid = &self.data[ptr..ptr + unshared_len as usize];
+            let key_at_mid = self.restart_key(offset)?;
 
-            if key_at_mid < search_key {
+            if (self.cmp)(key_at_mid, search_key) == Ordering::Less {
                 best_restart_index = mid;
                 left = mid + 1;
             } else {
@@ -212,45 +223,148 @@ impl<'a> BlockReader<'a> {
         let mut current_key = Vec::new();
 
         while ptr < self.restarts_offset {
-            let (shared_len, len1) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len1;
+            let entry = self.entry_at(ptr, &mut current_key)?;
+            if (self.cmp)(&entry.key, search_key) != Ordering::Less {
+                return Some(entry);
+            }
+            ptr = entry.next_offset;
+        }
+
+        None
+    }
+
+    /// Finds the LAST entry whose key is <= `search_key`; the mirror image of `seek`.
+    pub fn seek_for_prev(&self, search_key: &[u8]) -> Option<BlockEntry<'a>> {
+        match self.seek(search_key) {
+            Some(entry) if (self.cmp)(&entry.key, search_key) == Ordering::Equal => Some(entry),
+            Some(entry) => self.prev_entry(entry.offset),
+            None => self.last_entry(),
+        }
+    }
 
-            let (unshared_len, len2) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len2;
+    pub fn first_entry(&self) -> Option<BlockEntry<'a>> {
+        if self.num_restarts == 0 || self.restarts_offset == 0 {
+            return None;
+        }
+        self.entry_at(0, &mut Vec::new())
+    }
 
-            let (value_len, len3) = varint::decode_u32(&self.data[ptr..])?;
-            ptr += len3;
+    pub fn last_entry(&self) -> Option<BlockEntry<'a>> {
+        self.prev_entry(self.restarts_offset)
+    }
 
-            current_key.truncate(shared_len as usize);
-            let unshared_bytes = &self.data[ptr..ptr + unshared_len as usize];
-            current_key.extend_from_slice(unshared_bytes);
-            ptr += unshared_len as usize;
+    /// Returns the entry immediately before the one starting at `offset` (pass
+    /// `restarts_offset` to get the last entry).
+    ///
+    /// Entries are prefix-compressed against their predecessor, so they can only be decoded
+    /// front to back. The restart array is what makes stepping backwards affordable: we jump to
+    /// the last restart point before `offset`, where the key is stored in full, and decode
+    /// forward from there — at most `restart_interval` entries rather than the whole block.
+    pub fn prev_entry(&self, offset: usize) -> Option<BlockEntry<'a>> {
+        if self.num_restarts == 0 || offset == 0 {
+            return None;
+        }
+
+        // Binary search for the last restart point strictly before `offset`. Restart 0 is
+        // always at offset 0, so one exists.
+        let mut left = 0;
+        let mut right = self.num_restarts;
+        while right - left > 1 {
+            let mid = left + (right - left) / 2;
+            if (self.read_restart_offset(mid) as usize) < offset {
+                left = mid;
+            } else {
+                right = mid;
+            }
+        }
 
-            let value_bytes = &self.data[ptr..ptr + value_len as usize];
-            ptr += value_len as usize;
+        let mut ptr = self.read_restart_offset(left) as usize;
+        let mut current_key = Vec::new();
 
-            // If the current key is strictly >= search_key, return its value!
-            if current_key.as_slice() >= search_key {
-                return Some(value_bytes);
+        while ptr < offset {
+            let entry = self.entry_at(ptr, &mut current_key)?;
+            if entry.next_offset >= offset {
+                return Some(entry);
             }
+            ptr = entry.next_offset;
         }
 
         None
     }
 
+    /// Decodes the entry starting at `offset`. `current_key` must hold the previous entry's
+    /// key (or anything, at a restart point); it is updated to this entry's key.
+    ///
+    /// Returns `None` if the entry does not decode: a varint runs off the end, the key shares
+    /// more bytes than the previous key has, or the entry spills into the restart array.
+    pub fn entry_at(&self, offset: usize, current_key: &mut Vec<u8>) -> Option<BlockEntry<'a>> {
+        let entries = &self.data[..self.restarts_offset];
+        let mut ptr = offset;
+
+        let (shared_len, len1) = varint::decode_u32(entries.get(ptr..)?)?;
+        ptr += len1;
+
+        let (unshared_len, len2) = varint::decode_u32(entries.get(ptr..)?)?;
+        ptr += len2;
+
+        let (value_len, len3) = varint::decode_u32(entries.get(ptr..)?)?;
+        ptr += len3;
+
+        if shared_len as usize > current_key.len() {
+            return None;
+        }
+        current_key.truncate(shared_len as usize);
+        let unshared_bytes = entries.get(ptr..ptr + unshared_len as usize)?;
+        current_key.extend_from_slice(unshared_bytes);
+        ptr += unshared_len as usize;
+
+        let value_bytes = entries.get(ptr..ptr + value_len as usize)?;
+        ptr += value_len as usize;
+
+        Some(BlockEntry {
+            offset,
+            next_offset: ptr,
+            key: current_key.clone(),
+            value: value_bytes,
+        })
+    }
+
+    // The constructor checked that the whole restart array lies inside the block.
     fn read_restart_offset(&self, index: usize) -> u32 {
         let offset = self.restarts_offset + index * 4;
         u32::from_le_bytes(self.data[offset..offset + 4].try_into().unwrap())
     }
+
+    // The full key stored at the restart point at `offset`, where shared_len is always 0.
+    fn restart_key(&self, offset: usize) -> Option<&'a [u8]> {
+        let entries = &self.data[..self.restarts_offset];
+        let mut ptr = offset;
+
+        let (_, len1) = varint::decode_u32(entries.get(ptr..)?)?;
+        ptr += len1;
+
+        let (unshared_len, len2) = varint::decode_u32(entries.get(ptr..)?)?;
+        ptr += len2;
+
+        let (_, len3) = varint::decode_u32(entries.get(ptr..)?)?;
+        ptr += len3;
+
+        entries.get(ptr..ptr + unshared_len as usize)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::{SSTABLE_BLOCK_SIZE, SSTABLE_RESTART_INTERVAL};
+
+    fn new_builder() -> BlockBuilder {
+        BlockBuilder::new(SSTABLE_BLOCK_SIZE, SSTABLE_RESTART_INTERVAL)
+    }
 
     #[test]
     fn test_block_builder_add() {
-        let mut builder = BlockBuilder::new();
+        let mut builder = new_builder();
 
         // Let's add two keys that share a prefix: "apple" and "appstore"
         builder.add(b"apple", b"val1");
@@ -264,12 +378,12 @@ mod tests {
         // Let's verify our state after the fact.
         assert_eq!(builder.counter, 2);
         assert_eq!(builder.last_key, b"appstore");
-        assert!(builder.buffer.len() > 0);
+        assert!(!builder.buffer.is_empty());
     }
 
     #[test]
     fn test_block_builder_restarts() {
-        let mut builder = BlockBuilder::new();
+        let mut builder = new_builder();
 
         // Add 17 keys to trigger the RESTART_INTERVAL (which is 16)
         for i in 0..17 {
@@ -293,7 +407,7 @@ mod tests {
 
     #[test]
     fn test_block_builder_finish() {
-        let mut builder = BlockBuilder::new();
+        let mut builder = new_builder();
 
         builder.add(b"apple", b"val1");
         builder.add(b"appstore", b"val2");
@@ -320,14 +434,14 @@ mod tests {
 
     #[test]
     fn test_block_reader_init() {
-        let mut builder = BlockBuilder::new();
+        let mut builder = new_builder();
 
         builder.add(b"apple", b"val1");
         builder.add(b"appstore", b"val2");
 
         let data = builder.finish();
 
-        let reader = BlockReader::new(data);
+        let reader = BlockReader::new(data).unwrap();
 
         assert_eq!(reader.num_restarts, 1);
 
@@ -338,7 +452,7 @@ mod tests {
 
     #[test]
     fn test_block_reader_restart_offset() {
-        let mut builder = BlockBuilder::new();
+        let mut builder = new_builder();
 
         for i in 0..17 {
             let key = format!("key{:02}", i);
@@ -347,7 +461,7 @@ mod tests {
 
         let data = builder.finish();
 
-        let reader = BlockReader::new(data);
+        let reader = BlockReader::new(data).unwrap();
 
         assert_eq!(reader.read_restart_offset(0), 0);
         assert!(reader.read_restart_offset(1) > 0);
@@ -355,7 +469,7 @@ mod tests {
 
     #[test]
     fn test_block_reader_get() {
-        let mut builder = BlockBuilder::new();
+        let mut builder = new_builder();
 
         builder.add(b"apple", b"val_apple");
         builder.add(b"appstore", b"val_appstore");
@@ -364,7 +478,7 @@ mod tests {
         builder.add(b"cat", b"val_cat");
 
         let data = builder.finish();
-        let reader = BlockReader::new(data);
+        let reader = BlockReader::new(data).unwrap();
 
         // Found keys
         assert_eq!(reader.get(b"apple"), Some(b"val_apple".as_slice()));
@@ -376,4 +490,98 @@ mod tests {
         assert_eq!(reader.get(b"bat"), None); // In the middle
         assert_eq!(reader.get(b"zebra"), None); // After everything
     }
+
+    #[test]
+    fn test_block_reader_seek() {
+        let mut builder = new_builder();
+
+        for i in (0..40).step_by(2) {
+            let key = format!("key{:02}", i);
+            builder.add(key.as_bytes(), key.as_bytes());
+        }
+
+        let data = builder.finish();
+        let reader = BlockReader::new(data).unwrap();
+
+        // Between two keys, past the first restart point.
+        let entry = reader.seek(b"key21").unwrap();
+        assert_eq!(entry.key, b"key22");
+        assert_eq!(entry.value, b"key22");
+
+        // `next_offset` points at the following entry.
+        let next = reader.seek(b"key23").unwrap();
+        assert_eq!(entry.next_offset, next.offset);
+
+        assert_eq!(reader.seek(b"a").unwrap().offset, 0);
+        assert!(reader.seek(b"key99").is_none());
+    }
+
+    #[test]
+    fn test_block_reader_backward_traversal() {
+        // 40 entries with the default restart interval of 16 spans three restart points.
+        let mut builder = new_builder();
+        for i in 0..40 {
+            let key = format!("key{:02}", i);
+            builder.add(key.as_bytes(), key.as_bytes());
+        }
+        let data = builder.finish();
+        let reader = BlockReader::new(data).unwrap();
+
+        // Walk the whole block from the back and make sure we see every key in reverse.
+        let mut keys = Vec::new();
+        let mut entry = reader.last_entry();
+        while let Some(e) = entry {
+            keys.push(String::from_utf8(e.key.clone()).unwrap());
+            entry = reader.prev_entry(e.offset);
+        }
+        let expected: Vec<String> = (0..40).rev().map(|i| format!("key{:02}", i)).collect();
+        assert_eq!(keys, expected);
+
+        assert_eq!(reader.seek_for_prev(b"key17").unwrap().key, b"key17");
+        assert_eq!(reader.seek_for_prev(b"key17a").unwrap().key, b"key17");
+        assert_eq!(reader.seek_for_prev(b"zzz").unwrap().key, b"key39");
+        assert!(reader.seek_for_prev(b"a").is_none());
+        assert_eq!(reader.first_entry().unwrap().key, b"key00");
+    }
+
+    #[test]
+    fn test_block_reader_survives_damaged_blocks() {
+        // Too short for the trailer, or for the restart array it announces.
+        assert!(BlockReader::new(&[]).is_none());
+        assert!(BlockReader::new(&[1, 0, 0]).is_none());
+        assert!(BlockReader::new(&[0, 0, 0, 0, 2, 0, 0, 0]).is_none());
+
+        let mut builder = new_builder();
+        builder.add(b"apple", b"val1");
+        builder.add(b"appstore", b"val2");
+        let entries = builder.buffer.clone();
+
+        // The first entry is cut off half-way through its key.
+        let mut truncated = entries[..5].to_vec();
+        truncated.extend_from_slice(&0u32.to_le_bytes());
+        truncated.extend_from_slice(&1u32.to_le_bytes());
+        let reader = BlockReader::new(&truncated).unwrap();
+        assert!(reader.first_entry().is_none());
+        assert!(reader.seek(b"apple").is_none());
+        assert!(reader.last_entry().is_none());
+
+        // A restart point past the entries.
+        let mut stray = entries.clone();
+        stray.extend_from_slice(&1000u32.to_le_bytes());
+        stray.extend_from_slice(&1u32.to_le_bytes());
+        let reader = BlockReader::new(&stray).unwrap();
+        assert!(reader.seek(b"apple").is_none());
+        assert!(reader.get(b"apple").is_none());
+
+        // The second entry claims to share more bytes than the first key has.
+        let mut overshared = entries[..12].to_vec();
+        overshared.extend_from_slice(&[100, 0, 0]);
+        overshared.extend_from_slice(&0u32.to_le_bytes());
+        overshared.extend_from_slice(&1u32.to_le_bytes());
+        let reader = BlockReader::new(&overshared).unwrap();
+        let first = reader.first_entry().unwrap();
+        assert_eq!(first.key, b"apple");
+        let mut key = first.key;
+        assert!(reader.entry_at(first.next_offset, &mut key).is_none());
+    }
 }
@@ -1,149 +1,355 @@
-use super::{block::BlockReader, sst::SSTableReader, varint};
-use crate::constants::{COMPRESSION_NONE, COMPRESSION_SNAPPY};
+use super::{
+    block::{BlockEntry, BlockReader},
+    sst::SSTableReader,
+};
+use crate::internal_key::{self, ValueType};
+use crate::iterator::InternalIterator;
+use crate::merge::{self, MergeOperator};
+use crate::range_del::RangeTombstones;
+use crate::ttl;
+use crate::{Error, Options};
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
-// Reads the 1-byte compression type sentinel written by SSTableBuilder and decompresses.
-// Returning None (instead of panicking) on an unknown type makes the reader forward-compatible:
-// a file written by a future version of lsmdb with a new compressor can be gracefully skipped
-// rather than crashing all existing readers.
-fn decompress_block(raw_block: &[u8]) -> Option<Vec<u8>> {
-    let compression_type = raw_block[0];
-    let payload = &raw_block[1..];
-    match compression_type {
-        t if t == COMPRESSION_SNAPPY => snap::raw::Decoder::new().decompress_vec(payload).ok(),
-        t if t == COMPRESSION_NONE => Some(payload.to_vec()),
-        _ => None,
-    }
+// Both the index and the data blocks are keyed by internal keys. Stepping between entries only
+// needs offsets, but any search has to use the internal key order.
+fn block_reader(data: &[u8]) -> Option<BlockReader<'_>> {
+    BlockReader::with_comparator(data, internal_key::compare)
 }
 
+/// A bidirectional cursor over every entry of one SSTable.
+///
+/// The cursor sits *on* an entry (or is invalid once it walks off either end). Moving forward
+/// decodes the next prefix-compressed entry in O(1); moving backward uses the restart array of
+/// the data block (see `BlockReader::prev_entry`) and, at a block boundary, steps the index
+/// cursor back the same way.
+///
+/// A block that cannot be read ends the walk: the cursor turns invalid and
+/// [`error`](InternalIterator::error) says what was wrong, rather than skipping to the next
+/// block and silently leaving out entries.
 pub struct SSTableIterator {
-    reader: SSTableReader,
-
-    current_block_idx: usize,
-    current_block_data: Option<Vec<u8>>,
-    current_block_ptr: usize,
-    // `current_key` is accumulated incrementally because Data Blocks use prefix compression:
-    // each stored key is only the suffix that differs from the previous key. Rebuilding the
-    // full key here avoids allocating full keys inside the block format itself.
-    current_key: Vec<u8>,
-
-    // `index_ptr` is a cursor into the raw Index Block bytes. Advancing it linearly as we
-    // load blocks is O(N) total. If instead we re-parsed the entire Index Block on every
-    // `load_next_block` call it would be O(N²) over the whole iteration.
-    index_ptr: usize,
-    index_key: Vec<u8>,
+    reader: Arc<SSTableReader>,
+    verify_checksums: bool,
+
+    // Position in the Index Block: the entry naming the data block we are currently inside.
+    // Stepping it forward decodes one entry from where the previous one ended, so walking the
+    // whole table touches each index entry once instead of re-searching the index per block.
+    index_entry: Option<EntryPos>,
+    block_data: Option<Vec<u8>>,
+    // Where `block_data` starts in the file, for error messages.
+    block_offset: u64,
+    // Position inside `block_data`. `None` means the iterator is not valid.
+    entry: Option<EntryPos>,
+    // Set once a block fails to read. Every later move leaves the iterator invalid.
+    error: Option<Error>,
+}
+
+// An owned copy of a `BlockEntry`'s position, so the iterator can remember where it is
+// without borrowing the block it was decoded from. Keeping the full key is what lets the next
+// prefix-compressed entry decode correctly.
+struct EntryPos {
+    offset: usize,
+    next_offset: usize,
+    key: Vec<u8>,
+    value_len: usize,
+}
+
+impl From<BlockEntry<'_>> for EntryPos {
+    fn from(entry: BlockEntry<'_>) -> Self {
+        Self {
+            offset: entry.offset,
+            next_offset: entry.next_offset,
+            value_len: entry.value.len(),
+            key: entry.key,
+        }
+    }
+}
+
+impl EntryPos {
+    // Values sit at the very end of an entry, right before the next one starts.
+    fn value<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.next_offset - self.value_len..self.next_offset]
+    }
 }
 
 impl SSTableIterator {
-    pub fn new(reader: SSTableReader) -> Self {
+    // Takes an `Arc` so an engine-level scan can keep iterating a table after releasing the
+    // `sstables` lock — a concurrent compaction may drop the table from the level list, but
+    // the mmap stays valid until the last iterator referencing it is gone.
+    //
+    // With `verify_checksums` set, every data block must match its checksum as it is loaded.
+    pub fn new(reader: Arc<SSTableReader>, verify_checksums: bool) -> Self {
         let mut iter = Self {
             reader,
-            current_block_idx: 0,
-            current_block_data: None,
-            current_block_ptr: 0,
-            current_key: Vec::new(),
-            index_ptr: 0,
-            index_key: Vec::new(),
+            verify_checksums,
+            index_entry: None,
+            block_data: None,
+            block_offset: 0,
+            entry: None,
+            error: None,
         };
 
-        iter.load_next_block();
+        iter.seek_to_first();
         iter
     }
 
-    fn load_next_block(&mut self) {
-        let index_block = BlockReader::new(&self.reader.index_data);
-
-        // `restarts_offset` marks the start of the restart-point array at the tail of the
-        // index block. Once index_ptr reaches it, we have consumed all index entries.
-        if self.index_ptr >= index_block.restarts_offset {
-            self.current_block_data = None;
-            return;
+    // Decompresses the data block named by `index_entry` into `block_data`. Returns false
+    // when there is no such block, or it cannot be read and the iterator has failed.
+    fn load_block(&mut self) -> bool {
+        self.block_data = None;
+        if self.error.is_some() {
+            return false;
         }
+        let Some(index_entry) = &self.index_entry else {
+            return false;
+        };
 
-        let ptr = self.index_ptr;
-        let (shared_len, len1) = varint::decode_u32(&index_block.data[ptr..]).unwrap();
-        let mut ptr = ptr + len1;
-
-        let (unshared_len, len2) = varint::decode_u32(&index_block.data[ptr..]).unwrap();
-        ptr += len2;
+        // Each index entry value is [data_block_offset, data_block_size] encoded as varints.
+        // The offset is absolute within the mmap — no need to track a running base pointer.
+        let handle = index_entry.value(&self.reader.index_data);
+        let block = self
+            .reader
+            .decode_handle(handle)
+            .and_then(|(offset, size)| {
+                self.block_offset = offset;
+                self.reader.load_block(offset, size, self.verify_checksums)
+            });
+        match block {
+            Ok(block) => {
+                self.block_data = Some(block);
+                true
+            }
+            Err(e) => {
+                self.fail(e);
+                false
+            }
+        }
+    }
 
-        let (value_len, len3) = varint::decode_u32(&index_block.data[ptr..]).unwrap();
-        ptr += len3;
+    // Gives up on the table at the current position.
+    fn fail(&mut self, error: Error) {
+        self.error = Some(error);
+        self.index_entry = None;
+        self.entry = None;
+    }
 
-        self.index_key.truncate(shared_len as usize);
-        let unshared_bytes = &index_block.data[ptr..ptr + unshared_len as usize];
-        self.index_key.extend_from_slice(unshared_bytes);
-        ptr += unshared_len as usize;
+    // Gives up because an entry of the current block does not decode.
+    fn fail_in_block(&mut self, message: &str) {
+        let error = Error::corruption(&self.reader.path, self.block_offset, message);
+        self.fail(error);
+    }
 
-        let value_bytes = &index_block.data[ptr..ptr + value_len as usize];
-        ptr += value_len as usize;
+    fn fail_in_index(&mut self) {
+        let error = Error::corruption(
+            &self.reader.path,
+            self.reader.index_offset,
+            "Index entry does not decode",
+        );
+        self.fail(error);
+    }
 
-        self.index_ptr = ptr;
+    fn next_index_entry(&mut self) {
+        let reader = Arc::clone(&self.reader);
+        let (Some(e), Some(index_block)) =
+            (self.index_entry.take(), block_reader(&reader.index_data))
+        else {
+            return;
+        };
+        if e.next_offset < index_block.restarts_offset {
+            let mut key = e.key;
+            self.index_entry = index_block
+                .entry_at(e.next_offset, &mut key)
+                .map(EntryPos::from);
+            if self.index_entry.is_none() {
+                self.fail_in_index();
+            }
+        }
+    }
 
-        // Each index entry value is [data_block_offset, data_block_size] encoded as varints.
-        // The offset is absolute within the mmap — no need to track a running base pointer.
-        let mut v_ptr = 0;
-        let (offset, v_len1) = varint::decode_u64(&value_bytes[v_ptr..]).unwrap();
-        v_ptr += v_len1;
-        let (size, _) = varint::decode_u64(&value_bytes[v_ptr..]).unwrap();
-
-        let raw_block = &self.reader.mmap[offset as usize..offset as usize + size as usize];
-        let Some(block_data) = decompress_block(raw_block) else {
-            // Silently skip blocks with unrecognized compression rather than panicking,
-            // so a partially-migrated file doesn't take down the whole compaction run.
+    fn prev_index_entry(&mut self) {
+        let reader = Arc::clone(&self.reader);
+        let (Some(e), Some(index_block)) =
+            (self.index_entry.take(), block_reader(&reader.index_data))
+        else {
             return;
         };
+        if e.offset > 0 {
+            self.index_entry = index_block.prev_entry(e.offset).map(EntryPos::from);
+            if self.index_entry.is_none() {
+                self.fail_in_index();
+            }
+        }
+    }
+
+    // Lands on the first entry of the current block. The builder never writes an empty
+    // block, so a block without one is damaged.
+    fn enter_block_forward(&mut self) {
+        self.entry = None;
+        if self.load_block() {
+            let data = self.block_data.as_deref().unwrap();
+            self.entry = block_reader(data)
+                .and_then(|block| block.first_entry())
+                .map(EntryPos::from);
+            if self.entry.is_none() {
+                self.fail_in_block("Data block holds no entries");
+            }
+        }
+    }
 
-        self.current_block_data = Some(block_data);
-        self.current_block_ptr = 0;
-        self.current_block_idx += 1;
-        self.current_key.clear();
+    // Lands on the last entry of the current block.
+    fn enter_block_backward(&mut self) {
+        self.entry = None;
+        if self.load_block() {
+            let data = self.block_data.as_deref().unwrap();
+            self.entry = block_reader(data)
+                .and_then(|block| block.last_entry())
+                .map(EntryPos::from);
+            if self.entry.is_none() {
+                self.fail_in_block("Data block holds no entries");
+            }
+        }
     }
 }
 
-impl Iterator for SSTableIterator {
-    type Item = (Vec<u8>, Vec<u8>);
+impl InternalIterator for SSTableIterator {
+    fn valid(&self) -> bool {
+        self.entry.is_some()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if let Some(block_data) = &self.current_block_data {
-                let block_reader = BlockReader::new(block_data);
+    fn key(&self) -> &[u8] {
+        &self
+            .entry
+            .as_ref()
+            .expect("SSTableIterator is not valid")
+            .key
+    }
 
-                // Exhausted this block — advance to the next one and retry in the same loop
-                // iteration rather than returning None prematurely.
-                if self.current_block_ptr >= block_reader.restarts_offset {
-                    self.load_next_block();
-                    continue;
-                }
+    fn value(&self) -> &[u8] {
+        let entry = self.entry.as_ref().expect("SSTableIterator is not valid");
+        entry.value(self.block_data.as_deref().unwrap())
+    }
 
-                let ptr = self.current_block_ptr;
-                let (shared_len, len1) = varint::decode_u32(&block_data[ptr..]).unwrap();
-                let mut ptr = ptr + len1;
+    fn seek_to_first(&mut self) {
+        let reader = Arc::clone(&self.reader);
+        self.index_entry = block_reader(&reader.index_data)
+            .and_then(|index| index.first_entry())
+            .map(EntryPos::from);
+        self.enter_block_forward();
+    }
 
-                let (unshared_len, len2) = varint::decode_u32(&block_data[ptr..]).unwrap();
-                ptr += len2;
+    fn seek_to_last(&mut self) {
+        let reader = Arc::clone(&self.reader);
+        self.index_entry = block_reader(&reader.index_data)
+            .and_then(|index| index.last_entry())
+            .map(EntryPos::from);
+        self.enter_block_backward();
+    }
 
-                let (value_len, len3) = varint::decode_u32(&block_data[ptr..]).unwrap();
-                ptr += len3;
+    /// Each index entry's key is the LAST key of its data block, so the first index entry at
+    /// or after `key` names the only block that can hold the target. Both the index and the data
+    /// block are binary-searched over their restart points, so a seek costs two O(log n)
+    /// lookups plus one block decompression — no matter how deep into the table the key is.
+    fn seek(&mut self, key: &[u8]) {
+        let reader = Arc::clone(&self.reader);
+        self.index_entry = block_reader(&reader.index_data)
+            .and_then(|index| index.seek(key))
+            .map(EntryPos::from);
 
-                self.current_key.truncate(shared_len as usize);
-                let unshared_bytes = &block_data[ptr..ptr + unshared_len as usize];
-                self.current_key.extend_from_slice(unshared_bytes);
-                ptr += unshared_len as usize;
+        self.entry = None;
+        if self.load_block() {
+            let data = self.block_data.as_deref().unwrap();
+            self.entry = block_reader(data)
+                .and_then(|block| block.seek(key))
+                .map(EntryPos::from);
+            // The index promised a key >= `key` in this block.
+            if self.entry.is_none() {
+                self.fail_in_block("Data block does not hold the keys its index entry promises");
+            }
+        }
+    }
 
-                let value_bytes = &block_data[ptr..ptr + value_len as usize];
-                ptr += value_len as usize;
+    /// The block found by the index seek is the first one whose last key is >= `key`, so if
+    /// every key in it is greater than `key` the answer is the last entry of the block before.
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        let reader = Arc::clone(&self.reader);
+        self.index_entry = block_reader(&reader.index_data)
+            .and_then(|index| index.seek(key))
+            .map(EntryPos::from);
 
-                self.current_block_ptr = ptr;
+        if self.index_entry.is_none() {
+            // Every key in the table is < `key`.
+            self.seek_to_last();
+            return;
+        }
 
-                return Some((self.current_key.clone(), value_bytes.to_vec()));
-            } else {
-                return None;
+        if self.load_block() {
+            let data = self.block_data.as_deref().unwrap();
+            self.entry = block_reader(data)
+                .and_then(|block| block.seek_for_prev(key))
+                .map(EntryPos::from);
+            if self.entry.is_some() {
+                return;
             }
+            self.prev_index_entry();
+            self.enter_block_backward();
         }
     }
+
+    fn next(&mut self) {
+        let Some(entry) = self.entry.take() else {
+            return;
+        };
+        let data = self.block_data.as_deref().unwrap();
+        // `load_block` made sure the block decodes.
+        let Some(block) = block_reader(data) else {
+            return;
+        };
+
+        if entry.next_offset < block.restarts_offset {
+            let mut key = entry.key;
+            self.entry = block
+                .entry_at(entry.next_offset, &mut key)
+                .map(EntryPos::from);
+            if self.entry.is_none() {
+                self.fail_in_block("Entry does not decode");
+            }
+            return;
+        }
+
+        self.next_index_entry();
+        self.enter_block_forward();
+    }
+
+    fn prev(&mut self) {
+        let Some(entry) = self.entry.take() else {
+            return;
+        };
+        let data = self.block_data.as_deref().unwrap();
+        let Some(block) = block_reader(data) else {
+            return;
+        };
+
+        if entry.offset > 0 {
+            self.entry = block.prev_entry(entry.offset).map(EntryPos::from);
+            if self.entry.is_none() {
+                self.fail_in_block("Entry does not decode");
+            }
+            return;
+        }
+
+        self.prev_index_entry();
+        self.enter_block_backward();
+    }
+
+    fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
 }
 
 // ---------------------------------------------------------
@@ -151,6 +357,8 @@ impl Iterator for SSTableIterator {
 // ---------------------------------------------------------
 
 struct HeapItem {
+    // An internal key. Distinct versions of one user key are distinct heap keys, ordered
+    // newest first.
     key: Vec<u8>,
     value: Vec<u8>,
     // The caller passes input_paths newest-first. `table_index` 0 is therefore the newest
@@ -165,9 +373,7 @@ struct HeapItem {
 // without any extra logic in the merge loop.
 impl Ord for HeapItem {
     fn cmp(&self, other: &Self) -> Ordering {
-        other
-            .key
-            .cmp(&self.key)
+        internal_key::compare(&other.key, &self.key)
             .then_with(|| other.table_index.cmp(&self.table_index))
     }
 }
@@ -183,24 +389,85 @@ impl PartialEq for HeapItem {
 }
 impl Eq for HeapItem {}
 
-/// Merges `input_paths` (newest-first) into one SSTable at `output_path`.
+// An entry copied out of a table: its internal key and value.
+type Entry = (Vec<u8>, Vec<u8>);
+
+// Copies out the entry under the cursor and steps past it. Fails if the table could not be
+// read to the end.
+fn take_entry(iter: &mut SSTableIterator) -> Result<Option<Entry>, Error> {
+    if !iter.valid() {
+        return match iter.error.take() {
+            Some(e) => Err(e),
+            None => Ok(None),
+        };
+    }
+    let entry = (iter.key().to_vec(), iter.value().to_vec());
+    iter.next();
+    Ok(Some(entry))
+}
+
+/// Merges `input_paths` (newest-first) into one SSTable at `output_path`, compressed for
+/// `output_level`.
 ///
 /// This is a k-way merge using a min-heap. We seed the heap with the first entry from each
-/// input iterator and then repeatedly pop the smallest key. When the same key appears in
-/// multiple tables, the heap's Ord impl ensures the newest table's version is popped first
-/// (see `HeapItem::cmp`) — subsequent occurrences of the same key are silently skipped via
-/// `last_key_written`. This is how tombstones and overwrites are resolved: the newest version
-/// of a key (even a tombstone) is the one that survives into the output SSTable.
-pub fn compact(input_paths: Vec<PathBuf>, output_path: PathBuf) -> std::io::Result<()> {
-    let mut iterators: Vec<SSTableIterator> = input_paths
+/// input iterator and then repeatedly pop the smallest internal key, which yields every user
+/// key's versions newest first. The same internal key appearing in several tables is written
+/// once (see `HeapItem::cmp` and `last_key_written`).
+///
+/// Older versions are only worth keeping while someone can still read them. `snapshots` holds
+/// the sequence numbers of the live snapshots, sorted ascending, and cuts each key's history
+/// into stripes: two versions are in the same stripe when no snapshot sits between them, so
+/// no reader can tell them apart. The newest version of each stripe is what the readers in it
+/// see, and everything older in the stripe is dropped. Tombstones are versions like any other,
+/// so they survive for as long as they shadow something a reader can see.
+///
+/// Merge operands at the top of a stripe are folded onto the value or tombstone beneath them
+/// in the same stripe, using `options.merge_operator`, and written as a plain value. If the
+/// stripe (or the input) ends first, the base lives elsewhere, so they stay operands —
+/// collapsed into one if the operator supports partial merges.
+///
+/// A version covered by a range tombstone from the same stripe is settled like one shadowed
+/// by a point tombstone, except that it is dropped outright: the tombstone itself lives in
+/// the range-tombstone block, and every input's tombstones are carried into the output.
+///
+/// With `bottommost` set, no table below the output holds data the inputs do not, so a
+/// tombstone in the oldest stripe has nothing left to shadow — everything beneath it was
+/// dropped above — and is dropped too, point and range tombstones alike. One a snapshot
+/// predates still hides versions that snapshot reads past, and stays.
+///
+/// A value written with a TTL whose deadline has passed is rewritten as a point tombstone:
+/// its bytes are reclaimed, while older versions of the key in deeper tables stay shadowed.
+/// Where that tombstone would be dropped at once, the value goes without one.
+///
+/// Returns whether anything survived: if not, no table is left at `output_path`.
+pub fn compact(
+    input_paths: Vec<PathBuf>,
+    output_path: PathBuf,
+    output_level: usize,
+    bottommost: bool,
+    options: &Options,
+    snapshots: &[u64],
+) -> Result<bool, Error> {
+    let readers: Vec<Arc<SSTableReader>> = input_paths
+        .iter()
+        .map(|path| SSTableReader::open(path.clone()).map(Arc::new))
+        .collect::<Result<_, _>>()?;
+    let range_tombstones = RangeTombstones::new(
+        readers
+            .iter()
+            .flat_map(|reader| reader.range_tombstones.tombstones().iter().cloned())
+            .collect(),
+    );
+    let mut iterators: Vec<SSTableIterator> = readers
         .iter()
-        .map(|path| SSTableIterator::new(SSTableReader::new(path.clone())))
+        // Compaction rewrites what it reads, so it must not carry damage into the new tables.
+        .map(|reader| SSTableIterator::new(Arc::clone(reader), true))
         .collect();
 
     let mut heap = BinaryHeap::new();
 
     for (idx, iter) in iterators.iter_mut().enumerate() {
-        if let Some((k, v)) = iter.next() {
+        if let Some((k, v)) = take_entry(iter)? {
             heap.push(HeapItem {
                 key: k,
                 value: v,
@@ -209,18 +476,109 @@ pub fn compact(input_paths: Vec<PathBuf>, output_path: PathBuf) -> std::io::Resu
         }
     }
 
-    let mut builder = super::sst::SSTableBuilder::new(output_path);
+    let operator = options.merge_operator.as_deref();
+    let now = ttl::now();
+    let mut builder = super::sst::SSTableBuilder::new(output_path, options, output_level)?;
+    for tombstone in range_tombstones.tombstones() {
+        if !(bottommost && snapshots.partition_point(|&s| s < tombstone.seq) == 0) {
+            builder.add_range_tombstone(tombstone.clone());
+        }
+    }
     let mut last_key_written: Option<Vec<u8>> = None;
+    // The user key of the previous version popped, and the stripe whose visible version has
+    // already been written for it.
+    let mut current_user_key: Option<Vec<u8>> = None;
+    let mut settled_stripe: Option<usize> = None;
+    // Operands at the top of `pending_stripe` not written yet, newest first.
+    let mut pending: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    let mut pending_stripe = 0;
 
     while let Some(item) = heap.pop() {
-        // The heap guarantees the newest version of each key is popped first. We write it once
-        // and skip any subsequent pops of the same key (older versions from other tables).
         if last_key_written.as_ref() != Some(&item.key) {
-            builder.add(&item.key, &item.value);
+            let user_key = internal_key::user_key(&item.key);
+            // Stripes are numbered by the oldest snapshot that can see the version;
+            // `snapshots.len()` stands for readers newer than every snapshot.
+            let seq = internal_key::sequence(&item.key);
+            let stripe = snapshots.partition_point(|&s| s < seq);
+            // Readers that can see the tombstone see nothing of this version, and if they are
+            // all in its stripe, no reader sees it at all.
+            let covered = range_tombstones
+                .oldest_covering_after(user_key, seq)
+                .is_some_and(|tombstone_seq| {
+                    snapshots.partition_point(|&s| s < tombstone_seq) == stripe
+                });
+
+            let value_type = internal_key::value_type(&item.key);
+            let expired =
+                value_type == Some(ValueType::ExpiringValue) && ttl::is_expired(&item.value, now);
+
+            let new_key = current_user_key.as_deref() != Some(user_key);
+            if new_key || stripe != pending_stripe {
+                write_operands(&mut builder, operator, &mut pending)?;
+            }
+            if new_key {
+                current_user_key = Some(user_key.to_vec());
+                settled_stripe = None;
+            }
+
+            if settled_stripe != Some(stripe) {
+                if covered {
+                    // Operands still pending are newer than the tombstone, so they apply to
+                    // nothing.
+                    match operator {
+                        Some(operator) if !pending.is_empty() => {
+                            write_merged(&mut builder, operator, None, &mut pending)?
+                        }
+                        _ => write_operands(&mut builder, operator, &mut pending)?,
+                    }
+                    settled_stripe = Some(stripe);
+                } else if expired {
+                    // Past its deadline the value reads as a tombstone, so it becomes one.
+                    match operator {
+                        Some(operator) if !pending.is_empty() => {
+                            write_merged(&mut builder, operator, None, &mut pending)?
+                        }
+                        _ => {
+                            write_operands(&mut builder, operator, &mut pending)?;
+                            // The tombstone would be dropped on sight; see `bottommost`.
+                            if !(bottommost && stripe == 0) {
+                                let key = internal_key::encode(user_key, seq, ValueType::Deletion);
+                                builder.add(&key, b"")?;
+                            }
+                        }
+                    }
+                    settled_stripe = Some(stripe);
+                } else if value_type == Some(ValueType::Merge) {
+                    pending_stripe = stripe;
+                    pending.push((item.key.clone(), item.value.clone()));
+                } else {
+                    match operator {
+                        // Operands are not folded into a value that has yet to expire: once it
+                        // does, reads fold them onto nothing.
+                        Some(operator)
+                            if !pending.is_empty()
+                                && value_type != Some(ValueType::ExpiringValue) =>
+                        {
+                            let base = (!internal_key::is_deletion(&item.key))
+                                .then_some(item.value.as_slice());
+                            write_merged(&mut builder, operator, base, &mut pending)?;
+                        }
+                        _ => {
+                            write_operands(&mut builder, operator, &mut pending)?;
+                            let obsolete =
+                                bottommost && stripe == 0 && internal_key::is_deletion(&item.key);
+                            if !obsolete {
+                                builder.add(&item.key, &item.value)?;
+                            }
+                        }
+                    }
+                    settled_stripe = Some(stripe);
+                }
+            }
             last_key_written = Some(item.key.clone());
         }
 
-        if let Some((k, v)) = iterators[item.table_index].next() {
+        if let Some((k, v)) = take_entry(&mut iterators[item.table_index])? {
             heap.push(HeapItem {
                 key: k,
                 value: v,
@@ -228,42 +586,197 @@ pub fn compact(input_paths: Vec<PathBuf>, output_path: PathBuf) -> std::io::Resu
             });
         }
     }
+    write_operands(&mut builder, operator, &mut pending)?;
 
+    if builder.is_empty() {
+        builder.abandon()?;
+        return Ok(false);
+    }
     builder.finish()?;
+    Ok(true)
+}
+
+// Folds the pending operands onto `base` and writes the result as a value. It replaces the
+// newest operand, so it takes its place.
+fn write_merged(
+    builder: &mut super::sst::SSTableBuilder,
+    operator: &dyn MergeOperator,
+    base: Option<&[u8]>,
+    pending: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) -> Result<(), Error> {
+    let Some((newest, _)) = pending.first() else {
+        return Ok(());
+    };
+    let key = internal_key::encode(
+        internal_key::user_key(newest),
+        internal_key::sequence(newest),
+        ValueType::Value,
+    );
+    let operands: Vec<Vec<u8>> = pending.drain(..).rev().map(|(_, v)| v).collect();
+    let merged = merge::full_merge(
+        Some(operator),
+        internal_key::user_key(&key),
+        base,
+        &operands,
+    );
+    builder.add(&key, &merged)
+}
+
+// Writes operands whose base value is not part of this compaction, collapsed into one under
+// the newest operand's key if the operator can partially merge them, or as they are if not.
+fn write_operands(
+    builder: &mut super::sst::SSTableBuilder,
+    operator: Option<&dyn MergeOperator>,
+    pending: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) -> Result<(), Error> {
+    let Some((newest, _)) = pending.first() else {
+        return Ok(());
+    };
+    let operands: Vec<Vec<u8>> = pending.iter().rev().map(|(_, v)| v.clone()).collect();
+    match merge::partial_merge_all(operator, internal_key::user_key(newest), &operands) {
+        Some(collapsed) => builder.add(newest, &collapsed)?,
+        None => {
+            for (key, value) in pending.iter() {
+                builder.add(key, value)?;
+            }
+        }
+    }
+    pending.clear();
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::internal_key::{MAX_SEQUENCE, ValueType};
+    use crate::merge::tests::Append;
+    use crate::range_del::RangeTombstone;
     use crate::sstable::sst::SSTableBuilder;
     use tempfile::NamedTempFile;
 
+    fn ikey(key: &[u8]) -> Vec<u8> {
+        internal_key::encode(key, 1, ValueType::Value)
+    }
+
+    // Like `take_entry`, with the sequence trailer stripped.
+    fn take_user_entry(iter: &mut SSTableIterator) -> Option<(Vec<u8>, Vec<u8>)> {
+        take_entry(iter)
+            .unwrap()
+            .map(|(k, v)| (internal_key::user_key(&k).to_vec(), v))
+    }
+
     #[test]
     fn test_sstable_iterator() {
         let file = NamedTempFile::new().unwrap();
-        let mut sstable = SSTableBuilder::new(file.path().to_path_buf());
+        let mut sstable =
+            SSTableBuilder::new(file.path().to_path_buf(), &Options::default(), 0).unwrap();
 
         // Fill spanning multiple blocks
         for i in 0..1000 {
             let key = format!("key{:04}", i);
             let val = format!("value{:04}", i);
-            sstable.add(key.as_bytes(), val.as_bytes());
+            sstable.add(&ikey(key.as_bytes()), val.as_bytes()).unwrap();
         }
         sstable.finish().unwrap();
 
-        let reader = SSTableReader::new(file.path().to_path_buf());
-        let mut iter = SSTableIterator::new(reader);
+        let reader = SSTableReader::open(file.path().to_path_buf()).unwrap();
+        let mut iter = SSTableIterator::new(Arc::new(reader), true);
 
         for i in 0..1000 {
-            let (k, v) = iter.next().unwrap();
+            let (k, v) = take_user_entry(&mut iter).unwrap();
             let key = format!("key{:04}", i);
             let val = format!("value{:04}", i);
             assert_eq!(k, key.as_bytes());
             assert_eq!(v, val.as_bytes());
         }
 
-        assert_eq!(iter.next(), None);
+        assert_eq!(take_user_entry(&mut iter), None);
+    }
+
+    #[test]
+    fn test_sstable_iterator_seek() {
+        let file = NamedTempFile::new().unwrap();
+        let mut sstable =
+            SSTableBuilder::new(file.path().to_path_buf(), &Options::default(), 0).unwrap();
+
+        // Even keys only, spanning multiple blocks, so odd seek targets fall between entries.
+        for i in (0..1000).step_by(2) {
+            let key = format!("key{:04}", i);
+            sstable.add(&ikey(key.as_bytes()), b"v").unwrap();
+        }
+        sstable.finish().unwrap();
+
+        let reader = Arc::new(SSTableReader::open(file.path().to_path_buf()).unwrap());
+        let mut iter = SSTableIterator::new(Arc::clone(&reader), true);
+
+        // Exact hit deep inside the file.
+        iter.seek(&internal_key::seek_key(b"key0600", MAX_SEQUENCE));
+        assert_eq!(take_user_entry(&mut iter).unwrap().0, b"key0600");
+        assert_eq!(take_user_entry(&mut iter).unwrap().0, b"key0602");
+
+        // Between two keys lands on the next larger one, and seeking backwards works.
+        iter.seek(&internal_key::seek_key(b"key0301", MAX_SEQUENCE));
+        assert_eq!(take_user_entry(&mut iter).unwrap().0, b"key0302");
+
+        // Before the first key and after the last key.
+        iter.seek(&internal_key::seek_key(b"a", MAX_SEQUENCE));
+        assert_eq!(take_user_entry(&mut iter).unwrap().0, b"key0000");
+        iter.seek(&internal_key::seek_key(b"zzz", MAX_SEQUENCE));
+        assert_eq!(take_user_entry(&mut iter), None);
+    }
+
+    #[test]
+    fn test_sstable_iterator_stops_at_a_damaged_block() {
+        let file = NamedTempFile::new().unwrap();
+        let mut sstable =
+            SSTableBuilder::new(file.path().to_path_buf(), &Options::default(), 0).unwrap();
+        for i in 0..1000 {
+            sstable
+                .add(&ikey(format!("key{:04}", i).as_bytes()), b"value")
+                .unwrap();
+        }
+        sstable.finish().unwrap();
+
+        // Give the second data block a compression type that does not exist.
+        let second_block = {
+            let reader = SSTableReader::open(file.path().to_path_buf()).unwrap();
+            let index = block_reader(&reader.index_data).unwrap();
+            let (offset, size) = reader
+                .decode_handle(index.first_entry().unwrap().value)
+                .unwrap();
+            offset + size
+        };
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        bytes[second_block as usize] = 0x7f;
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let reader = Arc::new(SSTableReader::open(file.path().to_path_buf()).unwrap());
+        let mut iter = SSTableIterator::new(Arc::clone(&reader), true);
+        let mut read = 0;
+        while iter.valid() {
+            read += 1;
+            iter.next();
+        }
+        assert!(read > 0 && read < 1000);
+        assert!(matches!(
+            iter.error(),
+            Some(Error::Corruption { offset, .. }) if *offset == second_block
+        ));
+        // The iterator stays failed, instead of skipping ahead to the blocks after.
+        iter.seek_to_last();
+        assert!(!iter.valid());
+
+        // A compaction would otherwise write the table out without the block's entries.
+        let output = NamedTempFile::new().unwrap();
+        let result = compact(
+            vec![file.path().to_path_buf()],
+            output.path().to_path_buf(),
+            1,
+            false,
+            &Options::default(),
+            &[],
+        );
+        assert!(matches!(result, Err(Error::Corruption { .. })));
     }
 
     #[test]
@@ -272,34 +785,58 @@ mod tests {
         let file2 = NamedTempFile::new().unwrap();
         let output = NamedTempFile::new().unwrap();
 
-        let mut t1 = SSTableBuilder::new(file1.path().to_path_buf());
-        t1.add(b"a", b"1");
-        t1.add(b"c", b"3");
-        t1.add(b"e", b"5");
+        let mut t1 =
+            SSTableBuilder::new(file1.path().to_path_buf(), &Options::default(), 0).unwrap();
+        t1.add(&ikey(b"a"), b"1").unwrap();
+        t1.add(&ikey(b"c"), b"3").unwrap();
+        t1.add(&ikey(b"e"), b"5").unwrap();
         t1.finish().unwrap();
 
-        let mut t2 = SSTableBuilder::new(file2.path().to_path_buf());
-        t2.add(b"b", b"2");
-        t2.add(b"d", b"4");
-        t2.add(b"f", b"6");
+        let mut t2 =
+            SSTableBuilder::new(file2.path().to_path_buf(), &Options::default(), 0).unwrap();
+        t2.add(&ikey(b"b"), b"2").unwrap();
+        t2.add(&ikey(b"d"), b"4").unwrap();
+        t2.add(&ikey(b"f"), b"6").unwrap();
         t2.finish().unwrap();
 
         compact(
             vec![file1.path().to_path_buf(), file2.path().to_path_buf()],
             output.path().to_path_buf(),
+            1,
+            false,
+            &Options::default(),
+            &[],
         )
         .unwrap();
 
-        let reader = SSTableReader::new(output.path().to_path_buf());
-        let mut iter = SSTableIterator::new(reader);
+        let reader = SSTableReader::open(output.path().to_path_buf()).unwrap();
+        let mut iter = SSTableIterator::new(Arc::new(reader), true);
 
-        assert_eq!(iter.next().unwrap(), (b"a".to_vec(), b"1".to_vec()));
-        assert_eq!(iter.next().unwrap(), (b"b".to_vec(), b"2".to_vec()));
-        assert_eq!(iter.next().unwrap(), (b"c".to_vec(), b"3".to_vec()));
-        assert_eq!(iter.next().unwrap(), (b"d".to_vec(), b"4".to_vec()));
-        assert_eq!(iter.next().unwrap(), (b"e".to_vec(), b"5".to_vec()));
-        assert_eq!(iter.next().unwrap(), (b"f".to_vec(), b"6".to_vec()));
-        assert_eq!(iter.next(), None);
+        assert_eq!(
+            take_user_entry(&mut iter).unwrap(),
+            (b"a".to_vec(), b"1".to_vec())
+        );
+        assert_eq!(
+            take_user_entry(&mut iter).unwrap(),
+            (b"b".to_vec(), b"2".to_vec())
+        );
+        assert_eq!(
+            take_user_entry(&mut iter).unwrap(),
+            (b"c".to_vec(), b"3".to_vec())
+        );
+        assert_eq!(
+            take_user_entry(&mut iter).unwrap(),
+            (b"d".to_vec(), b"4".to_vec())
+        );
+        assert_eq!(
+            take_user_entry(&mut iter).unwrap(),
+            (b"e".to_vec(), b"5".to_vec())
+        );
+        assert_eq!(
+            take_user_entry(&mut iter).unwrap(),
+            (b"f".to_vec(), b"6".to_vec())
+        );
+        assert_eq!(take_user_entry(&mut iter), None);
     }
 
     #[test]
@@ -308,38 +845,443 @@ mod tests {
         let file_new = NamedTempFile::new().unwrap();
         let output = NamedTempFile::new().unwrap();
 
-        let mut t_old = SSTableBuilder::new(file_old.path().to_path_buf());
-        t_old.add(b"apple", b"old_val");
-        t_old.add(b"banana", b"old_val");
+        let mut t_old =
+            SSTableBuilder::new(file_old.path().to_path_buf(), &Options::default(), 0).unwrap();
+        t_old.add(&ikey(b"apple"), b"old_val").unwrap();
+        t_old.add(&ikey(b"banana"), b"old_val").unwrap();
         t_old.finish().unwrap();
 
-        let mut t_new = SSTableBuilder::new(file_new.path().to_path_buf());
-        t_new.add(b"apple", b"new_val");
-        t_new.add(b"cat", b"new_val"); // entirely new key
+        let mut t_new =
+            SSTableBuilder::new(file_new.path().to_path_buf(), &Options::default(), 0).unwrap();
+        t_new.add(&ikey(b"apple"), b"new_val").unwrap();
+        t_new.add(&ikey(b"cat"), b"new_val").unwrap(); // entirely new key
         t_new.finish().unwrap();
 
         // Run Compaction! input_tables are ordered [NEWEST, OLDEST]
         compact(
             vec![file_new.path().to_path_buf(), file_old.path().to_path_buf()],
             output.path().to_path_buf(),
+            1,
+            false,
+            &Options::default(),
+            &[],
         )
         .unwrap();
 
-        let reader = SSTableReader::new(output.path().to_path_buf());
-        let mut iter = SSTableIterator::new(reader);
+        let reader = SSTableReader::open(output.path().to_path_buf()).unwrap();
+        let mut iter = SSTableIterator::new(Arc::new(reader), true);
 
         // "apple" exists in both, but the NEWEST value must win.
         assert_eq!(
-            iter.next().unwrap(),
+            take_user_entry(&mut iter).unwrap(),
             (b"apple".to_vec(), b"new_val".to_vec())
         );
         // "banana" only exists in the old one
         assert_eq!(
-            iter.next().unwrap(),
+            take_user_entry(&mut iter).unwrap(),
             (b"banana".to_vec(), b"old_val".to_vec())
         );
         // "cat" only exists in the new one
-        assert_eq!(iter.next().unwrap(), (b"cat".to_vec(), b"new_val".to_vec()));
-        assert_eq!(iter.next(), None);
+        assert_eq!(
+            take_user_entry(&mut iter).unwrap(),
+            (b"cat".to_vec(), b"new_val".to_vec())
+        );
+        assert_eq!(take_user_entry(&mut iter), None);
+    }
+
+    #[test]
+    fn test_sstable_iterator_backward() {
+        let file = NamedTempFile::new().unwrap();
+        let mut sstable =
+            SSTableBuilder::new(file.path().to_path_buf(), &Options::default(), 0).unwrap();
+
+        // Even keys only, spanning multiple blocks.
+        for i in (0..1000).step_by(2) {
+            let key = format!("key{:04}", i);
+            let val = format!("value{:04}", i);
+            sstable.add(&ikey(key.as_bytes()), val.as_bytes()).unwrap();
+        }
+        sstable.finish().unwrap();
+
+        let reader = Arc::new(SSTableReader::open(file.path().to_path_buf()).unwrap());
+        let mut iter = SSTableIterator::new(reader, true);
+
+        // A full walk from the back crosses every block boundary in reverse.
+        iter.seek_to_last();
+        for i in (0..1000).step_by(2).rev() {
+            assert!(iter.valid());
+            assert_eq!(
+                internal_key::user_key(iter.key()),
+                format!("key{:04}", i).as_bytes()
+            );
+            assert_eq!(iter.value(), format!("value{:04}", i).as_bytes());
+            iter.prev();
+        }
+        assert!(!iter.valid());
+
+        // Between two keys lands on the next smaller one; an exact hit lands on itself.
+        iter.seek_for_prev(&internal_key::seek_for_prev_key(b"key0301"));
+        assert_eq!(internal_key::user_key(iter.key()), b"key0300");
+        iter.seek_for_prev(&internal_key::seek_for_prev_key(b"key0600"));
+        assert_eq!(internal_key::user_key(iter.key()), b"key0600");
+
+        // Changing direction mid-walk.
+        iter.next();
+        assert_eq!(internal_key::user_key(iter.key()), b"key0602");
+        iter.prev();
+        iter.prev();
+        assert_eq!(internal_key::user_key(iter.key()), b"key0598");
+
+        iter.seek_for_prev(&internal_key::seek_for_prev_key(b"zzz"));
+        assert_eq!(internal_key::user_key(iter.key()), b"key0998");
+        iter.seek_for_prev(&internal_key::seek_for_prev_key(b"a"));
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_compaction_keeps_versions_visible_to_snapshots() {
+        let file_old = NamedTempFile::new().unwrap();
+        let file_new = NamedTempFile::new().unwrap();
+        let output = NamedTempFile::new().unwrap();
+
+        let mut t_old =
+            SSTableBuilder::new(file_old.path().to_path_buf(), &Options::default(), 0).unwrap();
+        t_old
+            .add(&internal_key::encode(b"k", 20, ValueType::Value), b"v20")
+            .unwrap();
+        t_old
+            .add(&internal_key::encode(b"k", 10, ValueType::Value), b"v10")
+            .unwrap();
+        t_old
+            .add(&internal_key::encode(b"k", 5, ValueType::Value), b"v5")
+            .unwrap();
+        t_old.finish().unwrap();
+
+        let mut t_new =
+            SSTableBuilder::new(file_new.path().to_path_buf(), &Options::default(), 0).unwrap();
+        t_new
+            .add(&internal_key::encode(b"k", 40, ValueType::Value), b"v40")
+            .unwrap();
+        t_new
+            .add(&internal_key::encode(b"k", 30, ValueType::Value), b"v30")
+            .unwrap();
+        t_new.finish().unwrap();
+
+        // Snapshot 12 reads v10 and snapshot 35 reads v30. Nobody can read v20 or v5 any more.
+        compact(
+            vec![file_new.path().to_path_buf(), file_old.path().to_path_buf()],
+            output.path().to_path_buf(),
+            1,
+            false,
+            &Options::default(),
+            &[12, 35],
+        )
+        .unwrap();
+
+        let reader = SSTableReader::open(output.path().to_path_buf()).unwrap();
+        let mut iter = SSTableIterator::new(Arc::new(reader), true);
+        let mut seqs = Vec::new();
+        while let Some((k, _)) = take_entry(&mut iter).unwrap() {
+            seqs.push(internal_key::sequence(&k));
+        }
+        assert_eq!(seqs, vec![40, 30, 10]);
+    }
+
+    #[test]
+    fn test_compaction_folds_merge_operands() {
+        let input = NamedTempFile::new().unwrap();
+        let output = NamedTempFile::new().unwrap();
+
+        let merge = |k: &[u8], seq| internal_key::encode(k, seq, ValueType::Merge);
+        let mut table =
+            SSTableBuilder::new(input.path().to_path_buf(), &Options::default(), 0).unwrap();
+        // "a": operands on top of a value, with the snapshot at 42 between them.
+        table.add(&merge(b"a", 50), b"a50").unwrap();
+        table.add(&merge(b"a", 40), b"a40").unwrap();
+        table
+            .add(&internal_key::encode(b"a", 30, ValueType::Value), b"a30")
+            .unwrap();
+        table.add(&merge(b"a", 20), b"a20").unwrap();
+        // "b": operands on top of a tombstone.
+        table.add(&merge(b"b", 30), b"b30").unwrap();
+        table
+            .add(&internal_key::encode(b"b", 20, ValueType::Deletion), b"")
+            .unwrap();
+        // "c": operands whose base is in some other table.
+        table.add(&merge(b"c", 30), b"c30").unwrap();
+        table.add(&merge(b"c", 20), b"c20").unwrap();
+        table.finish().unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(Append)),
+            ..Options::default()
+        };
+        compact(
+            vec![input.path().to_path_buf()],
+            output.path().to_path_buf(),
+            1,
+            false,
+            &options,
+            &[42],
+        )
+        .unwrap();
+
+        let reader = SSTableReader::open(output.path().to_path_buf()).unwrap();
+        let mut iter = SSTableIterator::new(Arc::new(reader), true);
+        let mut entries = Vec::new();
+        while let Some((k, v)) = take_entry(&mut iter).unwrap() {
+            let k = (
+                String::from_utf8(internal_key::user_key(&k).to_vec()).unwrap(),
+                internal_key::sequence(&k),
+                internal_key::value_type(&k).unwrap(),
+            );
+            entries.push((k, String::from_utf8(v).unwrap()));
+        }
+
+        let entry = |k: &str, seq, t, v: &str| ((k.to_string(), seq, t), v.to_string());
+        assert_eq!(
+            entries,
+            vec![
+                // Snapshot 42 reads "a30,a40", so a50 cannot be folded into it.
+                entry("a", 50, ValueType::Merge, "a50"),
+                entry("a", 40, ValueType::Value, "a30,a40"),
+                entry("b", 30, ValueType::Value, "b30"),
+                entry("c", 30, ValueType::Merge, "c20,c30"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compaction_drops_data_under_range_tombstones() {
+        let newer = NamedTempFile::new().unwrap();
+        let older = NamedTempFile::new().unwrap();
+        let output = NamedTempFile::new().unwrap();
+
+        let value = |k: &[u8], seq| internal_key::encode(k, seq, ValueType::Value);
+        // `[b, e)@60` sits in a newer table than most of the data it covers.
+        let mut table =
+            SSTableBuilder::new(newer.path().to_path_buf(), &Options::default(), 0).unwrap();
+        table.add(&value(b"c", 70), b"c70").unwrap();
+        table
+            .add(&internal_key::encode(b"d", 65, ValueType::Merge), b"d65")
+            .unwrap();
+        table.add_range_tombstone(RangeTombstone {
+            start: b"b".to_vec(),
+            end: b"e".to_vec(),
+            seq: 60,
+        });
+        table.finish().unwrap();
+
+        let mut table =
+            SSTableBuilder::new(older.path().to_path_buf(), &Options::default(), 0).unwrap();
+        table.add(&value(b"a", 10), b"a10").unwrap();
+        table.add(&value(b"b", 50), b"b50").unwrap();
+        table.add(&value(b"b", 30), b"b30").unwrap();
+        table.add(&value(b"c", 20), b"c20").unwrap();
+        table.add(&value(b"d", 20), b"d20").unwrap();
+        table.add(&value(b"e", 20), b"e20").unwrap();
+        table.finish().unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(Append)),
+            ..Options::default()
+        };
+        compact(
+            vec![newer.path().to_path_buf(), older.path().to_path_buf()],
+            output.path().to_path_buf(),
+            1,
+            false,
+            &options,
+            &[40],
+        )
+        .unwrap();
+
+        let reader = Arc::new(SSTableReader::open(output.path().to_path_buf()).unwrap());
+        assert_eq!(reader.range_tombstones.tombstones().len(), 1);
+        let mut iter = SSTableIterator::new(Arc::clone(&reader), true);
+        let mut entries = Vec::new();
+        while let Some((k, v)) = take_entry(&mut iter).unwrap() {
+            let k = String::from_utf8(internal_key::user_key(&k).to_vec()).unwrap();
+            entries.push((k, String::from_utf8(v).unwrap()));
+        }
+
+        let entry = |k: &str, v: &str| (k.to_string(), v.to_string());
+        assert_eq!(
+            entries,
+            vec![
+                entry("a", "a10"),
+                // b50 is covered, but b30 is what snapshot 40 reads.
+                entry("b", "b30"),
+                entry("c", "c70"),
+                entry("c", "c20"),
+                // The operand is newer than the tombstone, so it lands on nothing.
+                entry("d", "d65"),
+                entry("d", "d20"),
+                entry("e", "e20"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bottommost_compaction_drops_settled_tombstones() {
+        let input = NamedTempFile::new().unwrap();
+        let output = NamedTempFile::new().unwrap();
+
+        let entry = |k: &[u8], seq, t| internal_key::encode(k, seq, t);
+        let mut table =
+            SSTableBuilder::new(input.path().to_path_buf(), &Options::default(), 0).unwrap();
+        // Every reader sees "a" deleted, but snapshot 35 still reads b20 past the tombstone.
+        table
+            .add(&entry(b"a", 30, ValueType::Deletion), b"")
+            .unwrap();
+        table
+            .add(&entry(b"a", 10, ValueType::Value), b"a10")
+            .unwrap();
+        table
+            .add(&entry(b"b", 40, ValueType::Deletion), b"")
+            .unwrap();
+        table
+            .add(&entry(b"b", 20, ValueType::Value), b"b20")
+            .unwrap();
+        table
+            .add(&entry(b"c", 20, ValueType::Value), b"c20")
+            .unwrap();
+        table.add_range_tombstone(RangeTombstone {
+            start: b"c".to_vec(),
+            end: b"e".to_vec(),
+            seq: 30,
+        });
+        table.add_range_tombstone(RangeTombstone {
+            start: b"x".to_vec(),
+            end: b"z".to_vec(),
+            seq: 60,
+        });
+        table.finish().unwrap();
+
+        let compacted = |bottommost| {
+            let written = compact(
+                vec![input.path().to_path_buf()],
+                output.path().to_path_buf(),
+                1,
+                bottommost,
+                &Options::default(),
+                &[35],
+            )
+            .unwrap();
+            assert!(written);
+            let reader = Arc::new(SSTableReader::open(output.path().to_path_buf()).unwrap());
+            let mut iter = SSTableIterator::new(Arc::clone(&reader), true);
+            let mut entries = Vec::new();
+            while let Some((k, _)) = take_entry(&mut iter).unwrap() {
+                entries.push((
+                    internal_key::user_key(&k).to_vec(),
+                    internal_key::sequence(&k),
+                ));
+            }
+            let range_seqs: Vec<u64> = reader
+                .range_tombstones
+                .tombstones()
+                .iter()
+                .map(|t| t.seq)
+                .collect();
+            (entries, range_seqs)
+        };
+
+        // Deeper tables may still hold older versions, so every tombstone stays.
+        assert_eq!(
+            compacted(false),
+            (
+                vec![
+                    (b"a".to_vec(), 30),
+                    (b"b".to_vec(), 40),
+                    (b"b".to_vec(), 20)
+                ],
+                vec![30, 60]
+            )
+        );
+        // At the bottom, only what snapshot 35 needs stays.
+        assert_eq!(
+            compacted(true),
+            (vec![(b"b".to_vec(), 40), (b"b".to_vec(), 20)], vec![60])
+        );
+
+        // With no snapshot, nothing survives, and no table is written.
+        std::fs::remove_file(output.path()).unwrap();
+        let written = compact(
+            vec![input.path().to_path_buf()],
+            output.path().to_path_buf(),
+            1,
+            true,
+            &Options::default(),
+            &[],
+        )
+        .unwrap();
+        assert!(!written);
+        assert!(!output.path().exists());
+        assert!(!output.path().with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn test_compaction_rewrites_or_drops_expired_values() {
+        let input = NamedTempFile::new().unwrap();
+        let output = NamedTempFile::new().unwrap();
+
+        let expiring = |k: &[u8], seq| internal_key::encode(k, seq, ValueType::ExpiringValue);
+        let mut table =
+            SSTableBuilder::new(input.path().to_path_buf(), &Options::default(), 0).unwrap();
+        // "a" expired long ago, and shadows an older plain value.
+        table
+            .add(&expiring(b"a", 20), &ttl::encode(1, b"a20"))
+            .unwrap();
+        table
+            .add(&internal_key::encode(b"a", 10, ValueType::Value), b"a10")
+            .unwrap();
+        // "b" has yet to expire, so the operand above it is not folded in.
+        table
+            .add(&internal_key::encode(b"b", 30, ValueType::Merge), b"b30")
+            .unwrap();
+        table
+            .add(&expiring(b"b", 20), &ttl::encode(u64::MAX, b"b20"))
+            .unwrap();
+        table.finish().unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(Append)),
+            ..Options::default()
+        };
+        let compacted = |bottommost| {
+            compact(
+                vec![input.path().to_path_buf()],
+                output.path().to_path_buf(),
+                1,
+                bottommost,
+                &options,
+                &[],
+            )
+            .unwrap();
+            let reader = SSTableReader::open(output.path().to_path_buf()).unwrap();
+            let mut iter = SSTableIterator::new(Arc::new(reader), true);
+            let mut entries = Vec::new();
+            while let Some((k, v)) = take_entry(&mut iter).unwrap() {
+                entries.push((internal_key::value_type(&k).unwrap(), v));
+            }
+            entries
+        };
+        assert_eq!(
+            compacted(false),
+            vec![
+                (ValueType::Deletion, Vec::new()),
+                (ValueType::Merge, b"b30".to_vec()),
+                (ValueType::ExpiringValue, ttl::encode(u64::MAX, b"b20")),
+            ]
+        );
+        // With nothing deeper for it to shadow, "a" leaves no tombstone either.
+        assert_eq!(
+            compacted(true),
+            vec![
+                (ValueType::Merge, b"b30".to_vec()),
+                (ValueType::ExpiringValue, ttl::encode(u64::MAX, b"b20")),
+            ]
+        );
     }
 }
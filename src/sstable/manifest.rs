@@ -1,3 +1,6 @@
+use crate::Error;
+use crate::column_family::DEFAULT_COLUMN_FAMILY_NAME;
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::Path;
@@ -5,104 +8,338 @@ use std::path::Path;
 /// Represents a delta change to the state of the LSM Tree.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VersionEdit {
-    /// Adds a new SSTable to a specific Level
-    AddTable { level: u32, sst_id: u64 },
-    /// Removes an SSTable from a specific Level (due to Compaction)
-    RemoveTable { level: u32, sst_id: u64 },
+    /// Adds a new SSTable to a specific Level of column family `cf`
+    AddTable { cf: u32, level: u32, sst_id: u64 },
+    /// Removes an SSTable from a specific Level of column family `cf` (due to Compaction)
+    RemoveTable { cf: u32, level: u32, sst_id: u64 },
+    /// Records that every sequence number up to `seq` has been used. Logged on each flush,
+    /// because the WAL files holding those sequence numbers are deleted afterwards.
+    LastSequence { seq: u64 },
+    /// Creates column family `id`, named `name`.
+    AddColumnFamily { id: u32, name: String },
+    /// Records that every write to column family `cf` up to sequence number `seq` is in its
+    /// SSTables. The WAL is shared by all families, so its files outlive a flush of one of
+    /// them, and replaying those writes again would put them in front of newer SSTables.
+    FlushedSequence { cf: u32, seq: u64 },
 }
 
+// Edits are `[tag (1)] [u32 (4 LE)] [u64 (8 LE)]` — 13 bytes — except where noted. The first
+// three tags predate column families and still encode the edits of the default family, so a
+// database that never creates another family keeps the original format.
+const TAG_ADD_TABLE: u8 = 1;
+const TAG_REMOVE_TABLE: u8 = 2;
+const TAG_LAST_SEQUENCE: u8 = 3;
+// `[tag (1)] [id (4 LE)] [name length (2 LE)] [name]`
+const TAG_ADD_COLUMN_FAMILY: u8 = 4;
+// `[tag (1)] [cf (4 LE)] [level (4 LE)] [sst_id (8 LE)]`
+const TAG_ADD_TABLE_CF: u8 = 5;
+const TAG_REMOVE_TABLE_CF: u8 = 6;
+const TAG_FLUSHED_SEQUENCE: u8 = 7;
+
 impl VersionEdit {
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(13);
+        let mut buf = Vec::with_capacity(17);
         match self {
-            VersionEdit::AddTable { level, sst_id } => {
-                buf.push(1);
+            VersionEdit::AddTable { cf, level, sst_id }
+            | VersionEdit::RemoveTable { cf, level, sst_id } => {
+                let adds = matches!(self, VersionEdit::AddTable { .. });
+                if *cf == 0 {
+                    buf.push(if adds {
+                        TAG_ADD_TABLE
+                    } else {
+                        TAG_REMOVE_TABLE
+                    });
+                } else {
+                    buf.push(if adds {
+                        TAG_ADD_TABLE_CF
+                    } else {
+                        TAG_REMOVE_TABLE_CF
+                    });
+                    buf.extend_from_slice(&cf.to_le_bytes());
+                }
                 buf.extend_from_slice(&level.to_le_bytes());
                 buf.extend_from_slice(&sst_id.to_le_bytes());
             }
-            VersionEdit::RemoveTable { level, sst_id } => {
-                buf.push(2);
-                buf.extend_from_slice(&level.to_le_bytes());
-                buf.extend_from_slice(&sst_id.to_le_bytes());
+            // Same fixed-size record as the table edits, with the level field unused.
+            VersionEdit::LastSequence { seq } => {
+                buf.push(TAG_LAST_SEQUENCE);
+                buf.extend_from_slice(&0u32.to_le_bytes());
+                buf.extend_from_slice(&seq.to_le_bytes());
+            }
+            VersionEdit::AddColumnFamily { id, name } => {
+                buf.push(TAG_ADD_COLUMN_FAMILY);
+                buf.extend_from_slice(&id.to_le_bytes());
+                buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                buf.extend_from_slice(name.as_bytes());
+            }
+            VersionEdit::FlushedSequence { cf, seq } => {
+                buf.push(TAG_FLUSHED_SEQUENCE);
+                buf.extend_from_slice(&cf.to_le_bytes());
+                buf.extend_from_slice(&seq.to_le_bytes());
             }
         }
         buf
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
-        if bytes.len() < 13 {
-            return None;
-        }
-        let tag = bytes[0];
-        let level = u32::from_le_bytes(bytes[1..5].try_into().unwrap());
-        let sst_id = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+    /// Decodes the edit at the start of `bytes` and returns it with its length, or `None` if
+    /// `bytes` does not start with a whole edit — the torn tail of an interrupted append.
+    pub fn from_bytes(bytes: &[u8]) -> Option<(Self, usize)> {
+        let u32_at = |at: usize| Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+        let u64_at = |at: usize| Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?));
 
-        match tag {
-            1 => Some(VersionEdit::AddTable { level, sst_id }),
-            2 => Some(VersionEdit::RemoveTable { level, sst_id }),
+        match *bytes.first()? {
+            TAG_ADD_TABLE => Some((
+                VersionEdit::AddTable {
+                    cf: 0,
+                    level: u32_at(1)?,
+                    sst_id: u64_at(5)?,
+                },
+                13,
+            )),
+            TAG_REMOVE_TABLE => Some((
+                VersionEdit::RemoveTable {
+                    cf: 0,
+                    level: u32_at(1)?,
+                    sst_id: u64_at(5)?,
+                },
+                13,
+            )),
+            TAG_LAST_SEQUENCE => Some((VersionEdit::LastSequence { seq: u64_at(5)? }, 13)),
+            TAG_ADD_COLUMN_FAMILY => {
+                let name_len = u16::from_le_bytes(bytes.get(5..7)?.try_into().ok()?) as usize;
+                let name = String::from_utf8(bytes.get(7..7 + name_len)?.to_vec()).ok()?;
+                Some((
+                    VersionEdit::AddColumnFamily {
+                        id: u32_at(1)?,
+                        name,
+                    },
+                    7 + name_len,
+                ))
+            }
+            TAG_ADD_TABLE_CF => Some((
+                VersionEdit::AddTable {
+                    cf: u32_at(1)?,
+                    level: u32_at(5)?,
+                    sst_id: u64_at(9)?,
+                },
+                17,
+            )),
+            TAG_REMOVE_TABLE_CF => Some((
+                VersionEdit::RemoveTable {
+                    cf: u32_at(1)?,
+                    level: u32_at(5)?,
+                    sst_id: u64_at(9)?,
+                },
+                17,
+            )),
+            TAG_FLUSHED_SEQUENCE => Some((
+                VersionEdit::FlushedSequence {
+                    cf: u32_at(1)?,
+                    seq: u64_at(5)?,
+                },
+                13,
+            )),
             _ => None,
         }
     }
 }
 
+/// One column family's tables, as reconstructed by [`Manifest::recover`].
+#[derive(Debug, Default)]
+pub struct ColumnFamilyState {
+    pub name: String,
+    /// The `sst_id`s of the active tables, one vector per Level.
+    pub levels: Vec<Vec<u64>>,
+    /// Every write to the family up to this sequence number is in its SSTables.
+    pub flushed_sequence: u64,
+}
+
+/// The database layout reconstructed by [`Manifest::recover`].
+#[derive(Debug)]
+pub struct ManifestState {
+    /// Every column family, by id. The default family (id 0) is always present.
+    pub column_families: BTreeMap<u32, ColumnFamilyState>,
+    /// The highest sequence number recorded by a flush, or 0 if none was.
+    pub last_sequence: u64,
+}
+
+impl Default for ManifestState {
+    fn default() -> Self {
+        let default_family = ColumnFamilyState {
+            name: DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+            ..ColumnFamilyState::default()
+        };
+        Self {
+            column_families: BTreeMap::from([(0, default_family)]),
+            last_sequence: 0,
+        }
+    }
+}
+
 /// The Manifest persistently tracks the definitive layout of SSTables across all Levels.
 /// If an `.sst` file exists on disk but is NOT active in the Manifest, it is an orphaned
 /// ghost file from a crashed Compaction and must be safely ignored/deleted.
 pub struct Manifest {
-    file: File,
+    // `None` when the database was opened read-only.
+    file: Option<File>,
 }
 
 impl Manifest {
     /// Opens the Manifest log in append mode. Creates it if it doesn't exist.
-    pub fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
         let file = OpenOptions::new().create(true).append(true).open(path)?;
 
-        Ok(Self { file })
+        Ok(Self { file: Some(file) })
+    }
+
+    /// A Manifest that refuses every edit, for a database opened read-only. The log itself is
+    /// only read, by [`recover`](Self::recover).
+    pub fn read_only() -> Self {
+        Self { file: None }
+    }
+
+    fn file(&mut self) -> Result<&mut File, Error> {
+        self.file.as_mut().ok_or(Error::ReadOnly)
     }
 
     /// Logs a specific state mutation (e.g. creating a Level 0 table, or merging tables to Level 1).
-    pub fn log_edit(&mut self, edit: &VersionEdit) -> Result<(), anyhow::Error> {
+    pub fn log_edit(&mut self, edit: &VersionEdit) -> Result<(), Error> {
         let bytes = edit.to_bytes();
-        self.file.write_all(&bytes)?;
-        self.file.sync_data()?;
+        let file = self.file()?;
+        file.write_all(&bytes)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Forces the log to physical storage. Every edit is already synced as it is logged, so
+    /// this only matters if that ever fails half-way.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.file()?.sync_all()?;
         Ok(())
     }
 
     /// Replays the entire history of the Manifest to reconstruct the layout of the Database.
-    /// Returns a Vector for each Level, containing the `sst_id` of active tables.
-    pub fn recover(path: impl AsRef<Path>) -> Result<Vec<Vec<u64>>, anyhow::Error> {
-        let mut levels: Vec<Vec<u64>> = Vec::new();
+    pub fn recover(path: impl AsRef<Path>) -> Result<ManifestState, Error> {
+        let mut state = ManifestState::default();
 
         if !path.as_ref().exists() {
-            return Ok(levels);
+            return Ok(state);
         }
 
         let mut file = File::open(path)?;
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
 
+        // Edits are appended one at a time, so only the last one can be incomplete.
         let mut ptr = 0;
-        while ptr + 13 <= data.len() {
-            if let Some(edit) = VersionEdit::from_bytes(&data[ptr..ptr + 13]) {
-                match edit {
-                    VersionEdit::AddTable { level, sst_id } => {
-                        let lvl = level as usize;
-                        if levels.len() <= lvl {
-                            levels.resize(lvl + 1, Vec::new());
-                        }
-                        levels[lvl].push(sst_id);
+        while let Some((edit, len)) = VersionEdit::from_bytes(&data[ptr..]) {
+            ptr += len;
+            match edit {
+                VersionEdit::AddTable { cf, level, sst_id } => {
+                    let levels = &mut state.column_families.entry(cf).or_default().levels;
+                    let lvl = level as usize;
+                    if levels.len() <= lvl {
+                        levels.resize(lvl + 1, Vec::new());
                     }
-                    VersionEdit::RemoveTable { level, sst_id } => {
-                        let lvl = level as usize;
-                        if lvl < levels.len() {
-                            levels[lvl].retain(|&id| id != sst_id);
-                        }
+                    levels[lvl].push(sst_id);
+                }
+                VersionEdit::RemoveTable { cf, level, sst_id } => {
+                    let levels = &mut state.column_families.entry(cf).or_default().levels;
+                    let lvl = level as usize;
+                    if lvl < levels.len() {
+                        levels[lvl].retain(|&id| id != sst_id);
                     }
                 }
+                VersionEdit::LastSequence { seq } => {
+                    state.last_sequence = state.last_sequence.max(seq);
+                }
+                VersionEdit::AddColumnFamily { id, name } => {
+                    state.column_families.entry(id).or_default().name = name;
+                }
+                VersionEdit::FlushedSequence { cf, seq } => {
+                    let family = state.column_families.entry(cf).or_default();
+                    family.flushed_sequence = family.flushed_sequence.max(seq);
+                }
             }
-            ptr += 13;
         }
 
-        Ok(levels)
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_recovers_column_families() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("MANIFEST");
+
+        let edits = [
+            VersionEdit::AddTable {
+                cf: 0,
+                level: 0,
+                sst_id: 10,
+            },
+            VersionEdit::AddColumnFamily {
+                id: 1,
+                name: "blobs".to_string(),
+            },
+            VersionEdit::AddTable {
+                cf: 1,
+                level: 0,
+                sst_id: 11,
+            },
+            VersionEdit::FlushedSequence { cf: 1, seq: 42 },
+            VersionEdit::LastSequence { seq: 42 },
+            VersionEdit::AddTable {
+                cf: 1,
+                level: 1,
+                sst_id: 12,
+            },
+            VersionEdit::RemoveTable {
+                cf: 1,
+                level: 0,
+                sst_id: 11,
+            },
+        ];
+        let mut manifest = Manifest::open(&path).unwrap();
+        for edit in &edits {
+            manifest.log_edit(edit).unwrap();
+        }
+        // The default family's table edits keep the original 13-byte format.
+        assert_eq!(edits[0].to_bytes().len(), 13);
+        for edit in &edits {
+            let bytes = edit.to_bytes();
+            assert_eq!(
+                VersionEdit::from_bytes(&bytes),
+                Some((edit.clone(), bytes.len()))
+            );
+        }
+
+        // A torn final edit is ignored.
+        let torn = VersionEdit::AddTable {
+            cf: 1,
+            level: 0,
+            sst_id: 13,
+        }
+        .to_bytes();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&torn[..10])
+            .unwrap();
+
+        let state = Manifest::recover(&path).unwrap();
+        assert_eq!(state.last_sequence, 42);
+        assert_eq!(state.column_families.len(), 2);
+        assert_eq!(state.column_families[&0].name, "default");
+        assert_eq!(state.column_families[&0].levels, vec![vec![10]]);
+        let blobs = &state.column_families[&1];
+        assert_eq!(blobs.name, "blobs");
+        assert_eq!(blobs.levels, vec![vec![], vec![12]]);
+        assert_eq!(blobs.flushed_sequence, 42);
     }
 }
@@ -0,0 +1,156 @@
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
+use crate::{Error, Snapshot, StorageEngine, WriteBatch};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An optimistic transaction, started by [`StorageEngine::begin`].
+///
+/// Reads see the database as it was when the transaction started, plus the transaction's own
+/// writes. Writes are buffered until [`commit`](Self::commit), which applies them atomically —
+/// unless another writer modified one of the keys the transaction read or wrote after it
+/// started, in which case nothing is written and `commit` fails with a
+/// [`TransactionConflict`]. Dropping a transaction without committing discards its writes.
+///
+/// No locks are held while the transaction runs, so it never blocks other writers; the price
+/// is that a conflicting transaction only finds out at the end, and has to be retried.
+///
+/// ```no_run
+/// use lsmdb::{Error, StorageEngine};
+///
+/// # let engine = StorageEngine::open("/tmp/db").unwrap();
+/// loop {
+///     let mut txn = engine.begin();
+///     let balance = txn.get(b"balance")?.map_or(0, |v| v[0]);
+///     txn.put(b"balance", [balance + 1]);
+///     match txn.commit() {
+///         Err(Error::TransactionConflict(_)) => continue,
+///         result => break result?,
+///     }
+/// }
+/// # Ok::<(), lsmdb::Error>(())
+/// ```
+pub struct Transaction<'a> {
+    engine: &'a StorageEngine,
+    // Keeps the versions the transaction reads from being compacted away.
+    snapshot: Snapshot,
+    // Every key read or written, with its column family id. Commit fails if any of them was
+    // written by someone else after the snapshot.
+    keys: BTreeSet<(u32, Vec<u8>)>,
+    // The transaction's latest write to each key; `None` is a deletion.
+    writes: BTreeMap<(u32, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(engine: &'a StorageEngine, snapshot: Snapshot) -> Self {
+        Self {
+            engine,
+            snapshot,
+            keys: BTreeSet::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Reads `key`: the transaction's own write to it if there is one, or else its value when
+    /// the transaction started.
+    pub fn get<K: AsRef<[u8]>>(&mut self, key: K) -> Result<Option<Vec<u8>>, Error> {
+        self.get_in(
+            DEFAULT_COLUMN_FAMILY_ID,
+            key.as_ref(),
+            |engine, snapshot, key| engine.get_at(snapshot, key),
+        )
+    }
+
+    /// Reads `key` from column family `cf`, like [`get`](Self::get).
+    pub fn get_cf<K: AsRef<[u8]>>(
+        &mut self,
+        cf: &ColumnFamily,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.get_in(cf.id, key.as_ref(), |engine, snapshot, key| {
+            engine.get_at_cf(cf, snapshot, key)
+        })
+    }
+
+    // Reads through `read` unless the transaction wrote the key itself.
+    fn get_in<F>(&mut self, cf: u32, key: &[u8], read: F) -> Result<Option<Vec<u8>>, Error>
+    where
+        F: FnOnce(&StorageEngine, &Snapshot, &[u8]) -> Result<Option<Vec<u8>>, Error>,
+    {
+        let entry = (cf, key.to_vec());
+        if let Some(write) = self.writes.get(&entry) {
+            return Ok(write.clone());
+        }
+        let value = read(self.engine, &self.snapshot, key)?;
+        self.keys.insert(entry);
+        Ok(value)
+    }
+
+    /// Buffers a write of `value` to `key`.
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) {
+        self.write(DEFAULT_COLUMN_FAMILY_ID, key.as_ref(), Some(value.as_ref()));
+    }
+
+    /// Buffers a write of `value` to `key` in column family `cf`.
+    pub fn put_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, cf: &ColumnFamily, key: K, value: V) {
+        self.write(cf.id, key.as_ref(), Some(value.as_ref()));
+    }
+
+    /// Buffers a deletion of `key`.
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.write(DEFAULT_COLUMN_FAMILY_ID, key.as_ref(), None);
+    }
+
+    /// Buffers a deletion of `key` in column family `cf`.
+    pub fn delete_cf<K: AsRef<[u8]>>(&mut self, cf: &ColumnFamily, key: K) {
+        self.write(cf.id, key.as_ref(), None);
+    }
+
+    fn write(&mut self, cf: u32, key: &[u8], value: Option<&[u8]>) {
+        let entry = (cf, key.to_vec());
+        self.keys.insert(entry.clone());
+        self.writes.insert(entry, value.map(<[u8]>::to_vec));
+    }
+
+    /// Applies the transaction's writes atomically, as one [`WriteBatch`].
+    ///
+    /// Fails with a [`TransactionConflict`] — writing nothing — if any key the transaction read
+    /// or wrote was modified by another writer after the transaction started. The check and
+    /// the write happen under the engine's write lock, so no write can slip in between.
+    pub fn commit(self) -> Result<(), Error> {
+        let batch = WriteBatch::from_writes(self.writes);
+        self.engine
+            .commit_transaction(self.snapshot.sequence(), &self.keys, batch)
+    }
+}
+
+/// The error [`Transaction::commit`] fails with when another writer modified one of the
+/// transaction's keys after it started. Nothing was written; retrying the whole transaction
+/// is safe.
+///
+/// It reaches the caller as [`Error::TransactionConflict`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionConflict {
+    key: Vec<u8>,
+}
+
+impl TransactionConflict {
+    pub(crate) fn new(key: Vec<u8>) -> Self {
+        Self { key }
+    }
+
+    /// The key that was modified.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl std::fmt::Display for TransactionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Transaction conflict: key {:?} was modified after the transaction started",
+            String::from_utf8_lossy(&self.key)
+        )
+    }
+}
+
+impl std::error::Error for TransactionConflict {}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A lockable key: a column family id and a user key.
+pub(crate) type LockKey = (u32, Vec<u8>);
+
+/// Why a lock could not be acquired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockFailure {
+    Timeout,
+    Deadlock,
+}
+
+/// Exclusive per-key locks, owned by transaction ids.
+///
+/// Keys are spread over stripes, each a map from key to owner behind its own mutex, so
+/// transactions on unrelated keys rarely touch the same mutex. A waiter sleeps on its stripe's
+/// condvar until the key is released or its timeout runs out.
+///
+/// Deadlocks are found with a wait-for graph: before sleeping, a transaction records which
+/// transaction it waits for and follows the chain of waits from there. A transaction waits for
+/// at most one other, so the graph is a set of chains, and a chain leading back to the waiter
+/// is a deadlock — which the waiter breaks by failing instead of sleeping.
+pub(crate) struct LockManager {
+    stripes: Vec<(Mutex<HashMap<LockKey, u64>>, Condvar)>,
+    // Transaction id -> the transaction holding the lock it waits for. Always locked after a
+    // stripe, never before.
+    waits_for: Mutex<HashMap<u64, u64>>,
+    timeout: Duration,
+}
+
+impl LockManager {
+    pub(crate) fn new(num_stripes: usize, timeout: Duration) -> Self {
+        Self {
+            stripes: (0..num_stripes.max(1))
+                .map(|_| (Mutex::new(HashMap::new()), Condvar::new()))
+                .collect(),
+            waits_for: Mutex::new(HashMap::new()),
+            timeout,
+        }
+    }
+
+    /// Locks `key` for transaction `txn`, waiting for its current owner to release it.
+    /// Locking a key `txn` already holds succeeds at once.
+    pub(crate) fn lock(&self, txn: u64, key: &LockKey) -> Result<(), LockFailure> {
+        let (owners, released) = self.stripe(key);
+        // A poisoned stripe only means a thread panicked between two map updates, each of
+        // which leaves the map consistent.
+        let mut owners = owners.lock().unwrap_or_else(|e| e.into_inner());
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            let owner = match owners.get(key) {
+                None => {
+                    owners.insert(key.clone(), txn);
+                    self.stop_waiting(txn);
+                    return Ok(());
+                }
+                Some(&owner) if owner == txn => return Ok(()),
+                Some(&owner) => owner,
+            };
+
+            if self.wait_would_deadlock(txn, owner) {
+                self.stop_waiting(txn);
+                return Err(LockFailure::Deadlock);
+            }
+
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                self.stop_waiting(txn);
+                return Err(LockFailure::Timeout);
+            };
+            owners = released
+                .wait_timeout(owners, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Releases every key in `keys` that `txn` holds.
+    pub(crate) fn unlock<'a>(&self, txn: u64, keys: impl IntoIterator<Item = &'a LockKey>) {
+        for key in keys {
+            let (owners, released) = self.stripe(key);
+            let mut owners = owners.lock().unwrap_or_else(|e| e.into_inner());
+            if owners.get(key) == Some(&txn) {
+                owners.remove(key);
+                // Waiters for different keys share the condvar, so wake them all; each one
+                // rechecks its own key.
+                released.notify_all();
+            }
+        }
+    }
+
+    fn stripe(&self, key: &LockKey) -> &(Mutex<HashMap<LockKey, u64>>, Condvar) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.stripes[hasher.finish() as usize % self.stripes.len()]
+    }
+
+    // Records that `txn` waits for `owner`, unless `owner` already waits, directly or through
+    // others, for `txn`.
+    fn wait_would_deadlock(&self, txn: u64, owner: u64) -> bool {
+        let mut waits_for = self.waits_for.lock().unwrap_or_else(|e| e.into_inner());
+        let mut next = owner;
+        // Every step visits a different transaction unless the chain loops, so a chain longer
+        // than the map loops without passing through `txn` and cannot be ours to break.
+        for _ in 0..=waits_for.len() {
+            if next == txn {
+                return true;
+            }
+            match waits_for.get(&next) {
+                Some(&waited) => next = waited,
+                None => break,
+            }
+        }
+        waits_for.insert(txn, owner);
+        false
+    }
+
+    fn stop_waiting(&self, txn: u64) {
+        let mut waits_for = self.waits_for.lock().unwrap_or_else(|e| e.into_inner());
+        waits_for.remove(&txn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn key(k: &[u8]) -> LockKey {
+        (0, k.to_vec())
+    }
+
+    // Blocks until `txn` has recorded that it waits for `owner`. The edge is recorded under the
+    // stripe lock the waiter then sleeps on, so a release from here on is sure to wake it.
+    fn wait_for_edge(locks: &LockManager, txn: u64, owner: u64) {
+        while locks.waits_for.lock().unwrap().get(&txn) != Some(&owner) {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_locks_are_exclusive_reentrant_and_time_out() {
+        let locks = LockManager::new(4, Duration::from_millis(50));
+        locks.lock(1, &key(b"a")).unwrap();
+        locks.lock(1, &key(b"a")).unwrap();
+        locks.lock(2, &key(b"b")).unwrap();
+        // The same user key in another column family is another lock.
+        locks.lock(2, &(1, b"a".to_vec())).unwrap();
+
+        assert_eq!(locks.lock(2, &key(b"a")), Err(LockFailure::Timeout));
+
+        locks.unlock(1, [&key(b"a")]);
+        locks.lock(2, &key(b"a")).unwrap();
+        // Unlocking a key held by someone else does nothing.
+        locks.unlock(1, [&key(b"a")]);
+        assert_eq!(locks.lock(3, &key(b"a")), Err(LockFailure::Timeout));
+    }
+
+    #[test]
+    fn test_waiter_gets_lock_when_released() {
+        let locks = Arc::new(LockManager::new(1, Duration::from_secs(5)));
+        locks.lock(1, &key(b"a")).unwrap();
+
+        let waiter = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || locks.lock(2, &key(b"a")))
+        };
+        wait_for_edge(&locks, 2, 1);
+        locks.unlock(1, [&key(b"a")]);
+        assert_eq!(waiter.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn test_deadlock_is_detected() {
+        let locks = Arc::new(LockManager::new(4, Duration::from_secs(5)));
+        locks.lock(1, &key(b"a")).unwrap();
+        locks.lock(2, &key(b"b")).unwrap();
+
+        // Transaction 1 waits for 2, then 2 closes the cycle and fails at once.
+        let first = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || locks.lock(1, &key(b"b")))
+        };
+        wait_for_edge(&locks, 1, 2);
+        assert_eq!(locks.lock(2, &key(b"a")), Err(LockFailure::Deadlock));
+
+        // Once 2 gives up its locks, 1 proceeds.
+        locks.unlock(2, [&key(b"b")]);
+        assert_eq!(first.join().unwrap(), Ok(()));
+    }
+}
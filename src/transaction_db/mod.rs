@@ -0,0 +1,267 @@
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
+use crate::constants::{LOCK_STRIPES, LOCK_TIMEOUT_MS};
+use crate::{Error, Options, StorageEngine, WriteBatch};
+use lock_manager::{LockFailure, LockKey, LockManager};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+mod lock_manager;
+
+/// Tuning for the lock table of a [`TransactionDB`].
+#[derive(Debug, Clone)]
+pub struct TransactionDBOptions {
+    /// See [`LOCK_TIMEOUT_MS`](crate::constants::LOCK_TIMEOUT_MS).
+    pub lock_timeout: Duration,
+    /// See [`LOCK_STRIPES`](crate::constants::LOCK_STRIPES).
+    pub lock_stripes: usize,
+}
+
+impl Default for TransactionDBOptions {
+    fn default() -> Self {
+        Self {
+            lock_timeout: Duration::from_millis(LOCK_TIMEOUT_MS),
+            lock_stripes: LOCK_STRIPES,
+        }
+    }
+}
+
+impl TransactionDBOptions {
+    /// Rejects values the lock table cannot work with.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.lock_stripes == 0 {
+            return Err(Error::invalid(
+                "Invalid options: lock_stripes must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A database whose transactions lock the keys they write, for workloads where optimistic
+/// [`Transaction`](crate::Transaction)s would keep conflicting and retrying.
+///
+/// A [`PessimisticTransaction`] locks a key when it first writes it, or reads it with
+/// [`get_for_update`](PessimisticTransaction::get_for_update), and holds the lock until it
+/// commits or is dropped. A transaction that wants a key another one holds waits for it, up to
+/// [`lock_timeout`](TransactionDBOptions::lock_timeout); if waiting would complete a cycle of
+/// transactions waiting on each other, it fails at once instead. Either way the call fails
+/// with a [`LockError`], and the transaction should be dropped and retried.
+///
+/// Writes made through [`engine`](Self::engine) directly bypass the locks.
+///
+/// ```no_run
+/// use lsmdb::TransactionDB;
+///
+/// let db = TransactionDB::open("/tmp/db")?;
+/// let mut txn = db.begin();
+/// // No other transaction can write the key until this one commits.
+/// let stock = txn.get_for_update(b"stock:widget")?.map_or(0, |v| v[0]);
+/// if stock > 0 {
+///     txn.put(b"stock:widget", [stock - 1])?;
+/// }
+/// txn.commit()?;
+/// # Ok::<(), lsmdb::Error>(())
+/// ```
+pub struct TransactionDB {
+    engine: StorageEngine,
+    locks: LockManager,
+    next_txn_id: AtomicU64,
+}
+
+impl TransactionDB {
+    /// Opens or creates the database at the given path, with default options.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::open_with_options(path, Options::default(), TransactionDBOptions::default())
+    }
+
+    /// Opens or creates the database at the given path; see
+    /// [`StorageEngine::open_with_options`].
+    pub fn open_with_options(
+        path: impl Into<PathBuf>,
+        options: Options,
+        txn_options: TransactionDBOptions,
+    ) -> Result<Self, Error> {
+        txn_options.validate()?;
+        let engine = StorageEngine::open_with_options(path, options)?;
+        Ok(Self {
+            engine,
+            locks: LockManager::new(txn_options.lock_stripes, txn_options.lock_timeout),
+            next_txn_id: AtomicU64::new(1),
+        })
+    }
+
+    /// The underlying engine, for reads and for managing column families.
+    pub fn engine(&self) -> &StorageEngine {
+        &self.engine
+    }
+
+    /// Starts a [`PessimisticTransaction`].
+    pub fn begin(&self) -> PessimisticTransaction<'_> {
+        PessimisticTransaction {
+            db: self,
+            id: self.next_txn_id.fetch_add(1, Ordering::Relaxed),
+            locked: BTreeSet::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Shuts the database down cleanly; see [`StorageEngine::close`].
+    pub fn close(self) -> Result<(), Error> {
+        self.engine.close()
+    }
+}
+
+/// A transaction of a [`TransactionDB`], holding locks on the keys it writes.
+///
+/// Reads without a lock see the latest committed value, plus the transaction's own writes.
+/// Writes are buffered until [`commit`](Self::commit), which applies them atomically as one
+/// [`WriteBatch`]. Dropping the transaction discards its writes; either way its locks are
+/// released.
+pub struct PessimisticTransaction<'a> {
+    db: &'a TransactionDB,
+    id: u64,
+    // Every key this transaction holds the lock on.
+    locked: BTreeSet<LockKey>,
+    // The transaction's latest write to each key; `None` is a deletion.
+    writes: BTreeMap<LockKey, Option<Vec<u8>>>,
+}
+
+impl PessimisticTransaction<'_> {
+    /// Reads `key` without locking it: the transaction's own write to it if there is one, or
+    /// else its latest committed value.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, Error> {
+        let key = (DEFAULT_COLUMN_FAMILY_ID, key.as_ref().to_vec());
+        match self.writes.get(&key) {
+            Some(write) => Ok(write.clone()),
+            None => self.db.engine.get(&key.1),
+        }
+    }
+
+    /// Reads `key` from column family `cf` without locking it, like [`get`](Self::get).
+    pub fn get_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let key = (cf.id, key.as_ref().to_vec());
+        match self.writes.get(&key) {
+            Some(write) => Ok(write.clone()),
+            None => self.db.engine.get_cf(cf, &key.1),
+        }
+    }
+
+    /// Locks `key`, then reads it like [`get`](Self::get). No other transaction can change
+    /// the value read until this one ends.
+    pub fn get_for_update<K: AsRef<[u8]>>(&mut self, key: K) -> Result<Option<Vec<u8>>, Error> {
+        self.lock((DEFAULT_COLUMN_FAMILY_ID, key.as_ref().to_vec()))?;
+        self.get(key)
+    }
+
+    /// Locks `key` in column family `cf`, then reads it like [`get_cf`](Self::get_cf).
+    pub fn get_for_update_cf<K: AsRef<[u8]>>(
+        &mut self,
+        cf: &ColumnFamily,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.lock((cf.id, key.as_ref().to_vec()))?;
+        self.get_cf(cf, key)
+    }
+
+    /// Locks `key` and buffers a write of `value` to it.
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) -> Result<(), Error> {
+        self.write(
+            (DEFAULT_COLUMN_FAMILY_ID, key.as_ref().to_vec()),
+            Some(value.as_ref().to_vec()),
+        )
+    }
+
+    /// Locks `key` in column family `cf` and buffers a write of `value` to it.
+    pub fn put_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        cf: &ColumnFamily,
+        key: K,
+        value: V,
+    ) -> Result<(), Error> {
+        self.write(
+            (cf.id, key.as_ref().to_vec()),
+            Some(value.as_ref().to_vec()),
+        )
+    }
+
+    /// Locks `key` and buffers its deletion.
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> Result<(), Error> {
+        self.write((DEFAULT_COLUMN_FAMILY_ID, key.as_ref().to_vec()), None)
+    }
+
+    /// Locks `key` in column family `cf` and buffers its deletion.
+    pub fn delete_cf<K: AsRef<[u8]>>(&mut self, cf: &ColumnFamily, key: K) -> Result<(), Error> {
+        self.write((cf.id, key.as_ref().to_vec()), None)
+    }
+
+    fn write(&mut self, key: LockKey, value: Option<Vec<u8>>) -> Result<(), Error> {
+        self.lock(key.clone())?;
+        self.writes.insert(key, value);
+        Ok(())
+    }
+
+    fn lock(&mut self, key: LockKey) -> Result<(), Error> {
+        if self.locked.contains(&key) {
+            return Ok(());
+        }
+        match self.db.locks.lock(self.id, &key) {
+            Ok(()) => {
+                self.locked.insert(key);
+                Ok(())
+            }
+            Err(LockFailure::Timeout) => Err(LockError::Timeout { key: key.1 }.into()),
+            Err(LockFailure::Deadlock) => Err(LockError::Deadlock { key: key.1 }.into()),
+        }
+    }
+
+    /// Applies the transaction's writes atomically, as one WAL batch, then releases its locks.
+    pub fn commit(mut self) -> Result<(), Error> {
+        let writes = std::mem::take(&mut self.writes);
+        self.db.engine.write(WriteBatch::from_writes(writes))
+    }
+}
+
+impl Drop for PessimisticTransaction<'_> {
+    fn drop(&mut self) {
+        self.db.locks.unlock(self.id, &self.locked);
+    }
+}
+
+/// The error a [`PessimisticTransaction`] fails with when it cannot lock a key. The
+/// transaction keeps the locks it already holds until it is dropped, so drop it before
+/// retrying.
+///
+/// It reaches the caller as [`Error::Lock`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockError {
+    /// Another transaction held the key for longer than the lock timeout.
+    Timeout { key: Vec<u8> },
+    /// Waiting for the key would have deadlocked: its holder waits, directly or through
+    /// others, for a key this transaction holds.
+    Deadlock { key: Vec<u8> },
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockError::Timeout { key } => write!(
+                f,
+                "Lock timeout: key {:?} stayed locked by another transaction",
+                String::from_utf8_lossy(key)
+            ),
+            LockError::Deadlock { key } => write!(
+                f,
+                "Deadlock: waiting for key {:?} would deadlock",
+                String::from_utf8_lossy(key)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
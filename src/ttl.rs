@@ -0,0 +1,62 @@
+//! Values that expire: the storage format behind
+//! [`StorageEngine::put_with_ttl`](crate::StorageEngine::put_with_ttl).
+//!
+//! An expiring value is stored as `[expires_at (8 LE)] [value]`, where `expires_at` is a Unix
+//! timestamp in milliseconds, under its own value type so plain values pay nothing for it.
+//! The same bytes travel through the WAL, the MemTable and SSTables unchanged. Once the
+//! deadline passes, reads treat the entry as a tombstone and compaction rewrites it as one,
+//! or drops it outright at the bottom of the tree.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const EXPIRY_SIZE: usize = 8;
+
+/// The current time, as the Unix timestamp in milliseconds that expiry is measured in.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// The deadline for a value written now to live for `ttl`.
+pub(crate) fn expires_at(ttl: Duration) -> u64 {
+    now().saturating_add(ttl.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+pub(crate) fn encode(expires_at: u64, value: &[u8]) -> Vec<u8> {
+    let mut stored = Vec::with_capacity(EXPIRY_SIZE + value.len());
+    stored.extend_from_slice(&expires_at.to_le_bytes());
+    stored.extend_from_slice(value);
+    stored
+}
+
+/// Splits a stored expiring value into its deadline and the value itself. A value too short
+/// to hold a deadline reads as long expired.
+pub(crate) fn decode(stored: &[u8]) -> (u64, &[u8]) {
+    match stored.split_first_chunk::<EXPIRY_SIZE>() {
+        Some((expires_at, value)) => (u64::from_le_bytes(*expires_at), value),
+        None => (0, &[]),
+    }
+}
+
+/// True if the stored expiring value is past its deadline at `now`.
+pub(crate) fn is_expired(stored: &[u8], now: u64) -> bool {
+    decode(stored).0 <= now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiring_value_round_trip() {
+        let stored = encode(1_000, b"session");
+        assert_eq!(decode(&stored), (1_000, &b"session"[..]));
+        assert!(!is_expired(&stored, 999));
+        assert!(is_expired(&stored, 1_000));
+
+        // An empty value still carries its deadline; a truncated one has none.
+        assert_eq!(decode(&encode(7, b"")), (7, &b""[..]));
+        assert!(is_expired(&[1, 2, 3], 0));
+    }
+}
@@ -1,5 +1,4 @@
-#![allow(unused)]
-
+use crate::Error;
 use std::fs::File;
 use std::io::Write;
 
@@ -7,15 +6,31 @@ use std::io::Write;
 pub enum Opcode {
     Put = 1,
     Delete = 2,
+    /// Several operations of the other kinds that must be replayed together or not at all.
+    Batch = 3,
+    /// A merge operand, folded into the key's value by the configured merge operator.
+    Merge = 4,
+    /// A range tombstone. The record's key is the start of the range and its value the end.
+    DeleteRange = 5,
+    /// A Put with a deadline. The record's value carries the expiry timestamp in front of the
+    /// value itself, in the format of [`crate::ttl`].
+    PutWithTtl = 6,
 }
 
-/// A logical database operation (Put or Delete) before serialization.
+/// Set in a record's opcode byte when a column family id follows it. Records of the default
+/// family leave it clear and carry no id, so their format is unchanged.
+const CF_FLAG: u8 = 0x80;
+
+/// A logical database operation (Put, Delete, Merge, DeleteRange or PutWithTtl) before
+/// serialization.
 ///
 /// The sequence number establishes a total causal ordering across all operations. During
 /// recovery, replaying records in seq_num order guarantees the MemTable ends up in the
 /// exact state it would have been in had the crash not occurred.
 pub struct Record {
     pub opcode: Opcode,
+    /// The id of the column family the operation writes to.
+    pub cf: u32,
     pub seq_num: u64,
     pub key: Vec<u8>,
     pub val: Vec<u8>,
@@ -24,7 +39,8 @@ pub struct Record {
 impl Record {
     /// Serializes the Record to bytes for embedding as a WAL chunk payload.
     ///
-    /// Wire format: `[Opcode (1)] [Seq (8 LE)] [KeyLen (2 LE)] [Key] [ValLen (4 LE)] [Val]`
+    /// Wire format: `[Opcode (1)] [Seq (8 LE)] [KeyLen (2 LE)] [Key] [ValLen (4 LE)] [Val]`,
+    /// with `[Cf (4 LE)]` after the opcode if the record is not for the default family.
     ///
     /// `KeyLen` is u16 (max 65 KB) and `ValLen` is u32 (max 4 GB). Keys are intentionally
     /// restricted: an LSM-Tree benefits from short keys because they are copied into every
@@ -32,7 +48,7 @@ impl Record {
     /// arbitrarily large (stored out-of-line in the WAL payload).
     pub fn serialize(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        bytes.push(self.opcode as u8);
+        self.push_opcode(&mut bytes);
         bytes.extend_from_slice(&self.seq_num.to_le_bytes());
 
         bytes.extend_from_slice(&(self.key.len() as u16).to_le_bytes());
@@ -43,11 +59,55 @@ impl Record {
 
         bytes
     }
+
+    /// Serializes several records as ONE logical WAL record.
+    ///
+    /// Wire format: `[Opcode::Batch (1)] [FirstSeq (8 LE)] [Count (4 LE)]` followed by `Count`
+    /// entries of `[Opcode (1)] [KeyLen (2 LE)] [Key] [ValLen (4 LE)] [Val]`, each with the
+    /// column family id after its opcode as in [`Record::serialize`].
+    ///
+    /// Entry `i` gets sequence number `FirstSeq + i`, so `records` must carry consecutive
+    /// sequence numbers. Because the batch is a single record, its chunks are covered by the
+    /// same reassembly and checksum rules as any other: a crash mid-append leaves a torn
+    /// record that recovery discards as a whole, never a prefix of the batch.
+    pub fn serialize_batch(records: &[Record]) -> Vec<u8> {
+        let first_seq = records.first().map_or(0, |r| r.seq_num);
+        debug_assert!(
+            records
+                .iter()
+                .enumerate()
+                .all(|(i, r)| r.seq_num == first_seq + i as u64),
+            "batch sequence numbers must be contiguous"
+        );
+
+        let mut bytes = Vec::new();
+        bytes.push(Opcode::Batch as u8);
+        bytes.extend_from_slice(&first_seq.to_le_bytes());
+        bytes.extend_from_slice(&(records.len() as u32).to_le_bytes());
+
+        for record in records {
+            record.push_opcode(&mut bytes);
+            bytes.extend_from_slice(&(record.key.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&record.key);
+            bytes.extend_from_slice(&(record.val.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&record.val);
+        }
+
+        bytes
+    }
+
+    fn push_opcode(&self, bytes: &mut Vec<u8>) {
+        if self.cf == 0 {
+            bytes.push(self.opcode as u8);
+        } else {
+            bytes.push(self.opcode as u8 | CF_FLAG);
+            bytes.extend_from_slice(&self.cf.to_le_bytes());
+        }
+    }
 }
 
 const BLOCK_SIZE: usize = crate::constants::WAL_BLOCK_SIZE;
 const HEADER_SIZE: usize = crate::constants::WAL_HEADER_SIZE;
-const MAX_PAYLOAD_SIZE: usize = crate::constants::WAL_MAX_PAYLOAD_SIZE;
 
 #[derive(Debug, Clone, Copy)]
 enum ChunkType {
@@ -93,10 +153,14 @@ struct WalWriter {
 }
 
 impl WalWriter {
-    fn append_record(&mut self, record: Record) -> std::io::Result<()> {
+    fn append_record(&mut self, record: &Record) -> std::io::Result<()> {
         self.write_physical_chunks(&record.serialize())
     }
 
+    fn append_batch(&mut self, records: &[Record]) -> std::io::Result<()> {
+        self.write_physical_chunks(&Record::serialize_batch(records))
+    }
+
     // Slices `data` into chunks that fit within the remaining space in each 32 KB physical block.
     // Chunking here (rather than at the Record level) decouples the logical write API from the
     // physical block structure, making the format easy to change without touching record logic.
@@ -161,22 +225,38 @@ impl WalWriter {
     }
 }
 
+/// A point in the WAL just past a complete record, where following a WAL another process is
+/// writing picks up again. The default is the start of the oldest file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalPosition {
+    pub file_num: u64,
+    pub offset: u64,
+}
+
 pub struct Wal {
-    writer: WalWriter,
+    // `None` when the WAL was opened read-only.
+    writer: Option<WalWriter>,
     reader: Option<WalReader>,
     current_file_num: u64,
     dir_path: std::path::PathBuf,
+    sync_on_write: bool,
 }
 
 impl Wal {
     /// Opens or creates the next WAL file in the directory.
     ///
     /// File numbers are monotonically increasing so older files can be GC'd by number
-    /// comparison rather than by examining their contents. On startup we always continue
-    /// writing to the highest-numbered existing file — if the last file was partially written
-    /// (process crash), the recovery reader will encounter a checksum failure at the truncated
-    /// chunk and stop, leaving previously valid records accessible.
-    pub fn new(dir_path: impl Into<std::path::PathBuf>) -> Result<Self, anyhow::Error> {
+    /// comparison rather than by examining their contents. On startup we never append to a
+    /// file that already holds records: if the last run crashed mid-append, that file ends in
+    /// a torn record, and writing after it would bury new records behind a chunk recovery
+    /// cannot get past. Instead we start the next file number (reusing the highest file only
+    /// if it is still empty), so a torn record is always the last thing in its file.
+    ///
+    /// `sync_on_write` mirrors `Options::wal_sync_on_write`; see `maybe_sync`.
+    pub fn new(
+        dir_path: impl Into<std::path::PathBuf>,
+        sync_on_write: bool,
+    ) -> Result<Self, Error> {
         let dir_path = dir_path.into();
         std::fs::create_dir_all(&dir_path)?;
 
@@ -193,68 +273,97 @@ impl Wal {
             }
         }
 
-        let current_file_num = if max_num == 0 { 1 } else { max_num };
+        let current_file_num = if max_num == 0 {
+            1
+        } else {
+            let last_len = std::fs::metadata(dir_path.join(format!("{:05}.log", max_num)))
+                .map(|m| m.len())
+                .unwrap_or(0);
+            if last_len == 0 { max_num } else { max_num + 1 }
+        };
         let file_path = dir_path.join(format!("{:05}.log", current_file_num));
 
+        let file = File::options().create(true).append(true).open(file_path)?;
         let writer = WalWriter {
-            file: File::options().create(true).append(true).open(file_path)?,
+            file,
             block_offset: 0,
         };
 
         Ok(Self {
-            writer,
+            writer: Some(writer),
             reader: None,
             current_file_num,
             dir_path,
+            sync_on_write,
         })
     }
 
-    /// Appends a Put record. Returns only after the record is safely in the WAL.
-    pub fn add(&mut self, seq_num: u64, key: Vec<u8>, value: Vec<u8>) -> Result<(), anyhow::Error> {
-        self.writer.append_record(Record {
-            opcode: Opcode::Put,
-            seq_num,
-            key,
-            val: value,
-        })?;
+    /// Opens the WAL files in the directory for [`recover`](Self::recover) alone. Nothing is
+    /// created, not even the directory, and every append fails.
+    pub fn open_read_only(dir_path: impl Into<std::path::PathBuf>) -> Self {
+        let dir_path = dir_path.into();
+        let current_file_num = log_files(&dir_path)
+            .map(|files| files.iter().map(|(num, _)| *num).max().unwrap_or(0))
+            .unwrap_or(0);
+        Self {
+            writer: None,
+            reader: None,
+            current_file_num,
+            dir_path,
+            sync_on_write: false,
+        }
+    }
+
+    /// Appends a single record of any kind and any column family. Returns only after the record
+    /// is safely in the WAL.
+    pub fn append(&mut self, record: &Record) -> Result<(), Error> {
+        self.writer()?.append_record(record)?;
         self.maybe_sync()
     }
 
-    /// Appends a Delete tombstone. An empty `val` signals deletion during recovery.
-    pub fn remove(&mut self, seq_num: u64, key: Vec<u8>) -> Result<(), anyhow::Error> {
-        self.writer.append_record(Record {
-            opcode: Opcode::Delete,
-            seq_num,
-            key,
-            val: vec![],
-        })?;
+    /// Appends a batch of records as a single logical record (see
+    /// `Record::serialize_batch`). Returns only after the whole batch is in the WAL.
+    pub fn add_batch(&mut self, records: &[Record]) -> Result<(), Error> {
+        self.writer()?.append_batch(records)?;
         self.maybe_sync()
     }
 
-    // When WAL_SYNC_ON_WRITE is false, the OS can coalesce and reorder writes for throughput,
+    // When sync_on_write is false, the OS can coalesce and reorder writes for throughput,
     // but a power loss may drop the last few records. The tradeoff is documented in constants.rs.
-    fn maybe_sync(&mut self) -> Result<(), anyhow::Error> {
-        if crate::constants::WAL_SYNC_ON_WRITE {
-            self.writer.file.sync_data()?;
+    fn maybe_sync(&mut self) -> Result<(), Error> {
+        if self.sync_on_write {
+            self.writer()?.file.sync_data()?;
         }
         Ok(())
     }
 
+    /// Forces every record appended so far to physical storage, whatever `sync_on_write` is.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.writer()?.file.sync_data()?;
+        Ok(())
+    }
+
+    // The writer, unless the WAL was opened read-only.
+    fn writer(&mut self) -> Result<&mut WalWriter, Error> {
+        self.writer.as_mut().ok_or(Error::ReadOnly)
+    }
+
     /// Gets the current active WAL file number.
     pub fn current_file_num(&self) -> u64 {
         self.current_file_num
     }
 
     /// Freezes the current WAL file and rotates to a new `.log` file.
-    pub fn rotate(&mut self) -> Result<(), anyhow::Error> {
+    pub fn rotate(&mut self) -> Result<(), Error> {
+        self.writer()?;
         self.current_file_num += 1;
         let file_path = self
             .dir_path
             .join(format!("{:05}.log", self.current_file_num));
-        self.writer = WalWriter {
+        self.writer = Some(WalWriter {
             file: File::options().create(true).append(true).open(file_path)?,
             block_offset: 0,
-        };
+        });
         Ok(())
     }
 
@@ -263,7 +372,10 @@ impl Wal {
     /// Only called after the SSTable has been fully written and the MANIFEST updated.
     /// Deleting before that point would make the corresponding writes unrecoverable on a
     /// crash between the SSTable write and the MANIFEST update.
-    pub fn delete_old_files(&self, up_to_inclusive: u64) -> Result<(), anyhow::Error> {
+    pub fn delete_old_files(&self, up_to_inclusive: u64) -> Result<(), Error> {
+        if self.writer.is_none() {
+            return Err(Error::ReadOnly);
+        }
         if !self.dir_path.exists() {
             return Ok(());
         }
@@ -285,38 +397,29 @@ impl Wal {
     /// Replays all WAL records in file-number order to reconstruct the MemTable state.
     ///
     /// Files are read in ascending numeric order so older writes are replayed before newer ones,
-    /// preserving the original causal order. A checksum failure in the reader stops recovery
-    /// at that point — records before the failure are valid, and records after it (if any) belong
-    /// to an interrupted write and should be discarded.
-    pub fn recover(&mut self) -> Result<Vec<Record>, anyhow::Error> {
+    /// preserving the original causal order. Batch records are expanded into their individual
+    /// Put/Delete records, with consecutive sequence numbers.
+    ///
+    /// A torn record at the end of a file ends recovery of that file: records before it are
+    /// valid, and the damaged one belongs to an append interrupted by a crash. Because
+    /// `Wal::new` never appends to a file that already holds records, nothing can follow it
+    /// in the same file, and later files (written by later runs) are still replayed. A
+    /// damaged record with more of the file after it is no crash, and fails recovery with
+    /// `Error::Corruption` rather than silently dropping the writes behind it.
+    pub fn recover(&mut self) -> Result<Vec<Record>, Error> {
         let mut records = Vec::new();
 
         if !self.dir_path.exists() {
             return Ok(records);
         }
 
-        let mut files: Vec<_> = std::fs::read_dir(&self.dir_path)?
-            .flatten()
-            .filter_map(|e| {
-                let name = e.file_name().into_string().ok()?;
-                if name.ends_with(".log") {
-                    let num: u64 = name.trim_end_matches(".log").parse().ok()?;
-                    Some((num, e.path()))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        files.sort_by_key(|(num, _)| *num);
-
-        for (_, path) in files {
-            let file = File::open(path)?;
-            self.reader = Some(WalReader::new(file));
+        for (_, path) in log_files(&self.dir_path)? {
+            let file = File::open(&path)?;
+            self.reader = Some(WalReader::at(file, path, 0));
 
             if let Some(reader) = &mut self.reader {
-                while let Some(record) = reader.next_record()? {
-                    records.push(record);
+                while let Some(logical_record) = reader.next_intact_record()? {
+                    records.extend(logical_record);
                 }
             }
         }
@@ -325,25 +428,114 @@ impl Wal {
 
         Ok(records)
     }
+
+    /// Reads the records written since `from`: those past `from.offset` in file
+    /// `from.file_num`, and all of those in later files. Returns them in order, with the
+    /// position just past the last one.
+    ///
+    /// This follows a WAL another process is appending to. A record it is still in the middle
+    /// of writing is left for the next call, which resumes in front of it; files it deleted
+    /// in the meantime, after flushing their writes, are skipped.
+    pub fn read_from(&self, from: WalPosition) -> Result<(Vec<Record>, WalPosition), Error> {
+        let mut records = Vec::new();
+        let mut end = from;
+        if !self.dir_path.exists() {
+            return Ok((records, end));
+        }
+
+        for (num, path) in log_files(&self.dir_path)? {
+            if num < from.file_num {
+                continue;
+            }
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let offset = if num == from.file_num { from.offset } else { 0 };
+            let mut reader = WalReader::at(file, path, offset);
+            end = WalPosition {
+                file_num: num,
+                offset,
+            };
+            while let Some(logical_record) = reader.next_intact_record()? {
+                records.extend(logical_record);
+                end.offset = reader.position();
+            }
+        }
+
+        Ok((records, end))
+    }
+}
+
+// The `.log` files in `dir_path` with their numbers, oldest first.
+fn log_files(dir_path: &std::path::Path) -> Result<Vec<(u64, std::path::PathBuf)>, Error> {
+    let mut files: Vec<_> = std::fs::read_dir(dir_path)?
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            if name.ends_with(".log") {
+                let num: u64 = name.trim_end_matches(".log").parse().ok()?;
+                Some((num, e.path()))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    files.sort_by_key(|(num, _)| *num);
+    Ok(files)
 }
 
 struct WalReader {
     file: File,
+    // Named in corruption errors.
+    path: std::path::PathBuf,
     buffer: [u8; BLOCK_SIZE],
+    // Where in the file the buffer starts. Block boundaries are worked out from the absolute
+    // position, so a buffer cut short at the end of a file that is still being written does
+    // not throw the block alignment off when reading resumes.
+    buffer_start: u64,
     buffer_offset: usize,
     buffer_len: usize,
 }
 
 impl WalReader {
-    pub fn new(file: File) -> Self {
+    /// A reader of `file`, found at `path`, starting at `offset`, which must be the end of a
+    /// record.
+    fn at(file: File, path: std::path::PathBuf, offset: u64) -> Self {
         Self {
             file,
+            path,
             buffer: [0; BLOCK_SIZE],
+            buffer_start: offset,
             buffer_offset: 0,
             buffer_len: 0,
         }
     }
 
+    /// The position in the file just past the last record read.
+    fn position(&self) -> u64 {
+        self.buffer_start + self.buffer_offset as u64
+    }
+
+    // Refills the buffer from the current position, which padding may have skipped past the
+    // end of the old buffer. Returns the number of bytes read; 0 at the end of the file.
+    fn refill(&mut self) -> std::io::Result<usize> {
+        use std::io::{Read, Seek, SeekFrom};
+        let position = self.position();
+        self.file.seek(SeekFrom::Start(position))?;
+        self.buffer_start = position;
+        self.buffer_offset = 0;
+        self.buffer_len = self.file.read(&mut self.buffer)?;
+        Ok(self.buffer_len)
+    }
+
+    // Bytes left in the current block.
+    fn block_leftover(&self) -> usize {
+        BLOCK_SIZE - (self.position() % BLOCK_SIZE as u64) as usize
+    }
+
     /// Reads exactly `len` bytes from the block buffer.
     /// If the block is exhausted, it reads the next block from the file.
     fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
@@ -352,17 +544,11 @@ impl WalReader {
 
         while read_so_far < total_to_read {
             // Refill buffer if empty
-            if self.buffer_offset >= self.buffer_len {
-                use std::io::Read;
-                self.buffer_len = self.file.read(&mut self.buffer)?;
-                self.buffer_offset = 0;
-
-                if self.buffer_len == 0 {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::UnexpectedEof,
-                        "Unexpected EOF",
-                    ));
-                }
+            if self.buffer_offset >= self.buffer_len && self.refill()? == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Unexpected EOF",
+                ));
             }
 
             let avail = self.buffer_len - self.buffer_offset;
@@ -379,31 +565,58 @@ impl WalReader {
         Ok(())
     }
 
-    /// Reads chunks and reassembles them into a single logical Record.
-    pub fn next_record(&mut self) -> Result<Option<Record>, anyhow::Error> {
+    // Whether nothing but zeros lies between the read position and the end of the file.
+    fn only_zeros_left(&mut self) -> std::io::Result<bool> {
+        loop {
+            let start = self.buffer_offset.min(self.buffer_len);
+            if self.buffer[start..self.buffer_len]
+                .iter()
+                .any(|&byte| byte != 0)
+            {
+                return Ok(false);
+            }
+            self.buffer_offset = self.buffer_offset.max(self.buffer_len);
+            if self.refill()? == 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    // Like `next_record`, except that a damaged record with nothing but zeros after it reads
+    // as the end of the file: it is the tail of an append that a crash interrupted, or that
+    // the writer is still in the middle of. Damage with anything after it is an error.
+    fn next_intact_record(&mut self) -> Result<Option<Vec<Record>>, Error> {
+        match self.next_record() {
+            Err(_) if self.only_zeros_left()? => Ok(None),
+            result => result,
+        }
+    }
+
+    // A corruption error for the record starting at `offset`.
+    fn corruption(&self, offset: u64, message: &str) -> Error {
+        Error::corruption(&self.path, offset, message)
+    }
+
+    /// Reads chunks and reassembles them into a single logical record. A Put or Delete yields
+    /// one `Record`; a batch yields all of its entries, or an error if any part is damaged.
+    pub fn next_record(&mut self) -> Result<Option<Vec<Record>>, Error> {
+        let start = self.position();
         let mut record_payload = Vec::new();
         let mut reading_fragmented = false;
 
         loop {
             // Check if we are at the end of the file/buffer cleanly
-            if self.buffer_offset >= self.buffer_len {
-                use std::io::Read;
-                self.buffer_len = self.file.read(&mut self.buffer)?;
-                self.buffer_offset = 0;
-                if self.buffer_len == 0 {
-                    // EOF reached cleanly if we haven't started a fragmented record
-                    if reading_fragmented {
-                        return Err(anyhow::anyhow!(
-                            "WAL corruption: Unexpected EOF during fragmented record"
-                        ));
-                    }
-                    return Ok(None);
+            if self.buffer_offset >= self.buffer_len && self.refill()? == 0 {
+                // EOF reached cleanly if we haven't started a fragmented record
+                if reading_fragmented {
+                    return Err(self.corruption(start, "Unexpected EOF during fragmented record"));
                 }
+                return Ok(None);
             }
 
             // Check if we hit padding. If the remaining space in the block
             // is less than HEADER_SIZE, skip to the next block.
-            let leftover = BLOCK_SIZE - (self.buffer_offset % BLOCK_SIZE);
+            let leftover = self.block_leftover();
             if leftover < HEADER_SIZE {
                 self.buffer_offset += leftover;
                 continue;
@@ -423,8 +636,7 @@ impl WalReader {
 
             // If length is 0 and chunk_type is 0, this is pure padding. Skip the rest of this block.
             if length == 0 && chunk_type == 0 {
-                let leftover = BLOCK_SIZE - (self.buffer_offset % BLOCK_SIZE);
-                self.buffer_offset += leftover;
+                self.buffer_offset += self.block_leftover();
                 continue;
             }
 
@@ -438,7 +650,7 @@ impl WalReader {
             let computed_checksum = crc32fast::hash(&crc_data);
 
             if checksum != computed_checksum {
-                return Err(anyhow::anyhow!("WAL corruption: Checksum mismatch"));
+                return Err(self.corruption(start, "Checksum mismatch"));
             }
 
             record_payload.extend_from_slice(&chunk_payload);
@@ -447,80 +659,146 @@ impl WalReader {
                 1 => {
                     // Full
                     if reading_fragmented {
-                        return Err(anyhow::anyhow!(
-                            "WAL corruption: Full chunk inside fragmented record"
-                        ));
+                        return Err(self.corruption(start, "Full chunk inside fragmented record"));
                     }
                     break;
                 }
                 2 => {
                     // First
                     if reading_fragmented {
-                        return Err(anyhow::anyhow!(
-                            "WAL corruption: First chunk inside fragmented record"
-                        ));
+                        return Err(self.corruption(start, "First chunk inside fragmented record"));
                     }
                     reading_fragmented = true;
                 }
                 3 => {
                     // Middle
                     if !reading_fragmented {
-                        return Err(anyhow::anyhow!(
-                            "WAL corruption: Middle chunk outside fragmented record"
-                        ));
+                        return Err(
+                            self.corruption(start, "Middle chunk outside fragmented record")
+                        );
                     }
                 }
                 4 => {
                     // Last
                     if !reading_fragmented {
-                        return Err(anyhow::anyhow!(
-                            "WAL corruption: Last chunk outside fragmented record"
-                        ));
+                        return Err(self.corruption(start, "Last chunk outside fragmented record"));
                     }
                     break;
                 }
-                _ => return Err(anyhow::anyhow!("WAL corruption: Unknown chunk type")),
+                _ => return Err(self.corruption(start, "Unknown chunk type")),
             }
         }
 
-        // Deserialize the raw string of bytes back into a Record!
-        if record_payload.len() < 15 {
-            return Err(anyhow::anyhow!("WAL corruption: Record payload too small"));
+        // Deserialize the raw string of bytes back into Records!
+        if record_payload.first() == Some(&(Opcode::Batch as u8)) {
+            return decode_batch(&record_payload)
+                .map(Some)
+                .map_err(|message| self.corruption(start, message));
         }
 
-        let opcode = match record_payload[0] {
-            1 => Opcode::Put,
-            2 => Opcode::Delete,
-            _ => return Err(anyhow::anyhow!("WAL corruption: Invalid opcode")),
-        };
-
-        let seq_num = u64::from_le_bytes(record_payload[1..9].try_into().unwrap());
-        let key_len = u16::from_le_bytes(record_payload[9..11].try_into().unwrap()) as usize;
-        let key = record_payload[11..11 + key_len].to_vec();
-
-        let val_offset = 11 + key_len;
-        if record_payload.len() < val_offset + 4 {
-            return Err(anyhow::anyhow!(
-                "WAL corruption: Invalid value length boundary"
-            ));
-        }
-
-        let val_len = u32::from_le_bytes(
-            record_payload[val_offset..val_offset + 4]
+        let (opcode, cf, ptr) =
+            decode_opcode(&record_payload).map_err(|message| self.corruption(start, message))?;
+        let seq_num = u64::from_le_bytes(
+            record_payload
+                .get(ptr..ptr + 8)
+                .ok_or_else(|| self.corruption(start, "Record payload too small"))?
                 .try_into()
                 .unwrap(),
-        ) as usize;
-        let val = record_payload[val_offset + 4..val_offset + 4 + val_len].to_vec();
+        );
+        let (key, val, _) = decode_key_value(&record_payload[ptr + 8..])
+            .map_err(|message| self.corruption(start, message))?;
 
-        Ok(Some(Record {
+        Ok(Some(vec![Record {
             opcode,
+            cf,
             seq_num,
             key,
             val,
-        }))
+        }]))
     }
 }
 
+// Decodes the opcode at the start of `data` and the column family id that may follow it, and
+// returns the bytes consumed.
+fn decode_opcode(data: &[u8]) -> Result<(Opcode, u32, usize), &'static str> {
+    let byte = *data.first().ok_or("Missing opcode")?;
+    let opcode = match byte & !CF_FLAG {
+        1 => Opcode::Put,
+        2 => Opcode::Delete,
+        4 => Opcode::Merge,
+        5 => Opcode::DeleteRange,
+        6 => Opcode::PutWithTtl,
+        _ => return Err("Invalid opcode"),
+    };
+    if byte & CF_FLAG == 0 {
+        return Ok((opcode, 0, 1));
+    }
+    let cf = u32::from_le_bytes(
+        data.get(1..5)
+            .ok_or("Truncated column family id")?
+            .try_into()
+            .unwrap(),
+    );
+    Ok((opcode, cf, 5))
+}
+
+// Decodes `[KeyLen (2 LE)] [Key] [ValLen (4 LE)] [Val]` and returns the bytes consumed. Every
+// length is bounds-checked: the CRC only proves the bytes are what was written, and a writer
+// bug must not turn into a panic at startup.
+fn decode_key_value(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>, usize), &'static str> {
+    let truncated = "Invalid value length boundary";
+
+    let key_len = u16::from_le_bytes(data.get(0..2).ok_or(truncated)?.try_into().unwrap());
+    let key_end = 2 + key_len as usize;
+    let key = data.get(2..key_end).ok_or(truncated)?.to_vec();
+
+    let val_len = u32::from_le_bytes(
+        data.get(key_end..key_end + 4)
+            .ok_or(truncated)?
+            .try_into()
+            .unwrap(),
+    );
+    let val_end = key_end + 4 + val_len as usize;
+    let val = data.get(key_end + 4..val_end).ok_or(truncated)?.to_vec();
+
+    Ok((key, val, val_end))
+}
+
+fn decode_batch(payload: &[u8]) -> Result<Vec<Record>, &'static str> {
+    // Opcode (1) + FirstSeq (8) + Count (4)
+    if payload.len() < 13 {
+        return Err("Batch header too small");
+    }
+
+    let first_seq = u64::from_le_bytes(payload[1..9].try_into().unwrap());
+    let count = u32::from_le_bytes(payload[9..13].try_into().unwrap()) as u64;
+
+    let mut records = Vec::new();
+    let mut ptr = 13;
+    for i in 0..count {
+        if ptr == payload.len() {
+            return Err("Batch shorter than its entry count");
+        }
+        let (opcode, cf, opcode_len) = decode_opcode(&payload[ptr..])?;
+        let (key, val, consumed) = decode_key_value(&payload[ptr + opcode_len..])?;
+        ptr += opcode_len + consumed;
+
+        records.push(Record {
+            opcode,
+            cf,
+            seq_num: first_seq + i,
+            key,
+            val,
+        });
+    }
+
+    if ptr != payload.len() {
+        return Err("Trailing bytes after batch entries");
+    }
+
+    Ok(records)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -535,16 +813,29 @@ mod tests {
         let val_size = target_serialized_size.saturating_sub(15 + 4); // 4 bytes for key "test"
         Record {
             opcode: Opcode::Put,
+            cf: 0,
             seq_num: 42,
             key: b"test".to_vec(),
             val: vec![0xAB; val_size],
         }
     }
 
+    // A record of the default column family.
+    fn record(opcode: Opcode, seq_num: u64, key: &[u8], val: &[u8]) -> Record {
+        Record {
+            opcode,
+            cf: 0,
+            seq_num,
+            key: key.to_vec(),
+            val: val.to_vec(),
+        }
+    }
+
     #[test]
     fn test_record_serialization() {
         let record = Record {
             opcode: Opcode::Put,
+            cf: 0,
             seq_num: 1,
             key: b"k".to_vec(),
             val: b"v".to_vec(),
@@ -553,6 +844,12 @@ mod tests {
         // 1 + 8 + 2 + 1 + 4 + 1 = 17 bytes
         assert_eq!(bytes.len(), 17);
         assert_eq!(bytes[0], 1); // Opcode::Put
+
+        // Records of another column family carry its id after the flagged opcode.
+        let bytes = Record { cf: 3, ..record }.serialize();
+        assert_eq!(bytes.len(), 21);
+        assert_eq!(bytes[0], 1 | CF_FLAG);
+        assert_eq!(bytes[1..5], 3u32.to_le_bytes());
     }
 
     #[test]
@@ -564,7 +861,7 @@ mod tests {
         };
 
         let record = create_dummy_record_of_size(100);
-        writer.append_record(record).unwrap();
+        writer.append_record(&record).unwrap();
 
         // The chunk written should be HEADER_SIZE (7) + 100 = 107 bytes.
         assert_eq!(writer.block_offset, 107);
@@ -592,7 +889,7 @@ mod tests {
         // We want a payload of 40,000 bytes. This is > MAX_PAYLOAD_SIZE (32,761)
         // so it must be split into a First chunk and a Last chunk.
         let record = create_dummy_record_of_size(40000);
-        writer.append_record(record).unwrap();
+        writer.append_record(&record).unwrap();
 
         // 1st chunk: HEADER (7) + 32,761 payload = 32,768 (fills block 0)
         // Remaining payload: 40,000 - 32,761 = 7,239 bytes.
@@ -609,7 +906,7 @@ mod tests {
         let chunk1_type = data[6];
         assert_eq!(chunk1_type, ChunkType::First as u8);
         let chunk1_len = u16::from_le_bytes([data[4], data[5]]);
-        assert_eq!(chunk1_len as usize, MAX_PAYLOAD_SIZE);
+        assert_eq!(chunk1_len as usize, crate::constants::WAL_MAX_PAYLOAD_SIZE);
 
         // Check Last chunk header (starts at offset 32768)
         let chunk2_type = data[32768 + 6];
@@ -630,7 +927,7 @@ mod tests {
         // A block is 32768. 5 bytes left means we write 32763 bytes.
         // 32763 bytes - 7 bytes header = 32756 bytes payload.
         let record1 = create_dummy_record_of_size(32756);
-        writer.append_record(record1).unwrap();
+        writer.append_record(&record1).unwrap();
 
         assert_eq!(writer.block_offset, 32763);
 
@@ -638,7 +935,7 @@ mod tests {
         // It needs a 7-byte header, but only 5 bytes are left in the block!
         // So the writer MUST pad the 5 bytes with zeros, and start the new chunk at offset 32768.
         let record2 = create_dummy_record_of_size(100);
-        writer.append_record(record2).unwrap();
+        writer.append_record(&record2).unwrap();
 
         // The new block offset should be the size of the new chunk (7 + 100)
         assert_eq!(writer.block_offset, 107);
@@ -651,9 +948,7 @@ mod tests {
         assert_eq!(data.len(), 32768 + 107);
 
         // Verify the padding bytes are strictly zero
-        for i in 32763..32768 {
-            assert_eq!(data[i], 0);
-        }
+        assert!(data[32763..32768].iter().all(|&b| b == 0));
 
         // Verify the second chunk started exactly at the beginning of the next block
         let chunk2_type = data[32768 + 6];
@@ -663,7 +958,7 @@ mod tests {
     #[test]
     fn test_wal_rotation() {
         let dir = tempfile::tempdir().unwrap();
-        let mut wal = Wal::new(dir.path()).unwrap();
+        let mut wal = Wal::new(dir.path(), true).unwrap();
 
         // Should create 00001.log
         assert_eq!(wal.current_file_num, 1);
@@ -679,7 +974,7 @@ mod tests {
         assert!(file2_path.exists());
 
         // Write something to file 2
-        wal.add(1, b"key".to_vec(), b"val".to_vec()).unwrap();
+        wal.append(&record(Opcode::Put, 1, b"key", b"val")).unwrap();
 
         let meta = fs::metadata(&file2_path).unwrap();
         assert!(meta.len() > 0);
@@ -688,21 +983,25 @@ mod tests {
     #[test]
     fn test_wal_recovery() {
         let dir = tempfile::tempdir().unwrap();
-        let mut wal = Wal::new(dir.path()).unwrap();
+        let mut wal = Wal::new(dir.path(), true).unwrap();
 
         // Write Normal Record
-        wal.add(1, b"key1".to_vec(), b"val1".to_vec()).unwrap();
+        wal.append(&record(Opcode::Put, 1, b"key1", b"val1"))
+            .unwrap();
 
         // Write Huge Fragmented Record
         let huge_val = vec![0xAB; 40000];
-        wal.add(2, b"key2".to_vec(), huge_val.clone()).unwrap();
+        wal.append(&record(Opcode::Put, 2, b"key2", &huge_val))
+            .unwrap();
 
         // Write Tombstone Record
-        wal.remove(3, b"key1".to_vec()).unwrap();
+        wal.append(&record(Opcode::Delete, 3, b"key1", b""))
+            .unwrap();
 
         // Rotate & Write more
         wal.rotate().unwrap();
-        wal.add(4, b"key3".to_vec(), b"val3".to_vec()).unwrap();
+        wal.append(&record(Opcode::Put, 4, b"key3", b"val3"))
+            .unwrap();
 
         // RECOVER
         let records = wal.recover().unwrap();
@@ -725,4 +1024,236 @@ mod tests {
         assert_eq!(records[3].key, b"key3");
         assert_eq!(records[3].val, b"val3");
     }
+
+    fn batch_records(first_seq: u64) -> Vec<Record> {
+        vec![
+            Record {
+                opcode: Opcode::Put,
+                cf: 0,
+                seq_num: first_seq,
+                key: b"a".to_vec(),
+                val: b"1".to_vec(),
+            },
+            Record {
+                opcode: Opcode::Delete,
+                cf: 2,
+                seq_num: first_seq + 1,
+                key: b"b".to_vec(),
+                val: vec![],
+            },
+            Record {
+                opcode: Opcode::Put,
+                cf: 1,
+                seq_num: first_seq + 2,
+                key: b"c".to_vec(),
+                val: vec![0xCD; 40000],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_wal_batch_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut wal = Wal::new(dir.path(), true).unwrap();
+
+        wal.append(&record(Opcode::Put, 1, b"before", b"x"))
+            .unwrap();
+        // Large enough to be split across two physical blocks.
+        wal.add_batch(&batch_records(2)).unwrap();
+        wal.append(&record(Opcode::Put, 5, b"after", b"y")).unwrap();
+        wal.append(&record(Opcode::Merge, 6, b"counter", b"+1"))
+            .unwrap();
+        wal.append(&record(Opcode::DeleteRange, 7, b"tenant:1:", b"tenant:1;"))
+            .unwrap();
+        wal.append(&record(
+            Opcode::PutWithTtl,
+            8,
+            b"session",
+            &crate::ttl::encode(1_000, b"s"),
+        ))
+        .unwrap();
+        wal.append(&Record {
+            opcode: Opcode::Merge,
+            cf: 7,
+            seq_num: 9,
+            key: b"counter".to_vec(),
+            val: b"+2".to_vec(),
+        })
+        .unwrap();
+
+        let records = wal.recover().unwrap();
+        let seqs: Vec<u64> = records.iter().map(|r| r.seq_num).collect();
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let cfs: Vec<u32> = records.iter().map(|r| r.cf).collect();
+        assert_eq!(cfs, vec![0, 0, 2, 1, 0, 0, 0, 0, 7]);
+
+        assert_eq!(records[1].key, b"a");
+        assert!(matches!(records[2].opcode, Opcode::Delete));
+        assert!(records[2].val.is_empty());
+        assert_eq!(records[3].val, vec![0xCD; 40000]);
+        assert_eq!(records[4].key, b"after");
+        assert!(matches!(records[5].opcode, Opcode::Merge));
+        assert_eq!(records[5].val, b"+1");
+        assert!(matches!(records[6].opcode, Opcode::DeleteRange));
+        assert_eq!(records[6].key, b"tenant:1:");
+        assert_eq!(records[6].val, b"tenant:1;");
+        assert!(matches!(records[7].opcode, Opcode::PutWithTtl));
+        assert_eq!(crate::ttl::decode(&records[7].val), (1_000, &b"s"[..]));
+        assert!(matches!(records[8].opcode, Opcode::Merge));
+        assert_eq!(records[8].val, b"+2");
+    }
+
+    #[test]
+    fn test_wal_torn_batch_is_dropped_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("00001.log");
+        {
+            let mut wal = Wal::new(dir.path(), true).unwrap();
+            wal.append(&record(Opcode::Put, 1, b"before", b"x"))
+                .unwrap();
+            wal.add_batch(&batch_records(2)).unwrap();
+        }
+
+        // Simulate a crash mid-append: the batch's last chunk never made it to disk.
+        let full_len = fs::metadata(&log_path).unwrap().len();
+        let file = File::options().write(true).open(&log_path).unwrap();
+        file.set_len(full_len - 100).unwrap();
+
+        // Reopening starts a fresh file instead of appending after the torn record.
+        let mut wal = Wal::new(dir.path(), true).unwrap();
+        assert_eq!(wal.current_file_num(), 2);
+        wal.append(&record(Opcode::Put, 5, b"after", b"y")).unwrap();
+
+        let records = wal.recover().unwrap();
+        let keys: Vec<&[u8]> = records.iter().map(|r| r.key.as_slice()).collect();
+        assert_eq!(keys, vec![b"before".as_slice(), b"after".as_slice()]);
+    }
+
+    #[test]
+    fn test_wal_reader_reports_where_the_corruption_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("00001.log");
+        let mut wal = Wal::new(dir.path(), true).unwrap();
+        wal.append(&record(Opcode::Put, 1, b"good", b"x")).unwrap();
+        let second = fs::metadata(&log_path).unwrap().len();
+        wal.append(&record(Opcode::Put, 2, b"bad", b"y")).unwrap();
+        drop(wal);
+
+        // Flip the last byte of the second record's value.
+        let mut bytes = fs::read(&log_path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        fs::write(&log_path, bytes).unwrap();
+
+        let mut reader = WalReader::at(File::open(&log_path).unwrap(), log_path.clone(), 0);
+        assert_eq!(reader.next_record().unwrap().unwrap()[0].key, b"good");
+        match reader.next_record() {
+            Err(Error::Corruption { file, offset, .. }) => {
+                assert_eq!(file, log_path);
+                assert_eq!(offset, second);
+            }
+            other => panic!("expected corruption, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_wal_recovery_fails_on_damage_before_the_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("00001.log");
+        let mut wal = Wal::new(dir.path(), true).unwrap();
+        wal.append(&record(Opcode::Put, 1, b"first", b"x")).unwrap();
+        wal.append(&record(Opcode::Put, 2, b"second", b"y"))
+            .unwrap();
+        let second_end = fs::metadata(&log_path).unwrap().len();
+        wal.append(&record(Opcode::Put, 3, b"third", b"z")).unwrap();
+        drop(wal);
+        let intact = fs::read(&log_path).unwrap();
+
+        // The last record torn, followed by the zeros of a preallocated block: a crash.
+        let mut bytes = intact.clone();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        bytes.extend_from_slice(&[0; 64]);
+        fs::write(&log_path, &bytes).unwrap();
+        let records = Wal::open_read_only(dir.path()).recover().unwrap();
+        let keys: Vec<&[u8]> = records.iter().map(|r| r.key.as_slice()).collect();
+        assert_eq!(keys, vec![b"first".as_slice(), b"second".as_slice()]);
+
+        // A damaged record with another after it is no torn append.
+        let mut bytes = intact;
+        bytes[second_end as usize - 1] ^= 0xff;
+        fs::write(&log_path, &bytes).unwrap();
+        assert!(matches!(
+            Wal::open_read_only(dir.path()).recover(),
+            Err(Error::Corruption { file, .. }) if file == log_path
+        ));
+    }
+
+    #[test]
+    fn test_wal_read_only_recovers_without_touching_files() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut wal = Wal::new(dir.path(), true).unwrap();
+            wal.append(&record(Opcode::Put, 1, b"a", b"1")).unwrap();
+            wal.rotate().unwrap();
+            wal.append(&record(Opcode::Put, 2, b"b", b"2")).unwrap();
+        }
+
+        let mut wal = Wal::open_read_only(dir.path());
+        assert_eq!(wal.current_file_num(), 2);
+        let keys: Vec<Vec<u8>> = wal.recover().unwrap().into_iter().map(|r| r.key).collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+
+        assert!(wal.append(&record(Opcode::Put, 3, b"c", b"3")).is_err());
+        assert!(wal.rotate().is_err());
+        assert!(wal.delete_old_files(2).is_err());
+        assert_eq!(wal.current_file_num(), 2);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // A directory that does not exist is not created.
+        let missing = dir.path().join("missing");
+        assert!(Wal::open_read_only(&missing).recover().unwrap().is_empty());
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_wal_read_from_resumes_after_the_last_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut wal = Wal::new(dir.path(), true).unwrap();
+        let follower = Wal::open_read_only(dir.path());
+        let keys =
+            |records: Vec<Record>| -> Vec<Vec<u8>> { records.into_iter().map(|r| r.key).collect() };
+
+        wal.append(&record(Opcode::Put, 1, b"a", b"1")).unwrap();
+        let (records, position) = follower.read_from(WalPosition::default()).unwrap();
+        assert_eq!(keys(records), vec![b"a".to_vec()]);
+
+        // Nothing new: the position stays put.
+        let (records, same) = follower.read_from(position).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(same, position);
+
+        // A record large enough to span blocks, then one in the next file.
+        wal.append(&record(Opcode::Put, 2, b"b", &[7; 40_000]))
+            .unwrap();
+        wal.add_batch(&batch_records(3)).unwrap();
+        wal.rotate().unwrap();
+        wal.append(&record(Opcode::Put, 6, b"c", b"3")).unwrap();
+        let (records, position) = follower.read_from(position).unwrap();
+        assert_eq!(records[0].val.len(), 40_000);
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[4].key, b"c");
+        assert_eq!(position.file_num, 2);
+
+        // A record cut off half-way is picked up once it is complete.
+        let log_path = dir.path().join("00002.log");
+        let full = fs::read(&log_path).unwrap();
+        wal.append(&record(Opcode::Put, 7, b"d", b"4")).unwrap();
+        let with_record = fs::read(&log_path).unwrap();
+        fs::write(&log_path, &with_record[..full.len() + 5]).unwrap();
+        let (records, torn) = follower.read_from(position).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(torn, position);
+        fs::write(&log_path, &with_record).unwrap();
+        let (records, _) = follower.read_from(torn).unwrap();
+        assert_eq!(keys(records), vec![b"d".to_vec()]);
+    }
 }
@@ -1,6 +1,13 @@
-use lsmdb::StorageEngine;
-use std::sync::Arc;
+use lsmdb::constants::{COMPRESSION_NONE, COMPRESSION_ZSTD, SSTABLE_FORMAT_VERSION};
+use lsmdb::{
+    ColumnFamily, CompressionType, CorruptSSTablePolicy, DEFAULT_COLUMN_FAMILY_NAME, Error,
+    LockError, MergeOperator, Options, ReadOptions, StorageEngine, TransactionDB,
+    TransactionDBOptions, WriteBatch,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc};
 use std::thread;
+use std::time::Duration;
 use tempfile::TempDir;
 
 #[test]
@@ -125,3 +132,1762 @@ fn test_huge_data_batch_flushing() {
         value_payload
     );
 }
+
+#[test]
+fn test_open_with_small_memtable_flushes_and_recovers() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+
+    let value_payload = vec![0x5A; 512];
+    {
+        let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+        for i in 0..1000 {
+            let key = format!("tuned_key_{:06}", i).into_bytes();
+            engine.put(&key, &value_payload).unwrap();
+        }
+        // Waits for the background flushes.
+        engine.close().unwrap();
+    }
+
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    for i in [0, 499, 999] {
+        let key = format!("tuned_key_{:06}", i).into_bytes();
+        assert_eq!(engine.get(&key).unwrap().unwrap(), value_payload);
+    }
+    assert!(std::fs::read_dir(db_path.join("sst")).unwrap().count() > 0);
+}
+
+#[test]
+fn test_open_with_invalid_options_fails_before_creating_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("db");
+
+    let options = Options {
+        bloom_filter_fpr: 1.5,
+        ..Options::default()
+    };
+
+    assert!(StorageEngine::open_with_options(&db_path, options).is_err());
+    assert!(!db_path.exists());
+}
+
+// A 64 KB MemTable and an L0 trigger of 2, so a few hundred KB of writes spread data over the
+// active MemTable, several L0 SSTables and (after compaction) L1.
+fn small_memtable_options() -> Options {
+    Options {
+        memtable_capacity_bytes: 64 * 1024,
+        l0_compaction_trigger: 2,
+        l1_max_bytes: 1024 * 1024,
+        wal_sync_on_write: false,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn test_scan_merges_all_layers() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine =
+        StorageEngine::open_with_options(temp_dir.path(), small_memtable_options()).unwrap();
+
+    let value_payload = vec![0x11; 256];
+    for i in 0..1000 {
+        let key = format!("scan_key_{:04}", i).into_bytes();
+        engine.put(&key, &value_payload).unwrap();
+    }
+    engine.flush(true).unwrap();
+
+    // Overwrites and deletes land in the MemTable while older versions sit on disk.
+    engine.put(b"scan_key_0010", b"fresh").unwrap();
+    engine.remove(b"scan_key_0011").unwrap();
+
+    let entries: Vec<_> = engine
+        .scan(b"scan_key_0005".as_slice()..b"scan_key_0015".as_slice())
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    let keys: Vec<String> = entries
+        .iter()
+        .map(|(k, _)| String::from_utf8(k.clone()).unwrap())
+        .collect();
+
+    let expected: Vec<String> = (5..15)
+        .filter(|i| *i != 11)
+        .map(|i| format!("scan_key_{:04}", i))
+        .collect();
+    assert_eq!(keys, expected);
+
+    let fresh = entries.iter().find(|(k, _)| k == b"scan_key_0010").unwrap();
+    assert_eq!(fresh.1, b"fresh");
+
+    // A full scan sees every live key exactly once, in order.
+    let all: Vec<Vec<u8>> = engine
+        .scan::<&[u8], _>(..)
+        .unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect();
+    assert_eq!(all.len(), 999);
+    assert!(all.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn test_scan_bounds() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = StorageEngine::open(temp_dir.path()).unwrap();
+
+    for key in ["a", "b", "c", "d"] {
+        engine.put(key, key).unwrap();
+    }
+
+    let collect = |iter: lsmdb::EngineIterator| -> Vec<Vec<u8>> {
+        iter.map(|entry| entry.unwrap().0).collect()
+    };
+
+    assert_eq!(
+        collect(engine.scan(b"b".as_slice()..=b"c".as_slice()).unwrap()),
+        vec![b"b".to_vec(), b"c".to_vec()]
+    );
+    assert_eq!(
+        collect(engine.scan(b"b".as_slice()..).unwrap()),
+        vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]
+    );
+    assert_eq!(
+        collect(engine.scan(..b"b".as_slice()).unwrap()),
+        vec![b"a".to_vec()]
+    );
+    assert_eq!(
+        collect(
+            engine
+                .scan::<&[u8], _>((
+                    std::ops::Bound::Excluded(b"a".as_slice()),
+                    std::ops::Bound::Excluded(b"d".as_slice()),
+                ))
+                .unwrap()
+        ),
+        vec![b"b".to_vec(), b"c".to_vec()]
+    );
+    assert!(collect(engine.scan(b"x".as_slice()..b"z".as_slice()).unwrap()).is_empty());
+}
+
+#[test]
+fn test_scan_prefix() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine =
+        StorageEngine::open_with_options(temp_dir.path(), small_memtable_options()).unwrap();
+
+    // Interleave several namespaces so matching keys are spread over many blocks and tables.
+    let value_payload = vec![0x22; 128];
+    for i in 0..500 {
+        for user in [41, 42, 420] {
+            let key = format!("user:{}:item:{:04}", user, i).into_bytes();
+            engine.put(&key, &value_payload).unwrap();
+        }
+    }
+    engine.flush(true).unwrap();
+
+    engine.remove(b"user:42:item:0007").unwrap();
+    engine.put(b"user:42:profile", b"alice").unwrap();
+
+    let keys: Vec<Vec<u8>> = engine
+        .scan_prefix(b"user:42:")
+        .unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect();
+
+    // 500 items minus the deleted one, plus the profile. "user:420:" must not leak in.
+    assert_eq!(keys.len(), 500);
+    assert!(keys.iter().all(|k| k.starts_with(b"user:42:")));
+    assert!(!keys.contains(&b"user:42:item:0007".to_vec()));
+    assert_eq!(keys.last().unwrap(), b"user:42:profile");
+
+    assert_eq!(
+        engine
+            .scan_prefix(b"user:43:")
+            .unwrap()
+            .map(Result::unwrap)
+            .count(),
+        0
+    );
+
+    // Prefixes ending in 0xFF have no simple successor and must still terminate correctly.
+    engine.put([0xFF, 0xFF, 0x01], b"edge").unwrap();
+    let edge: Vec<_> = engine
+        .scan_prefix([0xFF, 0xFF])
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(edge, vec![(vec![0xFF, 0xFF, 0x01], b"edge".to_vec())]);
+}
+
+#[test]
+fn test_reverse_iteration_latest_n() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine =
+        StorageEngine::open_with_options(temp_dir.path(), small_memtable_options()).unwrap();
+
+    // Time-ordered keys spread across SSTables, the immutable MemTable and the active one.
+    let value_payload = vec![0x33; 256];
+    for ts in 0..1000 {
+        let key = format!("event:{:08}", ts).into_bytes();
+        engine.put(&key, &value_payload).unwrap();
+    }
+    engine.flush(true).unwrap();
+    engine.put(b"event:00000998", b"edited").unwrap();
+    engine.remove(b"event:00000999").unwrap();
+    engine.put(b"zzz", b"outside the prefix").unwrap();
+
+    // "Latest 5": start after the last key and walk backwards.
+    let mut iter = engine.scan_prefix(b"event:").unwrap();
+    iter.seek_to_last();
+    let latest: Vec<(Vec<u8>, Vec<u8>)> = (0..5)
+        .map_while(|_| iter.prev())
+        .map(Result::unwrap)
+        .collect();
+    let keys: Vec<String> = latest
+        .iter()
+        .map(|(k, _)| String::from_utf8(k.clone()).unwrap())
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            "event:00000998",
+            "event:00000997",
+            "event:00000996",
+            "event:00000995",
+            "event:00000994"
+        ]
+    );
+    assert_eq!(latest[0].1, b"edited");
+
+    // A full backward walk sees every live key exactly once, in descending order.
+    let mut iter = engine.iter().unwrap();
+    iter.seek_to_last();
+    let mut all = Vec::new();
+    while let Some(entry) = iter.prev() {
+        all.push(entry.unwrap().0);
+    }
+    assert_eq!(all.len(), 1000);
+    assert_eq!(all[0], b"zzz");
+    assert!(all.windows(2).all(|w| w[0] > w[1]));
+
+    // seek_for_prev lands on the last key <= the target, then both directions continue.
+    iter.seek_for_prev(b"event:00000500x");
+    assert_eq!(iter.prev().unwrap().unwrap().0, b"event:00000500");
+    assert_eq!(iter.prev().unwrap().unwrap().0, b"event:00000499");
+    assert_eq!(iter.next().unwrap().unwrap().0, b"event:00000499");
+    assert_eq!(iter.next().unwrap().unwrap().0, b"event:00000500");
+}
+
+#[test]
+fn test_write_batch_applies_atomically_and_recovers() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+
+    {
+        let engine = StorageEngine::open(&db_path).unwrap();
+        engine.put(b"pending:tx42", b"transfer").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put(b"account:alice", b"90")
+            .put(b"account:bob", b"110")
+            .delete(b"pending:tx42")
+            // Later operations on the same key win.
+            .put(b"account:bob", b"111");
+        assert_eq!(batch.len(), 4);
+        engine.write(batch).unwrap();
+
+        assert_eq!(engine.get(b"account:alice").unwrap().unwrap(), b"90");
+        assert_eq!(engine.get(b"account:bob").unwrap().unwrap(), b"111");
+        assert!(engine.get(b"pending:tx42").unwrap().is_none());
+
+        // An empty batch is a no-op.
+        engine.write(WriteBatch::new()).unwrap();
+    }
+
+    // Replayed from the single batch record in the WAL.
+    let engine = StorageEngine::open(&db_path).unwrap();
+    assert_eq!(engine.get(b"account:alice").unwrap().unwrap(), b"90");
+    assert_eq!(engine.get(b"account:bob").unwrap().unwrap(), b"111");
+    assert!(engine.get(b"pending:tx42").unwrap().is_none());
+}
+
+#[test]
+fn test_snapshot_isolation_survives_flush_and_compaction() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+
+    {
+        let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+        engine.put(b"balance", b"100").unwrap();
+        engine.put(b"doomed", b"here").unwrap();
+        let snapshot = engine.snapshot();
+
+        engine.put(b"balance", b"40").unwrap();
+        engine.remove(b"doomed").unwrap();
+        engine.put(b"late", b"arrival").unwrap();
+
+        // Overwrite a batch of keys several times so the MemTable flushes repeatedly and L0
+        // compacts, rewriting the tables that hold the snapshot's versions.
+        for round in 0..4 {
+            for i in 0..500 {
+                let key = format!("filler:{:04}", i);
+                engine.put(key, format!("{}:{:0>100}", round, i)).unwrap();
+            }
+        }
+        engine.flush(true).unwrap();
+
+        assert_eq!(
+            engine.get_at(&snapshot, b"balance").unwrap().unwrap(),
+            b"100"
+        );
+        assert_eq!(
+            engine.get_at(&snapshot, b"doomed").unwrap().unwrap(),
+            b"here"
+        );
+        assert!(engine.get_at(&snapshot, b"late").unwrap().is_none());
+        assert!(engine.get_at(&snapshot, b"filler:0000").unwrap().is_none());
+
+        assert_eq!(engine.get(b"balance").unwrap().unwrap(), b"40");
+        assert!(engine.get(b"doomed").unwrap().is_none());
+
+        let seen: Vec<(Vec<u8>, Vec<u8>)> = engine
+            .scan_at::<&[u8], _>(&snapshot, ..)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            seen,
+            vec![
+                (b"balance".to_vec(), b"100".to_vec()),
+                (b"doomed".to_vec(), b"here".to_vec())
+            ]
+        );
+
+        // A plain scan keeps reading the state from when it was created.
+        let mut iter = engine.scan_prefix(b"balance").unwrap();
+        engine.put(b"balance", b"0").unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().1, b"40");
+    }
+
+    // The flushed writes' WAL files are gone, so new sequence numbers must come from the
+    // MANIFEST; otherwise this write would sort as older than the flushed versions.
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    engine.put(b"filler:0000", b"after reopen").unwrap();
+    assert_eq!(
+        engine.get(b"filler:0000").unwrap().unwrap(),
+        b"after reopen"
+    );
+}
+
+#[test]
+fn test_empty_values_are_distinct_from_deletes() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+
+    let check = |engine: &StorageEngine| {
+        assert_eq!(engine.get(b"set:alice").unwrap(), Some(Vec::new()));
+        assert_eq!(engine.get(b"set:bob").unwrap(), None);
+        assert_eq!(engine.get(b"set:carol").unwrap(), Some(Vec::new()));
+        let members: Vec<(Vec<u8>, Vec<u8>)> = engine
+            .scan_prefix(b"set:")
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            members,
+            vec![
+                (b"set:alice".to_vec(), vec![]),
+                (b"set:carol".to_vec(), vec![])
+            ]
+        );
+    };
+
+    {
+        let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch
+            .put(b"set:alice", b"")
+            .put(b"set:bob", b"")
+            .put(b"set:carol", b"");
+        engine.write(batch).unwrap();
+        engine.remove(b"set:bob").unwrap();
+        check(&engine);
+    }
+
+    // Replayed from the WAL: the empty puts and the delete must come back as what they were.
+    {
+        let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+        check(&engine);
+
+        // Push everything through a flush and an L0 compaction.
+        for round in 0..4 {
+            for i in 0..500 {
+                let key = format!("filler:{:04}", i);
+                engine.put(key, format!("{}:{:0>100}", round, i)).unwrap();
+            }
+        }
+        engine.flush(true).unwrap();
+        check(&engine);
+    }
+
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    check(&engine);
+}
+
+// Adds little-endian u64 deltas.
+struct Counter;
+
+fn decode_counter(bytes: &[u8]) -> u64 {
+    bytes.try_into().map(u64::from_le_bytes).unwrap_or(0)
+}
+
+impl MergeOperator for Counter {
+    fn name(&self) -> &str {
+        "counter"
+    }
+
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8> {
+        let total: u64 = operands.iter().map(|op| decode_counter(op)).sum();
+        (existing.map_or(0, decode_counter) + total)
+            .to_le_bytes()
+            .to_vec()
+    }
+
+    fn partial_merge(&self, _key: &[u8], older: &[u8], newer: &[u8]) -> Option<Vec<u8>> {
+        Some(
+            (decode_counter(older) + decode_counter(newer))
+                .to_le_bytes()
+                .to_vec(),
+        )
+    }
+}
+
+#[test]
+fn test_merge_counters_survive_flush_compaction_and_reopen() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let options = || Options {
+        merge_operator: Some(Arc::new(Counter)),
+        ..small_memtable_options()
+    };
+    let counter =
+        |engine: &StorageEngine, key: &[u8]| engine.get(key).unwrap().map(|v| decode_counter(&v));
+
+    {
+        let engine = StorageEngine::open_with_options(&db_path, options()).unwrap();
+        engine.put(b"counter:hits", 100u64.to_le_bytes()).unwrap();
+
+        // Spread the increments over several flushes and compactions, so the operands end up
+        // in every layer.
+        let mut snapshot = None;
+        for round in 0..4 {
+            for _ in 0..5 {
+                engine.merge(b"counter:hits", 1u64.to_le_bytes()).unwrap();
+                engine.merge(b"counter:misses", 2u64.to_le_bytes()).unwrap();
+            }
+            if round == 1 {
+                snapshot = Some(engine.snapshot());
+            }
+            for i in 0..500 {
+                let key = format!("filler:{:04}", i);
+                engine.put(key, format!("{}:{:0>100}", round, i)).unwrap();
+            }
+            engine.flush(true).unwrap();
+        }
+
+        assert_eq!(counter(&engine, b"counter:hits"), Some(120));
+        assert_eq!(counter(&engine, b"counter:misses"), Some(40));
+        let snapshot = snapshot.unwrap();
+        let at_snapshot = engine.get_at(&snapshot, b"counter:hits").unwrap();
+        assert_eq!(at_snapshot.map(|v| decode_counter(&v)), Some(110));
+
+        let counters: Vec<(Vec<u8>, u64)> = engine
+            .scan_prefix(b"counter:")
+            .unwrap()
+            .map(|entry| {
+                let (k, v) = entry.unwrap();
+                (k, decode_counter(&v))
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            vec![
+                (b"counter:hits".to_vec(), 120),
+                (b"counter:misses".to_vec(), 40)
+            ]
+        );
+
+        // A delete resets the counter; later merges start from nothing.
+        engine.remove(b"counter:misses").unwrap();
+        engine.merge(b"counter:misses", 7u64.to_le_bytes()).unwrap();
+        assert_eq!(counter(&engine, b"counter:misses"), Some(7));
+    }
+
+    let engine = StorageEngine::open_with_options(&db_path, options()).unwrap();
+    assert_eq!(counter(&engine, b"counter:hits"), Some(120));
+    assert_eq!(counter(&engine, b"counter:misses"), Some(7));
+}
+
+#[test]
+fn test_merge_requires_an_operator() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = StorageEngine::open(temp_dir.path()).unwrap();
+
+    assert!(engine.merge(b"counter", 1u64.to_le_bytes()).is_err());
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1").merge(b"counter", 1u64.to_le_bytes());
+    assert!(engine.write(batch).is_err());
+    // Nothing from the rejected batch was applied.
+    assert_eq!(engine.get(b"a").unwrap(), None);
+}
+
+#[test]
+fn test_invalid_writes_fail_with_invalid_argument() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = StorageEngine::open(temp_dir.path()).unwrap();
+    let is_invalid = |result: Result<(), Error>| matches!(result, Err(Error::InvalidArgument(_)));
+
+    let longest = vec![b'k'; lsmdb::constants::MAX_KEY_SIZE];
+    engine.put(&longest, b"fits").unwrap();
+    assert_eq!(engine.get(&longest).unwrap(), Some(b"fits".to_vec()));
+
+    let mut too_long = longest.clone();
+    too_long.push(b'k');
+    assert!(is_invalid(engine.put(&too_long, b"value")));
+    assert!(is_invalid(engine.delete_range(too_long.as_slice(), b"z")));
+    assert!(is_invalid(engine.merge(b"counter", b"1")));
+    assert!(is_invalid(engine.delete_range(b"b", b"a")));
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1").put(&too_long, b"2");
+    assert!(is_invalid(engine.write(batch)));
+    // Nothing from the rejected batch was applied.
+    assert_eq!(engine.get(b"a").unwrap(), None);
+}
+
+#[test]
+fn test_delete_range_survives_flush_compaction_and_reopen() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let tenant_keys = |engine: &StorageEngine, tenant: &str| {
+        engine
+            .scan_prefix(format!("tenant:{}:", tenant))
+            .unwrap()
+            .map(Result::unwrap)
+            .count()
+    };
+
+    {
+        let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+        // Enough data per tenant to spill into several SSTables and compactions.
+        for round in 0..3 {
+            for tenant in 1..=3 {
+                for i in 0..200 {
+                    let key = format!("tenant:{}:{:04}", tenant, i);
+                    engine.put(key, format!("{}:{:0>100}", round, i)).unwrap();
+                }
+            }
+            engine.flush(true).unwrap();
+        }
+
+        let snapshot = engine.snapshot();
+        engine.delete_range(b"tenant:2:", b"tenant:2;").unwrap();
+        // Written after the tombstone, so it survives it.
+        engine.put(b"tenant:2:0007", b"new").unwrap();
+
+        assert_eq!(engine.get(b"tenant:2:0008").unwrap(), None);
+        assert_eq!(engine.get(b"tenant:2:0007").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(tenant_keys(&engine, "1"), 200);
+        assert_eq!(tenant_keys(&engine, "2"), 1);
+        assert_eq!(tenant_keys(&engine, "3"), 200);
+
+        // Push the tombstone down through flushes and compactions.
+        for i in 0..2000 {
+            let key = format!("filler:{:04}", i);
+            engine.put(key, format!("{:0>100}", i)).unwrap();
+        }
+        engine.flush(true).unwrap();
+
+        assert_eq!(engine.get(b"tenant:2:0100").unwrap(), None);
+        assert_eq!(tenant_keys(&engine, "2"), 1);
+        // The snapshot predates the tombstone and still reads the tenant whole.
+        assert!(
+            engine
+                .get_at(&snapshot, b"tenant:2:0100")
+                .unwrap()
+                .is_some()
+        );
+        let at_snapshot = engine
+            .scan_at(&snapshot, b"tenant:2:".as_slice()..b"tenant:2;".as_slice())
+            .unwrap()
+            .count();
+        assert_eq!(at_snapshot, 200);
+
+        // Range deletions batch with other writes, and the end bound is exclusive.
+        let mut batch = WriteBatch::new();
+        batch
+            .delete_range(b"tenant:3:0000", b"tenant:3:0100")
+            .put(b"tenant:3:0050", b"kept");
+        engine.write(batch).unwrap();
+        assert_eq!(tenant_keys(&engine, "3"), 101);
+        assert!(engine.get(b"tenant:3:0100").unwrap().is_some());
+
+        assert!(engine.delete_range(b"b", b"a").is_err());
+    }
+
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    assert_eq!(tenant_keys(&engine, "1"), 200);
+    assert_eq!(tenant_keys(&engine, "2"), 1);
+    assert_eq!(tenant_keys(&engine, "3"), 101);
+    assert_eq!(
+        engine.get(b"tenant:3:0050").unwrap(),
+        Some(b"kept".to_vec())
+    );
+    assert_eq!(engine.get(b"tenant:3:0049").unwrap(), None);
+}
+
+#[test]
+fn test_ttl_values_expire_across_flush_compaction_and_reopen() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let ttl = Duration::from_millis(500);
+
+    {
+        let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+        engine.put(b"session:old", b"plain").unwrap();
+        engine.put_with_ttl(b"session:old", b"short", ttl).unwrap();
+        engine
+            .put_with_ttl(b"session:long", b"long", Duration::from_secs(3600))
+            .unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl(b"session:batch", b"short", ttl);
+        engine.write(batch).unwrap();
+        // Writing a key again drops its deadline.
+        engine
+            .put_with_ttl(b"session:renewed", b"short", ttl)
+            .unwrap();
+        engine.put(b"session:renewed", b"forever").unwrap();
+
+        assert_eq!(engine.get(b"session:old").unwrap(), Some(b"short".to_vec()));
+        assert_eq!(
+            engine
+                .scan_prefix(b"session:")
+                .unwrap()
+                .map(Result::unwrap)
+                .count(),
+            4
+        );
+
+        // Push the sessions through flushes and compactions before and after they expire.
+        for round in 0..2 {
+            for i in 0..1000 {
+                let key = format!("filler:{:04}", i);
+                engine.put(key, format!("{}:{:0>100}", round, i)).unwrap();
+            }
+            engine.flush(true).unwrap();
+            if round == 0 {
+                thread::sleep(ttl);
+            }
+        }
+
+        // Expired values read as deleted, without resurrecting the value beneath.
+        assert_eq!(engine.get(b"session:old").unwrap(), None);
+        assert_eq!(engine.get(b"session:batch").unwrap(), None);
+        assert_eq!(engine.get(b"session:long").unwrap(), Some(b"long".to_vec()));
+        let live: Vec<Vec<u8>> = engine
+            .scan_prefix(b"session:")
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        assert_eq!(
+            live,
+            vec![b"session:long".to_vec(), b"session:renewed".to_vec()]
+        );
+    }
+
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    assert_eq!(engine.get(b"session:old").unwrap(), None);
+    assert_eq!(engine.get(b"session:long").unwrap(), Some(b"long".to_vec()));
+    assert_eq!(
+        engine.get(b"session:renewed").unwrap(),
+        Some(b"forever".to_vec())
+    );
+}
+
+#[test]
+fn test_column_families_share_the_wal_and_flush_independently() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    // Large enough that the family never flushes, so its writes stay in the shared WAL.
+    let counters_options = || Options {
+        memtable_capacity_bytes: 8 * 1024 * 1024,
+        merge_operator: Some(Arc::new(Counter)),
+        ..Options::default()
+    };
+    let default_options = || Options {
+        merge_operator: Some(Arc::new(Counter)),
+        ..small_memtable_options()
+    };
+    let counter = |engine: &StorageEngine, cf: &ColumnFamily, key: &[u8]| {
+        engine.get_cf(cf, key).unwrap().map(|v| decode_counter(&v))
+    };
+
+    {
+        let engine = StorageEngine::open_with_options(&db_path, default_options()).unwrap();
+        let blobs = engine
+            .create_column_family("blobs", small_memtable_options())
+            .unwrap();
+        let counters = engine
+            .create_column_family("counters", counters_options())
+            .unwrap();
+        assert!(
+            engine
+                .create_column_family("blobs", Options::default())
+                .is_err()
+        );
+        assert_eq!(engine.column_family("blobs").unwrap().name(), "blobs");
+        assert!(engine.column_family("missing").is_none());
+
+        // One key, three families, three values.
+        let mut batch = WriteBatch::new();
+        batch.put(b"shared", b"default");
+        batch.put_cf(&blobs, b"shared", b"blob");
+        batch.merge_cf(&counters, b"shared", 5u64.to_le_bytes());
+        engine.write(batch).unwrap();
+        assert_eq!(engine.get(b"shared").unwrap(), Some(b"default".to_vec()));
+        assert_eq!(
+            engine.get_cf(&blobs, b"shared").unwrap(),
+            Some(b"blob".to_vec())
+        );
+        assert_eq!(counter(&engine, &counters, b"shared"), Some(5));
+
+        // The blobs family has no merge operator of its own.
+        assert!(engine.merge_cf(&blobs, b"shared", b"x").is_err());
+
+        // Snapshots see every family at the same point in time.
+        let snapshot = engine.snapshot();
+        engine.put_cf(&blobs, b"shared", b"blob v2").unwrap();
+        engine
+            .merge_cf(&counters, b"shared", 1u64.to_le_bytes())
+            .unwrap();
+
+        // Operands that get flushed while the counters family keeps their WAL file alive, so
+        // the WAL still holds them on reopen. They must be counted once.
+        engine.merge(b"visits", 1u64.to_le_bytes()).unwrap();
+        engine.merge(b"visits", 2u64.to_le_bytes()).unwrap();
+
+        // Flush and compact the default and blobs families several times over, while the
+        // counters family keeps all of its writes in its MemTable.
+        for i in 0..2000 {
+            engine
+                .put(format!("default:{:04}", i), format!("{:0>100}", i))
+                .unwrap();
+            engine
+                .put_cf(&blobs, format!("blob:{:04}", i), format!("{:0>100}", i))
+                .unwrap();
+            if i % 500 == 0 {
+                engine
+                    .merge_cf(&counters, b"shared", 1u64.to_le_bytes())
+                    .unwrap();
+            }
+        }
+        // Wait for the flushes of those two families, leaving the counters MemTable alone.
+        let default = engine.column_family(DEFAULT_COLUMN_FAMILY_NAME).unwrap();
+        engine.flush_cf(&default, true).unwrap();
+        engine.flush_cf(&blobs, true).unwrap();
+
+        assert_eq!(
+            engine.get_at_cf(&blobs, &snapshot, b"shared").unwrap(),
+            Some(b"blob".to_vec())
+        );
+        assert_eq!(
+            engine
+                .get_at_cf(&counters, &snapshot, b"shared")
+                .unwrap()
+                .map(|v| decode_counter(&v)),
+            Some(5)
+        );
+        assert_eq!(
+            engine
+                .scan_cf(&blobs, ..b"blob:0100".as_slice())
+                .unwrap()
+                .map(Result::unwrap)
+                .count(),
+            100
+        );
+        assert_eq!(
+            engine
+                .scan_prefix(b"blob:")
+                .unwrap()
+                .map(Result::unwrap)
+                .count(),
+            0
+        );
+        assert_eq!(counter(&engine, &counters, b"shared"), Some(10));
+    }
+
+    // The blobs family is not listed, so it opens with the default family's options.
+    let engine = StorageEngine::open_with_column_families(
+        &db_path,
+        default_options(),
+        [("counters", counters_options())],
+    )
+    .unwrap();
+    let blobs = engine.column_family("blobs").unwrap();
+    let counters = engine.column_family("counters").unwrap();
+
+    assert_eq!(engine.get(b"shared").unwrap(), Some(b"default".to_vec()));
+    assert_eq!(
+        counter(
+            &engine,
+            &engine.column_family("default").unwrap(),
+            b"visits"
+        ),
+        Some(3)
+    );
+    assert_eq!(
+        engine.get_cf(&blobs, b"shared").unwrap(),
+        Some(b"blob v2".to_vec())
+    );
+    assert_eq!(counter(&engine, &counters, b"shared"), Some(10));
+    assert_eq!(
+        engine
+            .scan_prefix(b"default:")
+            .unwrap()
+            .map(Result::unwrap)
+            .count(),
+        2000
+    );
+    assert_eq!(
+        engine
+            .scan_cf(&blobs, b"blob:".as_slice()..b"blob;".as_slice())
+            .unwrap()
+            .count(),
+        2000
+    );
+    assert_eq!(engine.get_cf(&counters, b"default:0000").unwrap(), None);
+}
+
+#[test]
+fn test_transactions_detect_conflicts() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine =
+        StorageEngine::open_with_options(temp_dir.path(), small_memtable_options()).unwrap();
+    let is_conflict =
+        |result: Result<(), Error>| matches!(result, Err(Error::TransactionConflict(_)));
+    engine.put(b"balance:alice", b"100").unwrap();
+    engine.put(b"balance:bob", b"0").unwrap();
+
+    // Reads see the transaction's own writes, and nobody else sees them before the commit.
+    let mut txn = engine.begin();
+    assert_eq!(txn.get(b"balance:alice").unwrap(), Some(b"100".to_vec()));
+    txn.put(b"balance:alice", b"60");
+    txn.put(b"balance:bob", b"40");
+    txn.delete(b"missing");
+    assert_eq!(txn.get(b"balance:alice").unwrap(), Some(b"60".to_vec()));
+    assert_eq!(engine.get(b"balance:alice").unwrap(), Some(b"100".to_vec()));
+    txn.commit().unwrap();
+    assert_eq!(engine.get(b"balance:bob").unwrap(), Some(b"40".to_vec()));
+
+    // A key read by the transaction and written by someone else since.
+    let mut txn = engine.begin();
+    txn.get(b"balance:alice").unwrap();
+    txn.put(b"balance:bob", b"50");
+    engine.put(b"balance:alice", b"0").unwrap();
+    match txn.commit() {
+        Err(Error::TransactionConflict(conflict)) => assert_eq!(conflict.key(), b"balance:alice"),
+        result => panic!("expected a conflict, got {:?}", result),
+    }
+    // Nothing was written.
+    assert_eq!(engine.get(b"balance:bob").unwrap(), Some(b"40".to_vec()));
+
+    // Blind writes conflict too, and so do deletions by range.
+    let mut txn = engine.begin();
+    txn.put(b"balance:bob", b"50");
+    engine.remove(b"balance:bob").unwrap();
+    assert!(is_conflict(txn.commit()));
+    let mut txn = engine.begin();
+    txn.get(b"balance:alice").unwrap();
+    engine.delete_range(b"balance:", b"balance;").unwrap();
+    assert!(is_conflict(txn.commit()));
+
+    // Writes to other keys, and writes made before the transaction started, do not.
+    engine.put(b"balance:carol", b"7").unwrap();
+    let mut txn = engine.begin();
+    txn.get(b"balance:carol").unwrap();
+    engine.put(b"balance:dave", b"1").unwrap();
+    txn.put(b"balance:erin", b"2");
+    txn.commit().unwrap();
+
+    // A conflicting write that has been flushed and compacted by the time of the commit.
+    let mut stale = engine.begin();
+    stale.get(b"balance:carol").unwrap();
+    let mut fresh = engine.begin();
+    fresh.get(b"balance:erin").unwrap();
+    engine.put(b"balance:carol", b"8").unwrap();
+    for i in 0..2000 {
+        engine
+            .put(format!("filler:{:04}", i), format!("{:0>100}", i))
+            .unwrap();
+    }
+    engine.flush(true).unwrap();
+    assert!(is_conflict(stale.commit()));
+    fresh.put(b"balance:erin", b"3");
+    fresh.commit().unwrap();
+    assert_eq!(engine.get(b"balance:erin").unwrap(), Some(b"3".to_vec()));
+
+    // Column families are separate keyspaces for conflicts as well.
+    let ledger = engine
+        .create_column_family("ledger", Options::default())
+        .unwrap();
+    let mut txn = engine.begin();
+    txn.get_cf(&ledger, b"balance:carol").unwrap();
+    txn.put_cf(&ledger, b"balance:carol", b"1");
+    engine.put(b"balance:carol", b"9").unwrap();
+    txn.commit().unwrap();
+    let mut txn = engine.begin();
+    txn.delete_cf(&ledger, b"balance:carol");
+    engine.put_cf(&ledger, b"balance:carol", b"2").unwrap();
+    assert!(is_conflict(txn.commit()));
+    assert_eq!(
+        engine.get_cf(&ledger, b"balance:carol").unwrap(),
+        Some(b"2".to_vec())
+    );
+}
+
+#[test]
+fn test_pessimistic_transactions_serialize_hot_keys() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Arc::new(
+        TransactionDB::open_with_options(
+            temp_dir.path(),
+            small_memtable_options(),
+            TransactionDBOptions {
+                lock_timeout: Duration::from_secs(10),
+                ..TransactionDBOptions::default()
+            },
+        )
+        .unwrap(),
+    );
+
+    // Read-modify-write on one key from several threads: the lock makes every increment count.
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    let mut txn = db.begin();
+                    let count = txn
+                        .get_for_update(b"counter")
+                        .unwrap()
+                        .map_or(0, |v| decode_counter(&v));
+                    txn.put(b"counter", (count + 1).to_le_bytes()).unwrap();
+                    txn.commit().unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let count = db
+        .engine()
+        .get(b"counter")
+        .unwrap()
+        .map(|v| decode_counter(&v));
+    assert_eq!(count, Some(100));
+
+    // Two transactions locking the same two keys in opposite orders: whichever would close
+    // the cycle fails at once, and the other proceeds once it is dropped.
+    let mut first = db.begin();
+    first.put(b"a", b"first").unwrap();
+    let (b_locked, wait_for_b) = mpsc::channel();
+    let second = {
+        let db = Arc::clone(&db);
+        thread::spawn(move || {
+            let mut txn = db.begin();
+            txn.put(b"b", b"second").unwrap();
+            b_locked.send(()).unwrap();
+            match txn.put(b"a", b"second") {
+                Ok(()) => {
+                    txn.commit().unwrap();
+                    true
+                }
+                Err(err) => {
+                    assert!(matches!(err, Error::Lock(LockError::Deadlock { key }) if key == b"a"));
+                    false
+                }
+            }
+        })
+    };
+    wait_for_b.recv().unwrap();
+    let first_won = match first.put(b"b", b"first") {
+        Ok(()) => {
+            assert_eq!(first.get(b"b").unwrap(), Some(b"first".to_vec()));
+            assert_eq!(db.engine().get(b"a").unwrap(), None);
+            first.commit().unwrap();
+            true
+        }
+        Err(err) => {
+            assert!(matches!(err, Error::Lock(LockError::Deadlock { key }) if key == b"b"));
+            drop(first);
+            false
+        }
+    };
+    let second_won = second.join().unwrap();
+    assert_ne!(first_won, second_won);
+    let winner = if first_won {
+        b"first".to_vec()
+    } else {
+        b"second".to_vec()
+    };
+    assert_eq!(db.engine().get(b"a").unwrap(), Some(winner.clone()));
+    assert_eq!(db.engine().get(b"b").unwrap(), Some(winner.clone()));
+
+    // A dropped transaction writes nothing and releases its locks.
+    let mut abandoned = db.begin();
+    abandoned.delete(b"a").unwrap();
+    drop(abandoned);
+    let mut txn = db.begin();
+    assert_eq!(txn.get_for_update(b"a").unwrap(), Some(winner));
+    txn.commit().unwrap();
+}
+
+#[test]
+fn test_pessimistic_transaction_lock_timeout() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = TransactionDB::open_with_options(
+        temp_dir.path(),
+        Options::default(),
+        TransactionDBOptions {
+            lock_timeout: Duration::from_millis(50),
+            ..TransactionDBOptions::default()
+        },
+    )
+    .unwrap();
+
+    let mut holder = db.begin();
+    holder.get_for_update(b"hot").unwrap();
+    let mut waiter = db.begin();
+    assert!(matches!(
+        waiter.put(b"hot", b"x"),
+        Err(Error::Lock(LockError::Timeout { key })) if key == b"hot"
+    ));
+    // Unlocked reads do not wait.
+    assert_eq!(waiter.get(b"hot").unwrap(), None);
+}
+
+#[test]
+fn test_compare_and_swap_is_atomic() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = Arc::new(
+        StorageEngine::open_with_options(temp_dir.path(), small_memtable_options()).unwrap(),
+    );
+
+    // `None` stands for an absent key on either side.
+    assert!(
+        engine
+            .compare_and_swap(b"lease", None, Some(b"node-1"))
+            .unwrap()
+    );
+    assert!(
+        !engine
+            .compare_and_swap(b"lease", None, Some(b"node-2"))
+            .unwrap()
+    );
+    assert!(
+        !engine
+            .compare_and_swap(b"lease", Some(b"node-2"), None)
+            .unwrap()
+    );
+    assert_eq!(engine.get(b"lease").unwrap(), Some(b"node-1".to_vec()));
+    assert!(
+        engine
+            .compare_and_swap(b"lease", Some(b"node-1"), None)
+            .unwrap()
+    );
+    assert_eq!(engine.get(b"lease").unwrap(), None);
+    // An empty value is present, not absent.
+    engine.put(b"empty", b"").unwrap();
+    assert!(!engine.compare_and_swap(b"empty", None, Some(b"x")).unwrap());
+    assert!(
+        engine
+            .compare_and_swap(b"empty", Some(b""), Some(b"x"))
+            .unwrap()
+    );
+
+    // Concurrent read-modify-write loops, with enough writes alongside to force flushes: no
+    // increment is lost.
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                for i in 0..50 {
+                    loop {
+                        let current = engine.get(b"counter").unwrap();
+                        let next = current.as_deref().map_or(0, decode_counter) + 1;
+                        if engine
+                            .compare_and_swap(
+                                b"counter",
+                                current.as_deref(),
+                                Some(&next.to_le_bytes()),
+                            )
+                            .unwrap()
+                        {
+                            break;
+                        }
+                    }
+                    engine
+                        .put(format!("filler:{}:{:02}", t, i), [0u8; 2000])
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let count = engine.get(b"counter").unwrap().map(|v| decode_counter(&v));
+    assert_eq!(count, Some(200));
+}
+
+#[test]
+fn test_multi_get_matches_get_across_layers() {
+    let temp_dir = TempDir::new().unwrap();
+    let engine = StorageEngine::open_with_options(
+        temp_dir.path(),
+        Options {
+            merge_operator: Some(Arc::new(Counter)),
+            ..small_memtable_options()
+        },
+    )
+    .unwrap();
+
+    // Enough writes to flush several tables, then newer versions, deletions and merge operands
+    // on top of them in the MemTable.
+    for i in 0..1000 {
+        engine.put(format!("key:{:04}", i), [0x22; 256]).unwrap();
+    }
+    for i in 0..10 {
+        engine
+            .merge(format!("count:{}", i), 1u64.to_le_bytes())
+            .unwrap();
+    }
+    engine.flush(true).unwrap();
+    engine.put(b"key:0010", b"fresh").unwrap();
+    engine.remove(b"key:0011").unwrap();
+    engine.delete_range(b"key:0500", b"key:0510").unwrap();
+    for i in 0..10 {
+        engine
+            .merge(format!("count:{}", i), 2u64.to_le_bytes())
+            .unwrap();
+    }
+
+    // Unsorted, with duplicates and keys that were never written.
+    let mut keys: Vec<String> = (0..1000)
+        .rev()
+        .step_by(7)
+        .map(|i| format!("key:{:04}", i))
+        .collect();
+    keys.extend(
+        [
+            "key:0010", "key:0011", "key:0505", "key:0010", "missing", "count:3", "count:9",
+        ]
+        .map(String::from),
+    );
+
+    let values = engine.multi_get(&keys).unwrap();
+    let expected: Vec<_> = keys.iter().map(|key| engine.get(key).unwrap()).collect();
+    assert_eq!(values, expected);
+
+    let value = |key: &str| values[keys.iter().position(|k| k == key).unwrap()].clone();
+    assert_eq!(value("key:0010"), Some(b"fresh".to_vec()));
+    assert_eq!(value("key:0011"), None);
+    assert_eq!(value("key:0505"), None);
+    assert_eq!(value("missing"), None);
+    assert_eq!(value("count:3").as_deref().map(decode_counter), Some(3));
+    assert_eq!(value("key:0999"), Some(vec![0x22; 256]));
+
+    assert!(engine.multi_get::<&[u8]>(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn test_close_waits_for_background_work_and_flushes_on_request() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    // Bytes of writes the WAL holds for the next open to replay.
+    let wal_bytes = || -> u64 {
+        std::fs::read_dir(db_path.join("wal"))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    };
+
+    // Closing right after the writes that trigger flushes must wait for them, not cut them
+    // off half-way: no half-written table is left behind.
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    for i in 0..1000 {
+        engine.put(format!("key:{:04}", i), [0x33; 256]).unwrap();
+    }
+    engine.close().unwrap();
+    let tables: Vec<_> = std::fs::read_dir(db_path.join("sst"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert!(!tables.is_empty());
+    assert!(tables.iter().all(|path| path.extension().unwrap() == "sst"));
+
+    // Without `flush_on_close` the MemTable's writes stay in the WAL...
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    assert_eq!(engine.get(b"key:0999").unwrap(), Some(vec![0x33; 256]));
+    engine.put(b"late", b"in the wal").unwrap();
+    engine.close().unwrap();
+    assert!(wal_bytes() > 0);
+
+    // ...and with it they are written to an SSTable on the way out.
+    let options = Options {
+        flush_on_close: true,
+        ..small_memtable_options()
+    };
+    let engine = StorageEngine::open_with_options(&db_path, options.clone()).unwrap();
+    assert_eq!(engine.get(b"late").unwrap(), Some(b"in the wal".to_vec()));
+    engine.close().unwrap();
+    assert_eq!(wal_bytes(), 0);
+
+    // A plain drop shuts down the same way.
+    let engine = StorageEngine::open_with_options(&db_path, options).unwrap();
+    engine.put(b"dropped", b"flushed").unwrap();
+    drop(engine);
+    assert_eq!(wal_bytes(), 0);
+
+    let engine = StorageEngine::open(&db_path).unwrap();
+    assert_eq!(engine.get(b"late").unwrap(), Some(b"in the wal".to_vec()));
+    assert_eq!(engine.get(b"dropped").unwrap(), Some(b"flushed".to_vec()));
+    assert_eq!(engine.get(b"key:0000").unwrap(), Some(vec![0x33; 256]));
+}
+
+#[test]
+fn test_flush_and_compact_range_reclaim_deleted_space() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let dir_bytes = |dir: &str| -> u64 {
+        std::fs::read_dir(db_path.join(dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    };
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+
+    // A flush that is waited for leaves nothing in the WAL to replay.
+    engine.put(b"first", b"write").unwrap();
+    engine.flush(true).unwrap();
+    assert_eq!(dir_bytes("wal"), 0);
+    assert_eq!(engine.get(b"first").unwrap(), Some(b"write".to_vec()));
+    // With nothing in the MemTable, there is nothing to do.
+    engine.flush(true).unwrap();
+
+    for i in 0..2000 {
+        engine.put(format!("key:{:04}", i), [0x44; 256]).unwrap();
+    }
+    for i in 0..1900 {
+        engine.remove(format!("key:{:04}", i)).unwrap();
+    }
+    engine.flush(true).unwrap();
+    let before = dir_bytes("sst");
+
+    // Outside the data: nothing to compact.
+    engine.compact_range(Some(b"zzz"), None).unwrap();
+    assert_eq!(dir_bytes("sst"), before);
+
+    engine.compact_range::<&[u8]>(None, None).unwrap();
+    let after = dir_bytes("sst");
+    assert!(after * 4 < before, "{after} bytes left of {before}");
+
+    let live: Vec<Vec<u8>> = engine
+        .scan::<&[u8], _>(..)
+        .unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect();
+    assert_eq!(live.len(), 101);
+    assert_eq!(engine.get(b"key:1899").unwrap(), None);
+    assert_eq!(engine.get(b"key:1900").unwrap(), Some(vec![0x44; 256]));
+
+    // The compacted layout is what the MANIFEST recorded.
+    drop(engine);
+    let engine = StorageEngine::open(&db_path).unwrap();
+    assert_eq!(engine.get(b"key:1999").unwrap(), Some(vec![0x44; 256]));
+    assert_eq!(engine.get(b"key:0000").unwrap(), None);
+    assert_eq!(engine.get(b"first").unwrap(), Some(b"write".to_vec()));
+}
+
+#[test]
+fn test_compact_range_drops_tombstones_at_the_bottom() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let tables = || {
+        std::fs::read_dir(db_path.join("sst"))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("sst".as_ref()))
+            .count()
+    };
+    let engine = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+
+    for i in 0..1000 {
+        engine.put(format!("key:{:04}", i), [0x55; 256]).unwrap();
+    }
+    engine.flush(true).unwrap();
+    // A snapshot from before the deletes reads through their tombstones, so they must stay.
+    let snapshot = engine.snapshot();
+    // Point tombstones for half the keys, and a range tombstone for the rest.
+    for i in 0..500 {
+        engine.remove(format!("key:{:04}", i)).unwrap();
+    }
+    engine.delete_range(b"key:0500", b"key:1000").unwrap();
+    engine.flush(true).unwrap();
+
+    engine.compact_range::<&[u8]>(None, None).unwrap();
+    assert!(tables() > 0);
+    assert_eq!(
+        engine.get_at(&snapshot, b"key:0700").unwrap(),
+        Some(vec![0x55; 256])
+    );
+    assert_eq!(
+        engine
+            .scan::<&[u8], _>(..)
+            .unwrap()
+            .map(Result::unwrap)
+            .count(),
+        0
+    );
+
+    // Without it, the values and every tombstone shadowing them go, leaving no table at all.
+    drop(snapshot);
+    engine.compact_range::<&[u8]>(None, None).unwrap();
+    assert_eq!(tables(), 0);
+    assert_eq!(engine.get(b"key:0700").unwrap(), None);
+
+    drop(engine);
+    let engine = StorageEngine::open(&db_path).unwrap();
+    assert_eq!(
+        engine
+            .scan::<&[u8], _>(..)
+            .unwrap()
+            .map(Result::unwrap)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_read_only_open_leaves_the_directory_untouched() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let listing = || -> Vec<std::path::PathBuf> {
+        let mut files = Vec::new();
+        for dir in [db_path.clone(), db_path.join("wal"), db_path.join("sst")] {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                files.push(entry.unwrap().path());
+            }
+        }
+        files.sort();
+        files
+    };
+
+    assert!(StorageEngine::open_read_only(db_path.join("missing")).is_err());
+    assert!(!db_path.join("missing").exists());
+
+    // The primary stays open: some of its data is in SSTables, the rest only in the WAL.
+    let primary = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    let events = primary
+        .create_column_family("events", small_memtable_options())
+        .unwrap();
+    for i in 0..1000 {
+        primary.put(format!("key:{:04}", i), [0x55; 256]).unwrap();
+    }
+    primary.flush(true).unwrap();
+    primary.put(b"key:0000", b"in the wal").unwrap();
+    primary.put_cf(&events, b"login", b"alice").unwrap();
+    let before = listing();
+
+    let reader = StorageEngine::open_read_only(&db_path).unwrap();
+    assert_eq!(
+        reader.get(b"key:0000").unwrap(),
+        Some(b"in the wal".to_vec())
+    );
+    assert_eq!(reader.get(b"key:0999").unwrap(), Some(vec![0x55; 256]));
+    let reader_events = reader.column_family("events").unwrap();
+    assert_eq!(
+        reader.get_cf(&reader_events, b"login").unwrap(),
+        Some(b"alice".to_vec())
+    );
+    assert_eq!(
+        reader
+            .scan::<&[u8], _>(..)
+            .unwrap()
+            .map(Result::unwrap)
+            .count(),
+        1000
+    );
+
+    // Writes made after the open are not seen.
+    primary.put(b"late", b"write").unwrap();
+    assert_eq!(reader.get(b"late").unwrap(), None);
+
+    let rejected = [
+        reader.put(b"key", b"value"),
+        reader.remove(b"key"),
+        reader.write({
+            let mut batch = WriteBatch::new();
+            batch.put(b"key", b"value");
+            batch
+        }),
+        reader
+            .create_column_family("more", Options::default())
+            .map(|_| ()),
+        reader.flush(true),
+        reader.compact_range::<&[u8]>(None, None),
+        reader.clear(),
+    ];
+    for result in rejected {
+        assert!(matches!(result, Err(Error::ReadOnly)));
+    }
+    let mut txn = reader.begin();
+    txn.put(b"key", b"value");
+    assert!(matches!(txn.commit(), Err(Error::ReadOnly)));
+    reader.close().unwrap();
+
+    // No file was created or deleted, not even a WAL file of the reader's own.
+    assert_eq!(listing(), before);
+}
+
+#[test]
+fn test_secondary_catches_up_with_flushes_compactions_and_the_wal() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+
+    let primary = StorageEngine::open_with_options(&db_path, small_memtable_options()).unwrap();
+    for i in 0..1000 {
+        primary.put(format!("key:{:04}", i), [0x55; 256]).unwrap();
+    }
+    primary.flush(true).unwrap();
+    primary.put(b"key:0000", b"in the wal").unwrap();
+
+    let secondary = StorageEngine::open_as_secondary(&db_path).unwrap();
+    assert_eq!(
+        secondary.get(b"key:0000").unwrap(),
+        Some(b"in the wal".to_vec())
+    );
+    assert_eq!(secondary.get(b"key:0999").unwrap(), Some(vec![0x55; 256]));
+
+    // Writes that only reached the WAL, and a new column family.
+    let events = primary
+        .create_column_family("events", small_memtable_options())
+        .unwrap();
+    primary.put_cf(&events, b"login", b"alice").unwrap();
+    primary.put(b"key:0001", b"tailed").unwrap();
+    primary.remove(b"key:0002").unwrap();
+    assert_eq!(secondary.get(b"key:0001").unwrap(), Some(vec![0x55; 256]));
+    assert!(secondary.column_family("events").is_none());
+
+    secondary.try_catch_up_with_primary().unwrap();
+    assert_eq!(
+        secondary.get(b"key:0001").unwrap(),
+        Some(b"tailed".to_vec())
+    );
+    assert_eq!(secondary.get(b"key:0002").unwrap(), None);
+    let secondary_events = secondary.column_family("events").unwrap();
+    assert_eq!(
+        secondary.get_cf(&secondary_events, b"login").unwrap(),
+        Some(b"alice".to_vec())
+    );
+
+    // The primary flushes the WAL away and compacts the tables the secondary has open.
+    for i in 0..1000 {
+        primary.remove(format!("key:{:04}", i)).unwrap();
+    }
+    primary.put(b"key:0500", b"survivor").unwrap();
+    primary.flush(true).unwrap();
+    primary.compact_range::<&[u8]>(None, None).unwrap();
+    primary.put_cf(&events, b"logout", b"alice").unwrap();
+
+    secondary.try_catch_up_with_primary().unwrap();
+    let remaining: Vec<_> = secondary
+        .scan::<&[u8], _>(..)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        remaining,
+        vec![(b"key:0500".to_vec(), b"survivor".to_vec())]
+    );
+    assert_eq!(
+        secondary.get_cf(&secondary_events, b"logout").unwrap(),
+        Some(b"alice".to_vec())
+    );
+
+    // Catching up again with nothing new changes nothing.
+    secondary.try_catch_up_with_primary().unwrap();
+    assert_eq!(
+        secondary
+            .scan::<&[u8], _>(..)
+            .unwrap()
+            .map(Result::unwrap)
+            .count(),
+        1
+    );
+
+    assert!(matches!(
+        secondary.put(b"key", b"value"),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        primary.try_catch_up_with_primary(),
+        Err(Error::InvalidArgument(_))
+    ));
+    secondary.close().unwrap();
+    primary.close().unwrap();
+}
+
+#[test]
+fn test_damaged_sstables_fail_reads_and_opens_or_are_quarantined() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    {
+        let engine = StorageEngine::open(&db_path).unwrap();
+        for i in 0..1000 {
+            engine.put(format!("old:{:04}", i), b"value").unwrap();
+        }
+        engine.flush(true).unwrap();
+        engine.put(b"new", b"value").unwrap();
+        engine.flush(true).unwrap();
+        engine.close().unwrap();
+    }
+    let mut tables: Vec<PathBuf> = std::fs::read_dir(db_path.join("sst"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    tables.sort_by_key(|path| {
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        stem.parse::<u64>().unwrap()
+    });
+    let [older, newer] = &tables[..] else {
+        panic!("expected two tables, found {:?}", tables);
+    };
+    let older_id: u64 = older
+        .file_stem()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // A damaged data block only fails the reads that need it, and cuts scans short.
+    let intact = std::fs::read(newer).unwrap();
+    let mut damaged = intact.clone();
+    damaged[0] = 0x7f;
+    std::fs::write(newer, &damaged).unwrap();
+    {
+        let engine = StorageEngine::open(&db_path).unwrap();
+        assert!(matches!(
+            engine.get(b"new"),
+            Err(Error::Corruption { ref file, offset: 0, .. }) if file == newer
+        ));
+        assert_eq!(engine.get(b"old:0001").unwrap(), Some(b"value".to_vec()));
+        let mut iter = engine.scan::<&[u8], _>(..).unwrap();
+        assert!(matches!(
+            iter.next(),
+            Some(Err(Error::Corruption { ref file, .. })) if file == newer
+        ));
+        assert!(iter.next().is_none());
+        engine.close().unwrap();
+    }
+    std::fs::write(newer, &intact).unwrap();
+
+    // A table that does not open fails the open, unless it is to be quarantined.
+    let bytes = std::fs::read(older).unwrap();
+    std::fs::write(older, &bytes[..30]).unwrap();
+    assert!(matches!(
+        StorageEngine::open(&db_path),
+        Err(Error::Corruption { ref file, .. }) if file == older
+    ));
+    let quarantine = || Options {
+        corrupt_sstable_policy: CorruptSSTablePolicy::Quarantine,
+        ..Options::default()
+    };
+    {
+        let reader = StorageEngine::open_read_only_with_column_families(
+            &db_path,
+            quarantine(),
+            Vec::<(&str, Options)>::new(),
+        )
+        .unwrap();
+        assert_eq!(reader.quarantined_sstables(), [older_id]);
+        assert_eq!(reader.get(b"old:0001").unwrap(), None);
+        assert_eq!(reader.get(b"new").unwrap(), Some(b"value".to_vec()));
+    }
+    assert!(older.exists());
+
+    {
+        let engine = StorageEngine::open_with_options(&db_path, quarantine()).unwrap();
+        assert_eq!(engine.quarantined_sstables(), [older_id]);
+        assert_eq!(engine.get(b"old:0001").unwrap(), None);
+        assert_eq!(engine.get(b"new").unwrap(), Some(b"value".to_vec()));
+        engine.close().unwrap();
+    }
+    assert!(!older.exists());
+    assert!(
+        db_path
+            .join("lost")
+            .join(older.file_name().unwrap())
+            .exists()
+    );
+
+    // The MANIFEST no longer lists it, so the default policy opens the database again.
+    let engine = StorageEngine::open(&db_path).unwrap();
+    assert!(engine.quarantined_sstables().is_empty());
+    assert_eq!(engine.get(b"new").unwrap(), Some(b"value".to_vec()));
+    engine.close().unwrap();
+}
+
+#[test]
+fn test_checksum_mismatches_fail_reads_unless_verification_is_off() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let engine = StorageEngine::open(&db_path).unwrap();
+    engine.put(b"greeting", b"hello, checksums").unwrap();
+    engine.flush(true).unwrap();
+
+    // Flip a bit of the value on disk; the block still decompresses, to the wrong bytes.
+    let table = std::fs::read_dir(db_path.join("sst"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let mut bytes = std::fs::read(&table).unwrap();
+    let at = bytes.windows(5).position(|w| w == b"hello").unwrap();
+    bytes[at] ^= 0x20;
+    std::fs::write(&table, &bytes).unwrap();
+
+    assert!(matches!(
+        engine.get(b"greeting"),
+        Err(Error::Corruption { ref message, .. }) if message.contains("Checksum mismatch")
+    ));
+    assert!(engine.multi_get(&[b"greeting"]).is_err());
+    let mut iter = engine.scan::<&[u8], _>(..).unwrap();
+    assert!(matches!(iter.next(), Some(Err(Error::Corruption { .. }))));
+    assert!(iter.next().is_none());
+
+    let unchecked = ReadOptions {
+        verify_checksums: false,
+    };
+    assert_eq!(
+        engine.get_opt(&unchecked, b"greeting").unwrap(),
+        Some(b"Hello, checksums".to_vec())
+    );
+    assert_eq!(
+        engine.multi_get_opt(&unchecked, &[b"greeting"]).unwrap(),
+        vec![Some(b"Hello, checksums".to_vec())]
+    );
+    let entries: Vec<_> = engine
+        .scan_opt::<&[u8], _>(&unchecked, ..)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        entries,
+        vec![(b"greeting".to_vec(), b"Hello, checksums".to_vec())]
+    );
+    engine.close().unwrap();
+}
+
+#[test]
+fn test_compression_is_chosen_per_level() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    let engine = StorageEngine::open_with_options(
+        &db_path,
+        Options {
+            compression: CompressionType::Zstd(9),
+            compression_per_level: vec![CompressionType::None],
+            ..Options::default()
+        },
+    )
+    .unwrap();
+    for i in 0..1000 {
+        engine
+            .put(format!("key{:04}", i), format!("value{:04}", i).repeat(8))
+            .unwrap();
+    }
+
+    // The first byte of a table is the compression type of its first data block.
+    let first_block_types = || -> Vec<u8> {
+        std::fs::read_dir(db_path.join("sst"))
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap()[0])
+            .collect()
+    };
+    engine.flush(true).unwrap();
+    assert_eq!(first_block_types(), vec![COMPRESSION_NONE]);
+    engine.compact_range::<&[u8]>(None, None).unwrap();
+    assert_eq!(first_block_types(), vec![COMPRESSION_ZSTD]);
+
+    assert_eq!(
+        engine.get(b"key0500").unwrap(),
+        Some("value0500".repeat(8).into_bytes())
+    );
+    assert_eq!(engine.iter().unwrap().map(Result::unwrap).count(), 1000);
+    engine.close().unwrap();
+}
+
+#[test]
+fn test_sstables_in_an_unknown_format_fail_the_open() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().to_path_buf();
+    {
+        let engine = StorageEngine::open(&db_path).unwrap();
+        engine.put(b"key", b"value").unwrap();
+        engine.flush(true).unwrap();
+        engine.close().unwrap();
+    }
+    let table = std::fs::read_dir(db_path.join("sst"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let intact = std::fs::read(&table).unwrap();
+    let footer = intact.len() - 64;
+
+    // A table from a later release: a version this build does not know, under a footer
+    // checksum that matches.
+    let mut newer = intact.clone();
+    newer[footer + 48..footer + 52].copy_from_slice(&(SSTABLE_FORMAT_VERSION + 1).to_le_bytes());
+    let checksum = crc32c::crc32c(&newer[footer..footer + 52]);
+    newer[footer + 52..footer + 56].copy_from_slice(&checksum.to_le_bytes());
+    // A file that does not end in the magic number.
+    let mut foreign = intact.clone();
+    *foreign.last_mut().unwrap() ^= 0xff;
+
+    for bytes in [newer, foreign] {
+        std::fs::write(&table, &bytes).unwrap();
+        assert!(matches!(
+            StorageEngine::open(&db_path),
+            Err(Error::NotSupported(_))
+        ));
+        // Nothing is wrong with such a table, so it is not quarantined either.
+        let quarantine = Options {
+            corrupt_sstable_policy: CorruptSSTablePolicy::Quarantine,
+            ..Options::default()
+        };
+        assert!(matches!(
+            StorageEngine::open_with_options(&db_path, quarantine),
+            Err(Error::NotSupported(_))
+        ));
+        assert!(table.exists());
+    }
+
+    std::fs::write(&table, &intact).unwrap();
+    let engine = StorageEngine::open(&db_path).unwrap();
+    assert_eq!(engine.get(b"key").unwrap(), Some(b"value".to_vec()));
+    engine.close().unwrap();
+}